@@ -41,15 +41,7 @@ module orchestrator #(NUM_VTU=1) (
         for (int k = 0; k < NUM_VTU; k++) begin
             if (vtu_valid[k]) begin
                 sbuf_addr = vtu_write_addr[k];
-                unique case (vtu_hit[k])
-                    BLOCK_AIR: sbuf_data = 16'hAE5D;
-                    BLOCK_WATER: sbuf_data = 16'h3211;
-                    BLOCK_GRASS: sbuf_data = 16'h5C29;
-                    BLOCK_DIRT: sbuf_data = 16'h8309;
-                    BLOCK_OAK_LOG: sbuf_data = 16'h59C5;
-                    BLOCK_OAK_LEAVES: sbuf_data = 16'h852E;
-                    default: sbuf_data = 16'h522A;
-                endcase
+                sbuf_data = block_color(vtu_hit[k]);
             end
         end
     end
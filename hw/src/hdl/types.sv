@@ -9,6 +9,7 @@ parameter FRAME_WIDTH = 64;
 parameter FRAME_HEIGHT = 32;
 parameter FRAME_AREA = FRAME_WIDTH * FRAME_HEIGHT;
 
+// BEGIN generated by palette/generate.py from palette/blocks.csv, don't edit by hand
 // For now we can support up to 32 blocks
 typedef enum logic [4:0] {
     BLOCK_AIR           = 'd0,
@@ -21,16 +22,42 @@ typedef enum logic [4:0] {
     BLOCK_BIRCH_PLANKS  = 'd7,
     BLOCK_WATER         = 'd8,
     BLOCK_SAND          = 'd9,
-    BLOCK_OAK_LOG       = 'd10,
-    BLOCK_GRAVEL        = 'd11,
+    BLOCK_GRAVEL        = 'd10,
+    BLOCK_OAK_LOG       = 'd11,
     BLOCK_SPRUCE_LOG    = 'd12,
-    BLOCK_BIRC_HLOG     = 'd13,
+    BLOCK_BIRCH_LOG     = 'd13,
     BLOCK_OAK_LEAVES    = 'd14,
     BLOCK_SPRUCE_LEAVES = 'd15,
     BLOCK_BIRCH_LEAVES  = 'd16,
     BLOCK_GLASS         = 'd17
 } BlockType;
 
+// Flat colour of a block (RGB565), the sky's for air
+function automatic logic [15:0] block_color(input BlockType block);
+    unique case (block)
+        BLOCK_AIR:           return 16'hAE5D;
+        BLOCK_STONE:         return 16'h522A;
+        BLOCK_GRASS:         return 16'h5C29;
+        BLOCK_DIRT:          return 16'h8309;
+        BLOCK_COBBLESTONE:   return 16'h522A;
+        BLOCK_OAK_PLANKS:    return 16'h522A;
+        BLOCK_SPRUCE_PLANKS: return 16'h522A;
+        BLOCK_BIRCH_PLANKS:  return 16'h522A;
+        BLOCK_WATER:         return 16'h3211;
+        BLOCK_SAND:          return 16'h522A;
+        BLOCK_GRAVEL:        return 16'h522A;
+        BLOCK_OAK_LOG:       return 16'h59C5;
+        BLOCK_SPRUCE_LOG:    return 16'h522A;
+        BLOCK_BIRCH_LOG:     return 16'h522A;
+        BLOCK_OAK_LEAVES:    return 16'h852E;
+        BLOCK_SPRUCE_LEAVES: return 16'h522A;
+        BLOCK_BIRCH_LEAVES:  return 16'h522A;
+        BLOCK_GLASS:         return 16'h522A;
+        default: return 16'h0000;
+    endcase
+endfunction
+// END generated
+
 // The position of a block (-64..=64 in all dimensions)
 typedef struct packed {
     logic signed [$clog2(CHUNK_WIDTH)-1:0] x;
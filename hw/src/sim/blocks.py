@@ -1,3 +1,5 @@
+"""Generated by palette/generate.py from palette/blocks.csv, don't edit by hand"""
+
 AIR           = 0
 STONE         = 1
 GRASS         = 2
@@ -8,11 +10,64 @@ SPRUCE_PLANKS = 6
 BIRCH_PLANKS  = 7
 WATER         = 8
 SAND          = 9
-OAK_LOG       = 10
-GRAVEL        = 11
+GRAVEL        = 10
+OAK_LOG       = 11
 SPRUCE_LOG    = 12
-BIRC_HLOG     = 13
+BIRCH_LOG     = 13
 OAK_LEAVES    = 14
 SPRUCE_LEAVES = 15
 BIRCH_LEAVES  = 16
-GLASS         = 17
\ No newline at end of file
+GLASS         = 17
+
+# Flat colour of every block, 8 bits per channel. The sky's for air
+COLORS = {
+    AIR: (174, 200, 235),
+    STONE: (82, 70, 84),
+    GRASS: (90, 133, 77),
+    DIRT: (133, 96, 77),
+    COBBLESTONE: (82, 70, 84),
+    OAK_PLANKS: (82, 70, 84),
+    SPRUCE_PLANKS: (82, 70, 84),
+    BIRCH_PLANKS: (82, 70, 84),
+    WATER: (52, 67, 138),
+    SAND: (82, 70, 84),
+    GRAVEL: (82, 70, 84),
+    OAK_LOG: (91, 58, 42),
+    SPRUCE_LOG: (82, 70, 84),
+    BIRCH_LOG: (82, 70, 84),
+    OAK_LEAVES: (129, 165, 118),
+    SPRUCE_LEAVES: (82, 70, 84),
+    BIRCH_LEAVES: (82, 70, 84),
+    GLASS: (82, 70, 84),
+}
+
+# opaque, transparent or translucent
+MATERIALS = {
+    AIR: 'transparent',
+    STONE: 'opaque',
+    GRASS: 'opaque',
+    DIRT: 'opaque',
+    COBBLESTONE: 'opaque',
+    OAK_PLANKS: 'opaque',
+    SPRUCE_PLANKS: 'opaque',
+    BIRCH_PLANKS: 'opaque',
+    WATER: 'translucent',
+    SAND: 'opaque',
+    GRAVEL: 'opaque',
+    OAK_LOG: 'opaque',
+    SPRUCE_LOG: 'opaque',
+    BIRCH_LOG: 'opaque',
+    OAK_LEAVES: 'translucent',
+    SPRUCE_LEAVES: 'translucent',
+    BIRCH_LEAVES: 'translucent',
+    GLASS: 'translucent',
+}
+
+# Coverage per voxel of translucent blocks, in multiples of 1/256
+ALPHAS = {
+    WATER: 96,
+    OAK_LEAVES: 192,
+    SPRUCE_LEAVES: 192,
+    BIRCH_LEAVES: 192,
+    GLASS: 32,
+}
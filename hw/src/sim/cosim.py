@@ -0,0 +1,189 @@
+"""
+Stimulus/response traces shared with the Rust model (`sw/src/cosim.rs`), to check that the
+HDL and the model behave the same, cycle by cycle.
+
+A trace is a CSV file: the header names every input (`in.` prefix) and output (`out.`
+prefix) of a module, then every row is one clock cycle, i.e. the inputs driven before the
+rising edge and the outputs right after it. Values are signed integers: fixed-point numbers
+are their raw bits, blocks are their id, 1-bit signals are 0 or 1.
+
+Signal names are the ones of the Rust model; `drive_*`/`sample_*` translate them to the
+ports of the HDL module.
+
+    # Replay a trace recorded by `fpgacraft cosim record vtu vtu.csv`
+    await replay(dut, "vtu.csv", drive_vtu, sample_vtu)
+
+    # Record the HDL, to replay with `fpgacraft cosim replay vtu vtu.csv`
+    rec = Recorder(dut, VTU_INPUTS, VTU_OUTPUTS, drive_vtu, sample_vtu)
+    outputs = await rec.cycle({"reset": 1, ...})
+    rec.save("vtu.csv")
+"""
+
+import csv
+import fixed
+from cocotb.binary import BinaryValue
+from cocotb.triggers import FallingEdge, ReadOnly, RisingEdge
+
+
+def read(path):
+    """Returns the names of the inputs and outputs, and a list of (inputs, outputs) dicts"""
+    with open(path, newline="") as f:
+        rows = csv.reader(f)
+        header = next(rows)
+
+        inputs = [c[len("in."):] for c in header if c.startswith("in.")]
+        outputs = [c[len("out."):] for c in header if c.startswith("out.")]
+        assert len(inputs) + len(outputs) == len(header), "columns must start with in. or out."
+
+        cycles = []
+        for row in rows:
+            values = [int(v) for v in row]
+            cycles.append((
+                dict(zip(inputs, values[:len(inputs)])),
+                dict(zip(outputs, values[len(inputs):])),
+            ))
+
+    return inputs, outputs, cycles
+
+
+def write(path, inputs, outputs, cycles):
+    with open(path, "w", newline="") as f:
+        rows = csv.writer(f, lineterminator="\n")
+        rows.writerow([f"in.{i}" for i in inputs] + [f"out.{o}" for o in outputs])
+        for ins, outs in cycles:
+            rows.writerow([ins[i] for i in inputs] + [outs[o] for o in outputs])
+
+
+async def step(dut, inputs, drive, sample):
+    """Drives `inputs` on the falling edge, then samples the outputs after the rising edge"""
+    await FallingEdge(dut.clk_in)
+    drive(dut, inputs)
+    await RisingEdge(dut.clk_in)
+    await ReadOnly()
+    return sample(dut)
+
+
+async def replay(dut, path, drive, sample, max_reported=10):
+    """Replays the inputs of the trace at `path`, and fails if any output differs"""
+    _, _, cycles = read(path)
+
+    mismatches = []
+    for cycle, (inputs, expected) in enumerate(cycles):
+        actual = await step(dut, inputs, drive, sample)
+
+        for name, value in expected.items():
+            if actual[name] != value:
+                mismatches.append(f"cycle {cycle}: {name} is {actual[name]}, trace says {value}")
+
+    assert not mismatches, \
+        f"{len(mismatches)} outputs differ from the trace, first ones:\n  " + "\n  ".join(mismatches[:max_reported])
+
+
+class Recorder:
+    """Drives the HDL one cycle at a time, and records it as a trace"""
+
+    def __init__(self, dut, inputs, outputs, drive, sample):
+        self.dut = dut
+        self.inputs = inputs
+        self.outputs = outputs
+        self.drive = drive
+        self.sample = sample
+        self.cycles = []
+
+    async def cycle(self, inputs):
+        outputs = await step(self.dut, inputs, self.drive, self.sample)
+        self.cycles.append((inputs, outputs))
+        return outputs
+
+    def save(self, path):
+        write(path, self.inputs, self.outputs, self.cycles)
+
+
+def signed(bits):
+    """Binary string => Python integer (two's complement)"""
+    value = int(bits, base=2)
+    return value - (1 << len(bits)) if bits[0] == "1" else value
+
+def encode(values, width):
+    """Python integers => binary string, `width` bits each, first one in the MSBs"""
+    return "".join(bin(v & ((1 << width) - 1))[2:].zfill(width) for v in values)
+
+def decode(bits, n):
+    """Binary string => `n` signed integers of equal width, first one in the MSBs"""
+    width = len(bits) // n
+    return [signed(bits[i*width:(i+1)*width]) for i in range(n)]
+
+def vec(values, name):
+    return [values[f"{name}.{c}"] for c in "xyz"]
+
+def unvec(name, components):
+    return {f"{name}.{c}": v for c, v in zip("xyz", components)}
+
+
+# == voxel_traversal_unit (vtu.sv) <-> VoxelTraversalUnit (vtu.rs) ==
+
+VTU_INPUTS = [
+    "reset",
+    "ray_origin_in.x", "ray_origin_in.y", "ray_origin_in.z",
+    "ray_direction_in.x", "ray_direction_in.y", "ray_direction_in.z",
+    "ray_init_in",
+    "l2.voxel_out", "l2.valid_out",
+]
+VTU_OUTPUTS = [
+    "voxel_out",
+    "normal_out.x", "normal_out.y", "normal_out.z",
+    "valid_out",
+    "l2.addr_in.x", "l2.addr_in.y", "l2.addr_in.z",
+    "l2.read_enable_in",
+]
+
+def drive_vtu(dut, ins):
+    # The HDL starts a new ray on reset
+    dut.rst_in.value = ins["reset"] | ins["ray_init_in"]
+    dut.ray_origin.value = BinaryValue(encode(vec(ins, "ray_origin_in"), fixed.B))
+    dut.ray_direction.value = BinaryValue(encode(vec(ins, "ray_direction_in"), fixed.B))
+    dut.ram_out.value = ins["l2.voxel_out"]
+    dut.ram_valid.value = ins["l2.valid_out"]
+
+def sample_vtu(dut):
+    return {
+        "voxel_out": dut.hit.value.integer,
+        **unvec("normal_out", decode(dut.hit_norm.value.binstr, 3)),
+        "valid_out": dut.hit_valid.value.integer,
+        **unvec("l2.addr_in", decode(dut.ram_addr.value.binstr, 3)),
+        "l2.read_enable_in": dut.ram_read_enable.value.integer,
+    }
+
+
+# == l2_cache (l2_cache.sv) <-> L2Cache (l2.rs) ==
+# The HDL has no `l3.ready_out`/`l3.addr_out`, so traces must keep the L3 always ready and
+# answer misses in order
+
+def l2_ports(dut):
+    return len(dut.read_enable.value.binstr)
+
+def drive_l2(dut, ins):
+    ports = l2_ports(dut)
+    width = len(dut.addr.value.binstr) // ports // 3
+
+    # Port 0 is in the LSBs
+    dut.rst_in.value = ins["reset"]
+    dut.addr.value = BinaryValue("".join(
+        encode(vec(ins, f"port{p}.addr_in"), width) for p in reversed(range(ports))
+    ))
+    dut.read_enable.value = sum(ins[f"port{p}.read_enable_in"] << p for p in range(ports))
+    dut.l3_out.value = ins["l3.voxel_out"]
+    dut.l3_valid.value = ins["l3.valid_out"]
+
+def sample_l2(dut):
+    ports = l2_ports(dut)
+    out = dut.out.value.integer
+    valid = dut.valid.value.integer
+
+    outputs = {}
+    for p in range(ports):
+        outputs[f"port{p}.voxel_out"] = (out >> (5 * p)) & 0b11111
+        outputs[f"port{p}.valid_out"] = (valid >> p) & 1
+    outputs.update(unvec("l3.addr_in", decode(dut.l3_addr.value.binstr, 3)))
+    outputs["l3.read_enable_in"] = dut.l3_read_enable.value.integer
+    return outputs
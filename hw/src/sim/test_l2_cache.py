@@ -10,6 +10,7 @@ from cocotb.triggers import ClockCycles, RisingEdge
 from cocotb.utils import get_sim_time as gst
 from cocotb.runner import get_runner
 from random import randint
+import cosim
 
 
 CACHE_SIZE = 16
@@ -163,6 +164,14 @@ async def test_a(dut):
         await ClockCycles(dut.clk_in, 10)
 
 
+@cocotb.test(skip="COSIM_TRACE" not in os.environ)
+async def test_cosim_replay(dut):
+    """Replays a trace from `fpgacraft cosim record l2 FILE --ports 4`, given as COSIM_TRACE"""
+    cocotb.start_soon(Clock(dut.clk_in, 10, units="ns").start())
+
+    await cosim.replay(dut, os.environ["COSIM_TRACE"], cosim.drive_l2, cosim.sample_l2)
+
+
 def is_runner():
     """Image Sprite Tester."""
     hdl_toplevel_lang = os.getenv("HDL_TOPLEVEL_LANG", "verilog")
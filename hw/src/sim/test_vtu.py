@@ -6,6 +6,7 @@ import vec3
 import vec3i
 import fixed
 import blocks
+import cosim
 import numpy as np
 import matplotlib.pyplot as plt
 from pathlib import Path
@@ -185,13 +186,7 @@ async def test_mock_render(dut):
 
         dist_traversed += dut.num_steps.value
 
-        if dut.hit.value == blocks.AIR: col = [174, 200, 235]
-        elif dut.hit.value == blocks.WATER: col = [52, 67, 138]
-        elif dut.hit.value == blocks.GRASS: col = [90, 133, 77]
-        elif dut.hit.value == blocks.DIRT: col = [133, 96, 77]
-        elif dut.hit.value == blocks.OAK_LOG: col = [91, 58, 42]
-        elif dut.hit.value == blocks.OAK_LEAVES: col = [129, 165, 118]
-        else: col = [82, 70, 84]
+        col = list(blocks.COLORS[dut.hit.value.integer])
 
         if dut.hit.value != blocks.AIR:
             col = (np.array(col) * light).astype(np.uint8)
@@ -202,6 +197,14 @@ async def test_mock_render(dut):
     plt.show()
 
 
+@cocotb.test(skip="COSIM_TRACE" not in os.environ)
+async def test_cosim_replay(dut):
+    """Replays a trace from `fpgacraft cosim record vtu FILE`, given as COSIM_TRACE"""
+    cocotb.start_soon(Clock(dut.clk_in, 10, units="ns").start())
+
+    await cosim.replay(dut, os.environ["COSIM_TRACE"], cosim.drive_vtu, cosim.sample_vtu)
+
+
 async def test_ray_init(dut, ray_direction, ray_origin):
     """
     Tests initialization of ray parameters
@@ -0,0 +1,93 @@
+"""
+Generates the hardware side of `blocks.csv`: the `BlockType` enum and `block_color` function
+of `hw/src/hdl/types.sv` (in between its "generated" markers), and `hw/src/sim/blocks.py`.
+The Rust code generates its own at build time.
+
+    python3 palette/generate.py          # Rewrites both files
+    python3 palette/generate.py --check  # Fails if they're out of date
+"""
+
+import csv
+import re
+import sys
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+PALETTE = ROOT / "palette" / "blocks.csv"
+TYPES_SV = ROOT / "hw" / "src" / "hdl" / "types.sv"
+BLOCKS_PY = ROOT / "hw" / "src" / "sim" / "blocks.py"
+
+BEGIN = "// BEGIN generated by palette/generate.py from palette/blocks.csv, don't edit by hand"
+END = "// END generated"
+
+
+def load():
+    with open(PALETTE, newline="") as f:
+        lines = (line for line in f if line.strip() and not line.startswith("#"))
+        blocks = list(csv.DictReader(lines))
+
+    for i, block in enumerate(blocks):
+        assert int(block["id"]) == i, f"ids must start at 0 and increase by 1, {block['name']} is {block['id']}"
+        block["id"] = i
+        block["snake"] = re.sub(r"(?<!^)(?=[A-Z])", "_", block["name"]).upper()
+        block["rgb"] = tuple(int(block["color"][i:i+2], 16) for i in (1, 3, 5))
+    return blocks
+
+
+def rgb565(rgb):
+    r, g, b = rgb
+    return ((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3)
+
+
+def types_sv(blocks):
+    width = max(len(b["snake"]) for b in blocks) + len("BLOCK_")
+    lines = [BEGIN, "// For now we can support up to 32 blocks", "typedef enum logic [4:0] {"]
+    lines += [
+        f"    {'BLOCK_' + b['snake']:<{width}} = 'd{b['id']}" + ("," if i + 1 < len(blocks) else "")
+        for i, b in enumerate(blocks)
+    ]
+    lines += ["} BlockType;", "", "// Flat colour of a block (RGB565), the sky's for air"]
+    lines += ["function automatic logic [15:0] block_color(input BlockType block);", "    unique case (block)"]
+    lines += [f"        {'BLOCK_' + b['snake'] + ':':<{width + 1}} return 16'h{rgb565(b['rgb']):04X};" for b in blocks]
+    lines += ["        default: return 16'h0000;", "    endcase", "endfunction", END]
+    return "\n".join(lines)
+
+
+def blocks_py(blocks):
+    width = max(len(b["snake"]) for b in blocks)
+    lines = [f'"""Generated by palette/generate.py from palette/blocks.csv, don\'t edit by hand"""', ""]
+    lines += [f"{b['snake']:<{width}} = {b['id']}" for b in blocks]
+    lines += ["", "# Flat colour of every block, 8 bits per channel. The sky's for air", "COLORS = {"]
+    lines += [f"    {b['snake']}: {b['rgb']}," for b in blocks]
+    lines += ["}", "", "# opaque, transparent or translucent", "MATERIALS = {"]
+    lines += [f"    {b['snake']}: {b['material']!r}," for b in blocks]
+    lines += ["}", "", "# Coverage per voxel of translucent blocks, in multiples of 1/256", "ALPHAS = {"]
+    lines += [f"    {b['snake']}: {b['alpha']}," for b in blocks if b["alpha"]]
+    lines += ["}", ""]
+    return "\n".join(lines)
+
+
+def main():
+    check = "--check" in sys.argv[1:]
+    blocks = load()
+
+    sv = TYPES_SV.read_text()
+    start, end = sv.index(BEGIN), sv.index(END) + len(END)
+    outputs = {
+        TYPES_SV: sv[:start] + types_sv(blocks) + sv[end:],
+        BLOCKS_PY: blocks_py(blocks),
+    }
+
+    stale = [path for path, text in outputs.items() if not path.exists() or path.read_text() != text]
+    if check:
+        for path in stale:
+            print(f"{path.relative_to(ROOT)} is out of date, run palette/generate.py")
+        sys.exit(1 if stale else 0)
+
+    for path in stale:
+        path.write_text(outputs[path])
+        print(f"wrote {path.relative_to(ROOT)}")
+
+
+if __name__ == "__main__":
+    main()
@@ -0,0 +1,109 @@
+// Reads `blocks.csv`, for the build scripts of the simulator and of the plugin, which both
+// `include!` this file. Keep it dependency-free.
+
+/// One line of `blocks.csv`
+#[allow(dead_code)]
+struct BlockDef {
+    id: u8,
+    name: String,
+    minecraft: String,
+    color: (u8, u8, u8),
+    material: String,
+    alpha: u16,
+}
+
+/// Every block of `blocks.csv` at `path`, in order of id. Panics with the offending line if
+/// the file isn't valid, which fails the build.
+fn load_palette(path: &std::path::Path) -> Vec<BlockDef> {
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let csv = std::fs::read_to_string(path)
+        .unwrap_or_else(|e| panic!("couldn't read {}: {e}", path.display()));
+
+    let mut blocks: Vec<BlockDef> = Vec::new();
+    let lines = csv
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+        .skip(1);
+
+    for (i, line) in lines {
+        let fail = |msg: &str| -> ! { panic!("{}:{}: {msg}", path.display(), i + 1) };
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        let [id, name, minecraft, color, material, alpha] = fields[..] else {
+            fail("expected 6 columns");
+        };
+
+        let id: u8 = id.parse().unwrap_or_else(|_| fail("invalid id"));
+        if usize::from(id) != blocks.len() || id > 31 {
+            fail("ids must start at 0, increase by 1 and fit in 5 bits");
+        }
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) || !name.chars().all(|c| c.is_ascii_alphanumeric()) {
+            fail("names must be in CamelCase");
+        }
+
+        let hex = color
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6)
+            .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+            .unwrap_or_else(|| fail("colors must be #rrggbb"));
+        let color = ((hex >> 16) as u8, (hex >> 8) as u8, hex as u8);
+
+        let alpha = match material {
+            "opaque" | "transparent" if alpha.is_empty() => 0,
+            "translucent" => alpha
+                .parse()
+                .ok()
+                .filter(|&a| a > 0 && a < 256)
+                .unwrap_or_else(|| fail("translucent blocks need an alpha between 1 and 255")),
+            "opaque" | "transparent" => fail("only translucent blocks have an alpha"),
+            _ => fail("material must be opaque, transparent or translucent"),
+        };
+
+        blocks.push(BlockDef {
+            id,
+            name: name.into(),
+            minecraft: minecraft.into(),
+            color,
+            material: material.into(),
+            alpha,
+        });
+    }
+
+    if blocks.first().map(|b| b.name.as_str()) != Some("Air") {
+        panic!("{}: block 0 must be Air, it's what empty memory reads as", path.display());
+    }
+    blocks
+}
+
+/// The `Block` enum, with its [Default] (air) and `TryFrom<u8>`
+fn block_enum(blocks: &[BlockDef]) -> String {
+    let mut src = String::new();
+
+    src += "#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]\n";
+    src += "#[repr(u8)]\n";
+    src += "#[allow(unused)]\n";
+    src += "pub enum Block {\n";
+    for block in blocks {
+        if block.id == 0 {
+            src += "    #[default]\n";
+        }
+        src += &format!("    {} = {},\n", block.name, block.id);
+    }
+    src += "}\n\n";
+
+    src += "impl std::convert::TryFrom<u8> for Block {\n";
+    src += "    type Error = u8;\n\n";
+    src += "    /// Block with the given id, i.e. its `BlockType` in the HDL\n";
+    src += "    fn try_from(id: u8) -> Result<Self, Self::Error> {\n";
+    src += "        match id {\n";
+    for block in blocks {
+        src += &format!("            {} => Ok(Block::{}),\n", block.id, block.name);
+    }
+    src += "            _ => Err(id),\n";
+    src += "        }\n";
+    src += "    }\n";
+    src += "}\n";
+
+    src
+}
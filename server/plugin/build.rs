@@ -0,0 +1,22 @@
+//! Generates the `Block` enum from `palette/blocks.csv`, see `src/block.rs`
+
+use std::{env, fmt::Write, fs, path::Path};
+
+include!("../../palette/palette.rs");
+
+fn main() {
+    let blocks = load_palette(Path::new("../../palette/blocks.csv"));
+    let mut src = block_enum(&blocks);
+
+    writeln!(src, "\nimpl From<quill::BlockKind> for Block {{").unwrap();
+    writeln!(src, "    fn from(value: quill::BlockKind) -> Self {{").unwrap();
+    writeln!(src, "        match value {{").unwrap();
+    for block in &blocks {
+        writeln!(src, "            quill::BlockKind::{} => Self::{},", block.minecraft, block.name).unwrap();
+    }
+    writeln!(src, "            _ => Self::Air,").unwrap();
+    writeln!(src, "        }}\n    }}\n}}").unwrap();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("block.rs");
+    fs::write(out, src).unwrap();
+}
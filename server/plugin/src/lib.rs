@@ -2,7 +2,8 @@ mod block;
 
 // for Windows
 use std::fs::File;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
 #[cfg(target_family="unix")]
 use std::os::unix::fs::FileExt;
 
@@ -12,7 +13,11 @@ use serialport::{SerialPort, SerialPortType, UsbPortInfo};
 
 #[quill::plugin]
 pub struct FpgaPlugin {
-    serial: Option<Box<dyn SerialPort>>,
+    serial: Option<Link>,
+    /// Port to connect to, from `FPGACRAFT_PORT`. Either the path of a serial port, or
+    /// `tcp://HOST:PORT` for the simulator's virtual FPGA (`fpgacraft --serial HOST:PORT`).
+    /// If unset, the first USB serial port made by Xilinx is used
+    port: Option<String>,
 
     /// Latest chunk data sent to the FPGA
     chunk_data: Box<[Block]>,
@@ -20,20 +25,30 @@ pub struct FpgaPlugin {
     chunk_center: Option<BlockPosition>,
     /// Handle for chunk.bin
     chunk_file: File,
+    /// Whether [FpgaPlugin::chunk_data] changed since it was last sent to the FPGA
+    chunk_dirty: bool,
+}
+
+/// Connection to the FPGA, or to the simulator pretending to be one
+enum Link {
+    Serial(Box<dyn SerialPort>),
+    Tcp(TcpStream),
 }
 
 impl Plugin for FpgaPlugin {
     fn enable(_game: &mut quill::Game, setup: &mut quill::Setup<Self>) -> Self {
         setup.add_system(Self::connect_serial);
-        // setup.add_system(Self::send_blocks);
         setup.add_system(Self::save_chunk_local);
+        setup.add_system(Self::send_blocks);
         // setup.add_system(Self::player_input);
 
         Self {
             serial: None,
+            port: std::env::var("FPGACRAFT_PORT").ok().filter(|port| !port.is_empty()),
             chunk_data: vec![Block::Air; Self::CHUNK_SIZE.pow(3)].into_boxed_slice(),
             chunk_center: Default::default(),
             chunk_file: File::create("chunk.bin").unwrap(),
+            chunk_dirty: false,
         }
     }
 
@@ -46,20 +61,35 @@ impl FpgaPlugin {
 
     /// Attempts to connect the FPGA each frame
     fn connect_serial(&mut self, _: &mut Game) {
-        // No easy way to detect disconnects, so we do a random I/O operation and if
-        // that fails just assume the port was closed.
-        if let Some(port) = &self.serial {
-            if let Err(serialport::Error { kind, .. }) = port.data_bits() {
+        if let Some(link) = &self.serial {
+            if let Err(e) = link.check() {
                 // Explicitely close the port
                 drop(self.serial.take());
 
-                println!("Closed serial port because of {kind:?}!");
+                println!("Closed serial port because of {e}!");
             } else {
                 // Port is open and active, don't try to open a new one!
                 return;
             }
         }
 
+        let Some(port) = self.port.clone().or_else(Self::find_fpga) else {
+            // No ports found!
+            return;
+        };
+
+        // Open the serial port
+        if let Ok(link) = Link::open(&port) {
+            self.serial = Some(link);
+            // Whatever was sent before is gone with the last connection, if there was one
+            self.chunk_dirty = true;
+
+            println!("Connected to {port}!");
+        }
+    }
+
+    /// Path of the serial port that's most likely our FPGA, if any
+    fn find_fpga() -> Option<String> {
         // Find all the ports that might be our FPGA
         let mut ports = serialport::available_ports()
             .expect("No ports found!")
@@ -86,24 +116,18 @@ impl FpgaPlugin {
         }
         
         // Otherwise, just pick the first one
-        let Some(port) = ports.first() else {
-            // No ports found!
-            return;
-        };
-
-        // Open the serial port
-        if let Ok(serial) = serialport::new(port, Self::SERIAL_BAUD).open() {
-            self.serial = Some(serial);
-
-            println!("Connected to {port}!");
-        }
+        ports.into_iter().next()
     }
 
-    /// Sends blocks to the FPGA
+    /// Sends blocks to the FPGA whenever they changed. On a real serial port, this blocks
+    /// for as long as it takes to get them through the UART
     fn send_blocks(&mut self, _game: &mut Game) {
-        // Make sure the serial port is available
+        if !self.chunk_dirty {
+            return;
+        }
+
+        // Make sure the serial port is available. If not, they'll be sent once it is
         let Some(port) = &mut self.serial else {
-            eprintln!("No serial port connected to send data!");
             return;
         };
     
@@ -117,13 +141,19 @@ impl FpgaPlugin {
         let data: &[u8] = unsafe { std::mem::transmute(&*self.chunk_data) };
 
         // Put the byte into a packet containing the valid start and stop signal
-        for byte in data {
-            let packet = [0, *byte, 1];
-            if let Err(e) = port.write_all(&packet) {
-                eprintln!("Failed to send data byte to FPGA: {e}");
-                return;
-            }
+        let packets = data
+            .iter()
+            .flat_map(|&byte| [0, byte, 1])
+            .collect::<Vec<_>>();
+
+        if let Err(e) = port.write_all(&packets).and_then(|()| port.flush()) {
+            eprintln!("Failed to send chunk data to FPGA: {e}");
+
+            // Try again on a new connection
+            drop(self.serial.take());
+            return;
         }
+        self.chunk_dirty = false;
     }
 
     /// Saves the entire 128*128*128 chunk near the player each time they move. This creates/modifies
@@ -164,6 +194,8 @@ impl FpgaPlugin {
             }
         }
 
+        self.chunk_dirty = true;
+
         // Save file
         let data = unsafe { std::mem::transmute(&*self.chunk_data) };
 
@@ -235,4 +267,53 @@ impl FpgaPlugin {
             eprintln!("Player not found.");
         }
     }
-}
\ No newline at end of file
+}
+
+impl Link {
+    /// Opens `port`, either `tcp://HOST:PORT` or the path of a serial port
+    fn open(port: &str) -> io::Result<Self> {
+        match port.strip_prefix("tcp://") {
+            Some(addr) => Ok(Self::Tcp(TcpStream::connect(addr)?)),
+            None => Ok(Self::Serial(serialport::new(port, FpgaPlugin::SERIAL_BAUD).open()?)),
+        }
+    }
+
+    /// Errors if the other end went away, as far as we can tell
+    fn check(&self) -> io::Result<()> {
+        match self {
+            // No easy way to detect disconnects, so we do a random I/O operation and if
+            // that fails just assume the port was closed.
+            Self::Serial(port) => port.data_bits().map(|_| ()).map_err(io::Error::from),
+            // Sockets do report it, once a write failed
+            Self::Tcp(stream) => match stream.take_error()? {
+                Some(e) => Err(e),
+                None => stream.peer_addr().map(|_| ()),
+            },
+        }
+    }
+}
+
+impl Read for Link {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Serial(port) => port.read(buf),
+            Self::Tcp(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Link {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Serial(port) => port.write(buf),
+            Self::Tcp(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Serial(port) => port.flush(),
+            Self::Tcp(stream) => stream.flush(),
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Generates the `Block` enum from `palette/blocks.csv`, see `src/block.rs`
+
+use std::{env, fmt::Write, fs, path::Path};
+
+include!("../palette/palette.rs");
+
+fn main() {
+    let blocks = load_palette(Path::new("../palette/blocks.csv"));
+    let mut src = block_enum(&blocks);
+
+    writeln!(src, "\nimpl Block {{").unwrap();
+    writeln!(src, "    /// Number of blocks, i.e. one more than the largest id").unwrap();
+    writeln!(src, "    pub const COUNT: usize = {};\n", blocks.len()).unwrap();
+
+    writeln!(src, "    /// Flat colour of the block, when it isn't textured. [Block::Air] is the sky").unwrap();
+    writeln!(src, "    pub const fn color(self) -> Rgb565 {{").unwrap();
+    writeln!(src, "        match self {{").unwrap();
+    for block in &blocks {
+        let (r, g, b) = block.color;
+        writeln!(src, "            Block::{} => Rgb565::new({r}, {g}, {b}),", block.name).unwrap();
+    }
+    writeln!(src, "        }}\n    }}\n").unwrap();
+
+    writeln!(src, "    pub const fn material(self) -> Material {{").unwrap();
+    writeln!(src, "        match self {{").unwrap();
+    for block in &blocks {
+        let material = match block.material.as_str() {
+            "opaque" => "Material::Opaque".to_string(),
+            "transparent" => "Material::Transparent".to_string(),
+            _ => format!("Material::Translucent {{ alpha: fixed!({}.0 / 256.0) }}", block.alpha),
+        };
+        writeln!(src, "            Block::{} => {material},", block.name).unwrap();
+    }
+    writeln!(src, "        }}\n    }}\n").unwrap();
+
+    // Anvil worlds name blocks like `minecraft:grass_block`, i.e. `BlockKind::GrassBlock`
+    writeln!(src, "    /// Block that Minecraft's block `identifier` (e.g. `minecraft:stone`) becomes, if any").unwrap();
+    writeln!(src, "    pub fn from_minecraft(identifier: &str) -> Option<Self> {{").unwrap();
+    writeln!(src, "        match identifier {{").unwrap();
+    for block in &blocks {
+        let mut identifier = String::from("minecraft:");
+        for (i, c) in block.minecraft.chars().enumerate() {
+            if c.is_ascii_uppercase() && i > 0 {
+                identifier.push('_');
+            }
+            identifier.push(c.to_ascii_lowercase());
+        }
+        writeln!(src, "            \"{identifier}\" => Some(Block::{}),", block.name).unwrap();
+    }
+    writeln!(src, "            _ => None,").unwrap();
+    writeln!(src, "        }}\n    }}\n}}").unwrap();
+
+    let out = Path::new(&env::var("OUT_DIR").unwrap()).join("block.rs");
+    fs::write(out, src).unwrap();
+}
@@ -0,0 +1,560 @@
+//! Reads boxes of blocks out of Minecraft's Anvil worlds, as saved by Minecraft 1.16 or by
+//! Feather (see `feather/base/src/anvil/region.rs`), so that scenery doesn't have to go
+//! through the plugin's `chunk.bin` first. Blocks go through the palette, like in the
+//! plugin: anything that isn't in `palette/blocks.csv` becomes air.
+
+use std::{collections::{hash_map::Entry, BTreeMap, HashMap}, fs, io::Read, path::{Path, PathBuf}};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+
+use crate::block::Block;
+
+/// Chunks per side of a region file
+const REGION_SIZE: i32 = 32;
+
+/// Bytes per sector of a region file
+const SECTOR_BYTES: usize = 4096;
+
+/// First data version where block states don't span two longs, i.e. 1.16
+const MIN_DATA_VERSION: i32 = 2566;
+
+/// Blocks of a box, and what didn't make it into them
+#[derive(Debug, Default)]
+pub struct Extract {
+    /// `size`³ blocks in `chunk.bin` order
+    pub blocks: Vec<Block>,
+    /// Chunk columns in the box that were never generated, which are air
+    pub missing_chunks: usize,
+    /// Minecraft blocks that aren't in the palette, and how many of each became air
+    pub unmapped: BTreeMap<String, usize>,
+}
+
+/// 16³ blocks of a chunk column
+#[derive(Debug)]
+struct Section {
+    /// Position in the column, in sections
+    y: i32,
+    /// Minecraft's name of every block state in the section
+    palette: Vec<String>,
+    /// Index in [Section::palette] of every block, packed into longs
+    states: Vec<i64>,
+}
+
+/// An NBT tag, with only what's needed to find blocks
+#[derive(Debug)]
+enum Tag {
+    Number(i64),
+    String(String),
+    List(Vec<Tag>),
+    Compound(HashMap<String, Tag>),
+    LongArray(Vec<i64>),
+    /// Anything else, which gets skipped
+    Other,
+}
+
+/// Reads the `size`³ box of the world in `dir` whose corner with the smallest coordinates
+/// is `corner`. `dir` is either the world (with a `region` directory) or its `region`
+/// directory.
+pub fn read_box(dir: &Path, corner: [i32; 3], size: usize) -> Result<Extract, String> {
+    let dir = match dir.join("region").is_dir() {
+        true => dir.join("region"),
+        false => dir.to_path_buf(),
+    };
+    if !dir.is_dir() {
+        return Err(format!("{} isn't a directory", dir.display()));
+    }
+
+    let mut extract = Extract {
+        blocks: vec![Block::Air; size.pow(3)],
+        ..Default::default()
+    };
+    let mut regions = HashMap::new();
+
+    let [x0, y0, z0] = corner;
+    let end = |start: i32| start + size as i32 - 1;
+
+    for cz in z0.div_euclid(16)..=end(z0).div_euclid(16) {
+        for cx in x0.div_euclid(16)..=end(x0).div_euclid(16) {
+            let region = (cx.div_euclid(REGION_SIZE), cz.div_euclid(REGION_SIZE));
+            let region = match regions.entry(region) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => entry.insert(read_region(&dir, region)?),
+            };
+
+            let Some(sections) = region
+                .as_ref()
+                .map(|(path, bytes)| read_chunk(path, bytes, cx, cz))
+                .transpose()?
+                .flatten()
+            else {
+                extract.missing_chunks += 1;
+                continue;
+            };
+
+            for Section { y: sy, palette, states } in sections {
+                // Block states are packed into longs, as many as fit without spanning two
+                let bits = usize::max(4, (usize::BITS - (palette.len().max(1) - 1).leading_zeros()) as usize);
+                let per_long = 64 / bits;
+                if states.len() < 4096usize.div_ceil(per_long) {
+                    continue;
+                }
+
+                for i in 0..4096 {
+                    let (x, y, z) = (cx * 16 + (i % 16) as i32, sy * 16 + (i / 256) as i32, cz * 16 + ((i / 16) % 16) as i32);
+                    let (dx, dy, dz) = (x - x0, y - y0, z - z0);
+                    if [dx, dy, dz].iter().any(|&d| d < 0 || d >= size as i32) {
+                        continue;
+                    }
+
+                    let state = (states[i / per_long] as u64 >> ((i % per_long) * bits)) & ((1 << bits) - 1);
+                    let Some(name) = palette.get(state as usize) else {
+                        return Err(format!("chunk ({cx}, {cz}) has a block state out of its palette"));
+                    };
+
+                    let block = Block::from_minecraft(name).unwrap_or_else(|| {
+                        *extract.unmapped.entry(name.clone()).or_default() += 1;
+                        Block::Air
+                    });
+                    extract.blocks[size * (size * dz as usize + dy as usize) + dx as usize] = block;
+                }
+            }
+        }
+    }
+
+    // Air under another name isn't worth mentioning
+    extract.unmapped.retain(|name, _| !name.ends_with("_air"));
+
+    Ok(extract)
+}
+
+/// Path and contents of the region file with chunks `region`, if it exists
+fn read_region(dir: &Path, (x, z): (i32, i32)) -> Result<Option<(PathBuf, Vec<u8>)>, String> {
+    let path = dir.join(format!("r.{x}.{z}.mca"));
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    let bytes = fs::read(&path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+    if bytes.len() < 2 * SECTOR_BYTES {
+        return Err(format!("{} is too small for a region file", path.display()));
+    }
+    Ok(Some((path, bytes)))
+}
+
+/// Sections of the chunk column `(x, z)`, or [None] if it was never generated
+fn read_chunk(path: &Path, region: &[u8], x: i32, z: i32) -> Result<Option<Vec<Section>>, String> {
+    let err = |e: &dyn std::fmt::Display| format!("{}: chunk ({x}, {z}): {e}", path.display());
+
+    // The header has the sector offset (3 bytes) and number of sectors (1 byte) of every chunk
+    let index = 4 * (x.rem_euclid(REGION_SIZE) + z.rem_euclid(REGION_SIZE) * REGION_SIZE) as usize;
+    let location = u32::from_be_bytes(region[index..index + 4].try_into().unwrap());
+    let (offset, sectors) = ((location >> 8) as usize * SECTOR_BYTES, location & 0xff);
+    if offset == 0 || sectors == 0 {
+        return Ok(None);
+    }
+
+    // Then the chunk is its length (4 bytes), its compression (1 byte) and NBT
+    let data = region
+        .get(offset..offset + 5)
+        .map(|header| (u32::from_be_bytes(header[..4].try_into().unwrap()) as usize, header[4]))
+        .and_then(|(len, compression)| Some((region.get(offset + 5..offset + 4 + len)?, compression)));
+    let Some((data, compression)) = data else {
+        return Err(err(&"truncated"));
+    };
+
+    let mut nbt = Vec::new();
+    match compression {
+        1 => GzDecoder::new(data).read_to_end(&mut nbt),
+        2 => ZlibDecoder::new(data).read_to_end(&mut nbt),
+        3 => {
+            nbt.extend_from_slice(data);
+            Ok(data.len())
+        },
+        c => return Err(err(&format!("unknown compression {c}"))),
+    }.map_err(|e| err(&e))?;
+
+    let root = parse_nbt(&nbt).map_err(|e| err(&e))?;
+    fn field<'a>(tag: &'a Tag, name: &str) -> Option<&'a Tag> {
+        match tag {
+            Tag::Compound(fields) => fields.get(name),
+            _ => None,
+        }
+    }
+
+    let version = match field(&root, "DataVersion") {
+        Some(&Tag::Number(version)) => version as i32,
+        _ => 0,
+    };
+    let Some(Tag::List(sections)) = field(&root, "Level").and_then(|level| field(level, "Sections")) else {
+        return Err(err(&format!("no Level.Sections, only Minecraft 1.16 and 1.17 worlds are supported (data version {version})")));
+    };
+    if version < MIN_DATA_VERSION {
+        return Err(err(&format!("data version {version} is older than 1.16, which packs blocks differently")));
+    }
+
+    let sections = sections
+        .iter()
+        .filter_map(|section| {
+            let Some(&Tag::Number(y)) = field(section, "Y") else {
+                return None;
+            };
+            let Some(Tag::List(palette)) = field(section, "Palette") else {
+                return None;
+            };
+            let Some(Tag::LongArray(states)) = field(section, "BlockStates") else {
+                return None;
+            };
+
+            let palette = palette
+                .iter()
+                .map(|entry| match field(entry, "Name") {
+                    Some(Tag::String(name)) => name.clone(),
+                    _ => String::new(),
+                })
+                .collect();
+            Some(Section { y: y as i32, palette, states: states.clone() })
+        })
+        .collect();
+
+    Ok(Some(sections))
+}
+
+/// Parses uncompressed NBT, which is a single named compound
+fn parse_nbt(bytes: &[u8]) -> Result<Tag, String> {
+    let mut reader = Reader { bytes, pos: 0 };
+
+    match reader.u8()? {
+        10 => {
+            reader.string()?;
+            reader.tag(10)
+        },
+        id => Err(format!("NBT starts with a tag of type {id} instead of a compound")),
+    }
+}
+
+/// Cursor over NBT
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let bytes = self.bytes
+            .get(self.pos..self.pos + n)
+            .ok_or_else(|| "NBT is truncated".to_string())?;
+        self.pos += n;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(i16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn len(&mut self) -> Result<usize, String> {
+        usize::try_from(self.i32()?).map_err(|_| "negative length in NBT".to_string())
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.i16()? as u16 as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    /// Payload of a tag of type `id`
+    fn tag(&mut self, id: u8) -> Result<Tag, String> {
+        Ok(match id {
+            1 => Tag::Number(self.u8()? as i8 as _),
+            2 => Tag::Number(self.i16()? as _),
+            3 => Tag::Number(self.i32()? as _),
+            4 => Tag::Number(self.i64()?),
+            5 => {
+                self.take(4)?;
+                Tag::Other
+            },
+            6 => {
+                self.take(8)?;
+                Tag::Other
+            },
+            7 => {
+                let len = self.len()?;
+                self.take(len)?;
+                Tag::Other
+            },
+            8 => Tag::String(self.string()?),
+            9 => {
+                let id = self.u8()?;
+                let len = self.len()?;
+                Tag::List((0..len).map(|_| self.tag(id)).collect::<Result<_, _>>()?)
+            },
+            10 => {
+                let mut fields = HashMap::new();
+                loop {
+                    let id = self.u8()?;
+                    if id == 0 {
+                        break;
+                    }
+                    let name = self.string()?;
+                    fields.insert(name, self.tag(id)?);
+                }
+                Tag::Compound(fields)
+            },
+            11 => {
+                let len = self.len()?;
+                self.take(4 * len)?;
+                Tag::Other
+            },
+            12 => {
+                let len = self.len()?;
+                Tag::LongArray((0..len).map(|_| self.i64()).collect::<Result<_, _>>()?)
+            },
+            id => return Err(format!("unknown NBT tag type {id}")),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use flate2::{write::{GzEncoder, ZlibEncoder}, Compression};
+
+    use super::*;
+
+    /// Writes NBT, with just the tags chunks need
+    #[derive(Default)]
+    struct Nbt(Vec<u8>);
+
+    impl Nbt {
+        fn name(&mut self, id: u8, name: &str) -> &mut Self {
+            self.0.push(id);
+            self.0.extend((name.len() as u16).to_be_bytes());
+            self.0.extend(name.as_bytes());
+            self
+        }
+
+        fn int(&mut self, name: &str, value: i32) -> &mut Self {
+            self.name(3, name).0.extend(value.to_be_bytes());
+            self
+        }
+
+        fn byte(&mut self, name: &str, value: i8) -> &mut Self {
+            self.name(1, name).0.push(value as u8);
+            self
+        }
+
+        fn string(&mut self, name: &str, value: &str) -> &mut Self {
+            self.name(8, name).0.extend((value.len() as u16).to_be_bytes());
+            self.0.extend(value.as_bytes());
+            self
+        }
+
+        fn longs(&mut self, name: &str, values: &[i64]) -> &mut Self {
+            self.name(12, name).0.extend((values.len() as i32).to_be_bytes());
+            self.0.extend(values.iter().flat_map(|v| v.to_be_bytes()));
+            self
+        }
+
+        fn compound(&mut self, name: &str) -> &mut Self {
+            self.name(10, name)
+        }
+
+        /// Starts a list of `len` compounds, each ended with [Nbt::end]
+        fn compounds(&mut self, name: &str, len: usize) -> &mut Self {
+            self.name(9, name).0.push(10);
+            self.0.extend((len as i32).to_be_bytes());
+            self
+        }
+
+        fn end(&mut self) -> &mut Self {
+            self.0.push(0);
+            self
+        }
+    }
+
+    /// Block states `blocks`, as `(index in the section, index in the palette)` and the
+    /// rest 0, packed `bits` at a time without spanning two longs
+    fn pack(bits: usize, blocks: &[(usize, usize)]) -> Vec<i64> {
+        let per_long = 64 / bits;
+        let mut longs = vec![0u64; 4096usize.div_ceil(per_long)];
+        for &(i, state) in blocks {
+            longs[i / per_long] |= (state as u64) << ((i % per_long) * bits);
+        }
+        longs.into_iter().map(|long| long as i64).collect()
+    }
+
+    /// Uncompressed NBT of a chunk column with a single section at the bottom
+    fn chunk(version: i32, palette: &[&str], states: &[i64]) -> Vec<u8> {
+        let mut nbt = Nbt::default();
+        nbt.compound("")
+            .int("DataVersion", version)
+            .compound("Level")
+            .compounds("Sections", 1)
+            .byte("Y", 0)
+            .compounds("Palette", palette.len());
+        for name in palette {
+            nbt.string("Name", name).end();
+        }
+        nbt.longs("BlockStates", states)
+            .end()
+            .end()
+            .end();
+        nbt.0
+    }
+
+    fn gzip(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zlib(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    /// A region file with `chunks`, as `((x, z) in the region, compression, data)`. The
+    /// length of each chunk is `len` more than it should be
+    fn region(chunks: &[((i32, i32), u8, Vec<u8>)], len: i32) -> Vec<u8> {
+        let mut bytes = vec![0; 2 * SECTOR_BYTES];
+        for ((x, z), compression, data) in chunks {
+            let sector = bytes.len() / SECTOR_BYTES;
+            let sectors = (data.len() + 5).div_ceil(SECTOR_BYTES);
+            let index = 4 * (x + z * REGION_SIZE) as usize;
+            bytes[index..index + 4].copy_from_slice(&((sector << 8 | sectors) as u32).to_be_bytes());
+
+            bytes.extend((data.len() as i32 + 1 + len).to_be_bytes());
+            bytes.push(*compression);
+            bytes.extend(data);
+            bytes.resize((sector + sectors) * SECTOR_BYTES, 0);
+        }
+        bytes
+    }
+
+    /// A world called `name` in the temporary directory, with `r.0.0.mca` only
+    fn world(name: &str, region: Vec<u8>) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("fpgacraft-anvil-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("region")).unwrap();
+        fs::write(dir.join("region").join("r.0.0.mca"), region).unwrap();
+        dir
+    }
+
+    /// Reads the box of `world`, and cleans it up
+    fn read(world: PathBuf, corner: [i32; 3], size: usize) -> Result<Extract, String> {
+        let extract = read_box(&world, corner, size);
+        fs::remove_dir_all(world).unwrap();
+        extract
+    }
+
+    /// Index in a section of the block at `(x, y, z)`
+    fn index(x: usize, y: usize, z: usize) -> usize {
+        x + 16 * z + 256 * y
+    }
+
+    #[test]
+    fn gzip_and_zlib_chunks() {
+        // 2 block states still take 4 bits, 16 to a long
+        let palette = ["minecraft:air", "minecraft:stone", "minecraft:glass"];
+        let chunks = [
+            ((0, 0), 1, gzip(&chunk(MIN_DATA_VERSION, &palette, &pack(4, &[(index(1, 2, 3), 1), (index(15, 0, 0), 2)])))),
+            ((1, 0), 2, zlib(&chunk(MIN_DATA_VERSION, &palette, &pack(4, &[(index(0, 0, 0), 2)])))),
+        ];
+        let extract = read(world("compression", region(&chunks, 0)), [0, 0, 0], 17).unwrap();
+
+        let block = |x: usize, y: usize, z: usize| extract.blocks[17 * (17 * z + y) + x];
+        assert_eq!(block(1, 2, 3), Block::Stone);
+        assert_eq!(block(15, 0, 0), Block::Glass);
+        assert_eq!(block(16, 0, 0), Block::Glass);
+        assert_eq!(extract.blocks.iter().filter(|&&b| b != Block::Air).count(), 3);
+
+        // The box reaches into (0, 1) and (1, 1) too, which were never generated
+        assert_eq!(extract.missing_chunks, 2);
+        assert!(extract.unmapped.is_empty());
+    }
+
+    #[test]
+    fn chunks_that_were_never_generated_are_air() {
+        let palette = ["minecraft:stone"];
+        let chunks = [((0, 0), 3, chunk(MIN_DATA_VERSION, &palette, &pack(4, &[])))];
+
+        // Neither in the region file, nor in a region file at all
+        let extract = read(world("missing", region(&chunks, 0)), [16, 0, -16], 16).unwrap();
+        assert!(extract.blocks.iter().all(|&b| b == Block::Air));
+        assert_eq!(extract.missing_chunks, 1);
+
+        let extract = read(world("missing-region", region(&chunks, 0)), [-16, 0, 0], 16).unwrap();
+        assert_eq!(extract.missing_chunks, 1);
+
+        // Whereas the one that's there is all stone
+        let extract = read(world("present", region(&chunks, 0)), [0, 0, 0], 16).unwrap();
+        assert!(extract.blocks.iter().all(|&b| b == Block::Stone));
+        assert_eq!(extract.missing_chunks, 0);
+    }
+
+    #[test]
+    fn palettes_wider_than_4_bits() {
+        // 17 block states take 5 bits, 12 to a long with the top 4 bits unused
+        let unknown = (0..14).map(|i| format!("minecraft:unknown_{i}")).collect::<Vec<_>>();
+        let palette = ["minecraft:air", "minecraft:stone", "minecraft:glass"]
+            .into_iter()
+            .chain(unknown.iter().map(String::as_str))
+            .collect::<Vec<_>>();
+        assert_eq!(palette.len(), 17);
+
+        let states = pack(5, &[(11, 1), (12, 2), (index(3, 4, 5), 16), (4095, 1)]);
+        assert_eq!(states.len(), 342);
+        let chunks = [((0, 0), 2, zlib(&chunk(MIN_DATA_VERSION, &palette, &states)))];
+        let extract = read(world("wide", region(&chunks, 0)), [0, 0, 0], 16).unwrap();
+
+        let block = |x: usize, y: usize, z: usize| extract.blocks[16 * (16 * z + y) + x];
+        assert_eq!(block(11, 0, 0), Block::Stone);
+        assert_eq!(block(12, 0, 0), Block::Glass);
+        assert_eq!(block(15, 15, 15), Block::Stone);
+        assert_eq!(block(3, 4, 5), Block::Air);
+        assert_eq!(extract.unmapped, BTreeMap::from([("minecraft:unknown_13".to_string(), 1)]));
+    }
+
+    #[test]
+    fn truncated_chunk() {
+        let chunks = [((0, 0), 3, chunk(MIN_DATA_VERSION, &["minecraft:stone"], &pack(4, &[])))];
+
+        // Longer than the file
+        let err = read(world("truncated", region(&chunks, SECTOR_BYTES as i32)), [0, 0, 0], 16).unwrap_err();
+        assert!(err.ends_with("chunk (0, 0): truncated"), "{err}");
+
+        // Or the NBT stops halfway through
+        let mut data = chunk(MIN_DATA_VERSION, &["minecraft:stone"], &pack(4, &[]));
+        data.truncate(data.len() / 2);
+        let err = read(world("truncated-nbt", region(&[((0, 0), 3, data)], 0)), [0, 0, 0], 16).unwrap_err();
+        assert!(err.ends_with("NBT is truncated"), "{err}");
+    }
+
+    #[test]
+    fn unknown_compression() {
+        let chunks = [((0, 0), 4, chunk(MIN_DATA_VERSION, &["minecraft:stone"], &pack(4, &[])))];
+
+        let err = read(world("compression-4", region(&chunks, 0)), [0, 0, 0], 16).unwrap_err();
+        assert!(err.ends_with("unknown compression 4"), "{err}");
+    }
+
+    #[test]
+    fn old_data_version() {
+        let chunks = [((0, 0), 3, chunk(MIN_DATA_VERSION - 1, &["minecraft:stone"], &pack(4, &[])))];
+
+        let err = read(world("old", region(&chunks, 0)), [0, 0, 0], 16).unwrap_err();
+        assert!(err.contains("older than 1.16"), "{err}");
+    }
+}
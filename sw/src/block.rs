@@ -1,24 +1,18 @@
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-#[allow(unused)]
-pub enum Block {
-    #[default]
-    Air,
-    Stone,
-    Grass,
-    Dirt,
-    Cobblestone,
-    OakPlanks,
-    SprucePlanks,
-    BirchPlanks,
-    Water,
-    Sand,
-    Gravel,
-    OakLog,
-    SpruceLog,
-    BirchLog,
-    OakLeaves,
-    SpruceLeaves,
-    BirchLeaves,
-    Glass,
-}
\ No newline at end of file
+//! Blocks are defined in `palette/blocks.csv`, which `build.rs` turns into the [Block] enum
+//! along with [Block::color] and [Block::material]. Add blocks there, not here.
+
+use crate::{fixed, math::{Fixed, Rgb565}};
+
+/// How light goes through a block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Material {
+    /// Rays stop at the block
+    Opaque,
+    /// Rays go through the block as if it weren't there
+    Transparent,
+    /// Rays go through the block, which covers what's behind it with its [Block::color] by
+    /// `alpha` per voxel crossed
+    Translucent { alpha: Fixed },
+}
+
+include!(concat!(env!("OUT_DIR"), "/block.rs"));
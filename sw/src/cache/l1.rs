@@ -0,0 +1,254 @@
+use crate::{block::Block, counters::CacheCounters, math::Vec3i, trace::{Trace, Tracer}};
+
+use super::{Invalidate, L2Port};
+
+/// A small fully-associative cache private to one VTU, sitting in front of its L2 port
+#[derive(Debug, Default)]
+pub struct L1Cache {
+    /// Reset the cache to a known state
+    pub reset: bool,
+    /// Voxel index being queried
+    pub addr_in: Vec3i,
+    /// Whether [L1Cache::addr_in] should actually be queried
+    pub read_enable_in: bool,
+    /// Voxel data being queried
+    pub voxel_out: Block,
+    /// Whether [L1Cache::voxel_out] corresponds to the address inputted
+    pub valid_out: bool,
+    /// Entries to drop this cycle, as the L3's window changes. What comes from the L2 is
+    /// always up to date, as it drops them first
+    pub invalidate_in: Invalidate,
+    /// Port of the L2 cache used for misses
+    pub l2: L2Port,
+    /// Performance counters, not part of the design
+    pub counters: CacheCounters,
+
+    /// Entries in the cache
+    entries: Box<[Option<Entry>]>,
+    /// Index of the next entry that will be replaced upon a cache miss
+    next_replacement: usize,
+    /// Address that missed and is being fetched from the L2
+    miss: Option<Vec3i>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Entry {
+    key: Vec3i,
+    value: Block,
+}
+
+impl L1Cache {
+    pub fn with_size(mut self, entries: usize) -> Self {
+        assert!(entries > 0, "an L1 cache needs at least one entry");
+
+        self.entries = vec![None; entries].into_boxed_slice();
+        self
+    }
+
+    pub fn rising_clk_edge(&mut self) {
+        // Reset
+        if self.reset {
+            self.voxel_out = Block::Air;
+            self.valid_out = false;
+            self.entries.fill(None);
+            self.next_replacement = 0;
+            self.miss = None;
+            self.counters = Default::default();
+
+            self.l2.read_enable_out = false;
+            return;
+        }
+
+        self.valid_out = false;
+
+        for entry in self.entries.iter_mut() {
+            if entry.is_some_and(|e| self.invalidate_in.covers(e.key)) {
+                *entry = None;
+            }
+        }
+
+        // The query changed while waiting on the L2, forget about the miss
+        if self.miss.is_some_and(|addr| !self.read_enable_in || addr != self.addr_in) {
+            self.miss = None;
+        }
+
+        // Stall until the L2 has the data, then fill an entry with it
+        if let Some(addr) = self.miss {
+            if !self.l2.valid_in {
+                self.counters.stall_cycles += 1;
+                return;
+            }
+
+            self.entries[self.next_replacement] = Some(Entry {
+                key: addr,
+                value: self.l2.voxel_in,
+            });
+            self.next_replacement = (self.next_replacement + 1) % self.entries.len();
+            self.miss = None;
+
+            self.voxel_out = self.l2.voxel_in;
+            self.valid_out = true;
+
+            self.l2.read_enable_out = false;
+            return;
+        }
+
+        if !self.read_enable_in {
+            self.l2.read_enable_out = false;
+            return;
+        }
+
+        // Check every entry
+        let hit = self.entries
+            .iter()
+            .flatten()
+            .find(|e| e.key == self.addr_in);
+
+        match hit {
+            // Cache-hit!
+            Some(entry) => {
+                self.voxel_out = entry.value;
+                self.valid_out = true;
+
+                self.l2.read_enable_out = false;
+
+                self.counters.hits += 1;
+            },
+            // Cache-miss, ask the L2
+            None => {
+                self.miss = Some(self.addr_in);
+                self.counters.misses += 1;
+                self.counters.stall_cycles += 1;
+
+                self.l2.addr_out = self.addr_in;
+                self.l2.read_enable_out = true;
+            },
+        }
+    }
+}
+
+impl Trace for L1Cache {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("l1", |t| {
+            t.bit("reset", self.reset);
+            t.vec3i("addr_in", self.addr_in);
+            t.bit("read_enable_in", self.read_enable_in);
+            t.block("voxel_out", self.voxel_out);
+            t.bit("valid_out", self.valid_out);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A reset L1 cache of `entries` entries
+    fn l1(entries: usize) -> L1Cache {
+        let mut l1 = L1Cache::default().with_size(entries);
+        l1.reset = true;
+        l1.rising_clk_edge();
+        l1.reset = false;
+        l1
+    }
+
+    /// Queries `addr` for one cycle, without an answer from the L2, returning what came out
+    fn query(l1: &mut L1Cache, addr: Vec3i) -> Option<Block> {
+        l1.addr_in = addr;
+        l1.read_enable_in = true;
+        l1.l2.valid_in = false;
+        l1.rising_clk_edge();
+
+        l1.valid_out.then_some(l1.voxel_out)
+    }
+
+    /// Misses on `addr`, then gets `block` from the L2 on the next cycle
+    fn fill(l1: &mut L1Cache, addr: Vec3i, block: Block) {
+        assert_eq!(query(l1, addr), None);
+        assert!(l1.l2.read_enable_out);
+        assert_eq!(l1.l2.addr_out, addr);
+
+        l1.l2.voxel_in = block;
+        l1.l2.valid_in = true;
+        l1.rising_clk_edge();
+        l1.l2.valid_in = false;
+
+        assert!(l1.valid_out);
+        assert_eq!(l1.voxel_out, block);
+        assert!(!l1.l2.read_enable_out);
+    }
+
+    #[test]
+    fn miss_then_hit() {
+        let mut l1 = l1(2);
+        let addr = Vec3i::new(1, 2, 3);
+
+        fill(&mut l1, addr, Block::Stone);
+        assert_eq!(query(&mut l1, addr), Some(Block::Stone));
+        assert!(!l1.l2.read_enable_out);
+
+        assert_eq!((l1.counters.hits, l1.counters.misses), (1, 1));
+    }
+
+    #[test]
+    fn stalls_until_the_l2_answers() {
+        let mut l1 = l1(2);
+        let addr = Vec3i::new(1, 2, 3);
+
+        for _ in 0..5 {
+            assert_eq!(query(&mut l1, addr), None);
+            assert!(l1.l2.read_enable_out);
+        }
+
+        // Only counted once, but stalled all along
+        assert_eq!((l1.counters.misses, l1.counters.stall_cycles), (1, 5));
+    }
+
+    #[test]
+    fn evicts_the_oldest_entry() {
+        let mut l1 = l1(2);
+        let (a, b, c) = (Vec3i::new(0, 0, 0), Vec3i::new(1, 0, 0), Vec3i::new(2, 0, 0));
+
+        fill(&mut l1, a, Block::Stone);
+        fill(&mut l1, b, Block::Dirt);
+        fill(&mut l1, c, Block::Grass);
+
+        // `c` went where `a` was
+        assert_eq!(query(&mut l1, b), Some(Block::Dirt));
+        assert_eq!(query(&mut l1, c), Some(Block::Grass));
+        assert_eq!(query(&mut l1, a), None);
+    }
+
+    #[test]
+    fn forgets_a_miss_when_the_query_changes() {
+        let mut l1 = l1(2);
+        let (a, b) = (Vec3i::new(0, 0, 0), Vec3i::new(1, 0, 0));
+
+        assert_eq!(query(&mut l1, a), None);
+        fill(&mut l1, b, Block::Dirt);
+
+        // The L2's answer went to `b`, `a` never made it in
+        assert_eq!(query(&mut l1, a), None);
+        assert_eq!(l1.l2.addr_out, a);
+    }
+
+    #[test]
+    fn drops_invalidated_entries() {
+        let mut l1 = l1(2);
+        let (a, b) = (Vec3i::new(0, 0, 0), Vec3i::new(1, 0, 0));
+
+        fill(&mut l1, a, Block::Stone);
+        fill(&mut l1, b, Block::Dirt);
+
+        l1.invalidate_in = Invalidate::Voxel(a);
+        assert_eq!(query(&mut l1, a), None);
+        l1.invalidate_in = Invalidate::None;
+        assert_eq!(query(&mut l1, b), Some(Block::Dirt));
+
+        fill(&mut l1, a, Block::Glass);
+        l1.invalidate_in = Invalidate::All;
+        assert_eq!(query(&mut l1, b), None);
+        l1.invalidate_in = Invalidate::None;
+        assert_eq!(query(&mut l1, a), None);
+    }
+}
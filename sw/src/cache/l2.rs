@@ -1,29 +1,65 @@
 use std::{cell::RefCell, iter::zip, rc::Rc};
 
-use crate::{block::Block, math::Vec3i};
+use crate::{block::Block, counters::L2Counters, math::Vec3i, top_level::{NUM_L2_ENTRIES, NUM_VTU}, trace::{Trace, Tracer}};
 
-use super::{l2_cache_add_access, l2_cache_add_hit, L3Cache};
+use super::{Invalidate, L3Cache};
 
-/// An L2 cache with `P` ports and `S` entries
+/// An L2 cache with one port per VTU, see [L2Cache::with_size]
 #[derive(Debug)]
-pub struct L2Cache<const P: usize, const S: usize> {
+pub struct L2Cache {
     /// Reset the cache to a known state
     pub reset: bool,
     /// Voxel indices being queried by each port
-    pub addr_in: [Vec3i; P],
+    pub addr_in: Vec<Vec3i>,
     /// Whether [L2Cache::addr_in] should actually be queried
-    pub read_enable_in: [bool; P],
+    pub read_enable_in: Vec<bool>,
     /// Voxel data being queried by each port
-    pub voxel_out: [Block; P],
+    pub voxel_out: Vec<Block>,
     /// Whether [L2Cache::voxel_out] corresponds to the address inputted
-    pub valid_out: [bool; P],
+    pub valid_out: Vec<bool>,
+    /// Entries to drop this cycle, as the L3's window changes
+    pub invalidate_in: Invalidate,
     /// Reference to an L3 cache used for cache misses
     pub l3: Rc<RefCell<L3Cache>>,
+    /// Performance counters, not part of the design
+    pub counters: L2Counters,
 
-    /// Entries in the cache
-    entries: [Option<Entry>; S],
-    /// Index of the next entry that will be replaced upon a cache miss
-    next_replacement: usize,
+    /// How entries are grouped into sets
+    organization: Organization,
+    /// Which entry of a set gets evicted upon a cache miss
+    replacement: Replacement,
+    /// Entries in the cache, set after set
+    entries: Box<[Option<Entry>]>,
+    /// Index (within its set) of the next entry that will be replaced upon a cache miss, per set
+    next_replacement: Vec<usize>,
+    /// Last cycle each entry was read or written
+    last_used: Box<[usize]>,
+    /// Binary tree of "least recently used half" bits, per set
+    plru_tree: Vec<u64>,
+    /// Source of randomness for [Replacement::Random]
+    lfsr: u16,
+    /// Free-running cycle counter
+    cycle: usize,
+    /// Misses that were sent to the L3 and haven't come back yet
+    pending: Vec<Vec3i>,
+    /// Address that each port missed on and is still waiting for, so that a miss is only
+    /// counted once
+    waiting: Vec<Option<Vec3i>>,
+}
+
+/// The wires between one port of the [L2Cache] and the VTU (or L1 cache) using it, from
+/// the user's side. The orchestrator connects them to the cache every cycle, see
+/// [L2Cache::drive] and [L2Cache::latch]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct L2Port {
+    /// Voxel index being queried, to [L2Cache::addr_in]
+    pub addr_out: Vec3i,
+    /// Whether [L2Port::addr_out] should actually be queried, to [L2Cache::read_enable_in]
+    pub read_enable_out: bool,
+    /// Voxel data being queried, from [L2Cache::voxel_out]
+    pub voxel_in: Block,
+    /// Whether [L2Port::voxel_in] corresponds to the address queried, from [L2Cache::valid_out]
+    pub valid_in: bool,
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -32,83 +68,480 @@ struct Entry {
     value: Block,
 }
 
-impl<const P: usize, const S: usize> Default for L2Cache<P, S> {
+/// How entries of the [L2Cache] are grouped into sets. A voxel can only be cached in
+/// one set, and a lookup compares against every entry (way) of that set.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Organization {
+    /// One set with every entry in it
+    #[default]
+    FullyAssociative,
+    /// As many sets as entries, one entry each
+    DirectMapped,
+    /// Sets of `N` entries each
+    SetAssociative(usize),
+}
+
+/// Which entry of a set gets evicted upon a cache miss
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Replacement {
+    /// Oldest entry to be filled (round-robin)
+    #[default]
+    Fifo,
+    /// Entry that has gone the longest without being read
+    Lru,
+    /// Tree of bits that approximates [Replacement::Lru]. Needs a power-of-two number of ways
+    PseudoLru,
+    /// Any entry, from a 16-bit LFSR
+    Random,
+}
+
+impl Organization {
+    /// Parses `full`, `direct`, or `N-way`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "full" => Ok(Self::FullyAssociative),
+            "direct" => Ok(Self::DirectMapped),
+            _ => s
+                .strip_suffix("-way")
+                .and_then(|n| n.parse().ok())
+                .filter(|&n| n > 0)
+                .map(Self::SetAssociative)
+                .ok_or_else(|| format!("unknown L2 organization {s:?}, expected full, direct or N-way")),
+        }
+    }
+
+    /// Number of entries per set, in a cache with `entries` entries
+    pub const fn ways(self, entries: usize) -> usize {
+        match self {
+            Self::FullyAssociative => entries,
+            Self::DirectMapped => 1,
+            Self::SetAssociative(n) => n,
+        }
+    }
+}
+
+impl Replacement {
+    /// Parses `fifo`, `lru`, `plru` or `random`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "fifo" => Ok(Self::Fifo),
+            "lru" => Ok(Self::Lru),
+            "plru" => Ok(Self::PseudoLru),
+            "random" => Ok(Self::Random),
+            _ => Err(format!("unknown L2 replacement policy {s:?}, expected fifo, lru, plru or random")),
+        }
+    }
+}
+
+impl Default for L2Cache {
     fn default() -> Self {
         Self {
             reset: Default::default(),
-            addr_in: [Default::default(); P],
-            read_enable_in: [Default::default(); P],
-            voxel_out: [Default::default(); P],
-            valid_out: [Default::default(); P],
+            addr_in: Default::default(),
+            read_enable_in: Default::default(),
+            voxel_out: Default::default(),
+            valid_out: Default::default(),
+            invalidate_in: Default::default(),
             l3: Default::default(),
-            entries: [Default::default(); S],
-            next_replacement: Default::default()
+            counters: Default::default(),
+            organization: Default::default(),
+            replacement: Default::default(),
+            entries: Default::default(),
+            next_replacement: Default::default(),
+            last_used: Default::default(),
+            plru_tree: Default::default(),
+            lfsr: Default::default(),
+            cycle: Default::default(),
+            pending: Default::default(),
+            waiting: Default::default(),
         }
+        .with_size(NUM_VTU, NUM_L2_ENTRIES)
     }
 }
 
-impl<const P: usize, const S: usize> L2Cache<P, S> {
+impl L2Cache {
+    /// Number of tag comparators the cache needs in hardware: every port compares
+    /// against every way of a set
+    pub fn comparators(&self) -> usize {
+        self.ports() * self.ways()
+    }
+
+    /// Gives the cache `ports` ports and `entries` entries. Comes before
+    /// [L2Cache::with_policy], whose sets depend on the number of entries
+    pub fn with_size(mut self, ports: usize, entries: usize) -> Self {
+        assert!(ports > 0 && entries > 0, "an L2 cache needs at least one port and one entry");
+
+        self.addr_in = vec![Default::default(); ports];
+        self.read_enable_in = vec![false; ports];
+        self.voxel_out = vec![Block::Air; ports];
+        self.valid_out = vec![false; ports];
+        self.waiting = vec![None; ports];
+        self.entries = vec![None; entries].into_boxed_slice();
+        self.last_used = vec![0; entries].into_boxed_slice();
+        self.counters = L2Counters::new(ports, self.comparators());
+        self
+    }
+
+    pub fn with_policy(mut self, organization: Organization, replacement: Replacement) -> Self {
+        let entries = self.entries.len();
+        let ways = organization.ways(entries);
+
+        assert!(ways > 0 && entries.is_multiple_of(ways), "{ways} ways don't evenly divide {entries} L2 entries");
+        assert!(replacement != Replacement::PseudoLru || ways.is_power_of_two(), "pseudo-LRU needs a power-of-two number of ways, got {ways}");
+        assert!(replacement != Replacement::PseudoLru || ways <= 64, "pseudo-LRU supports at most 64 ways, got {ways}");
+
+        self.organization = organization;
+        self.replacement = replacement;
+        self
+    }
+
+    /// Puts the outputs of port `i` on `port`'s wires
+    pub fn drive(&self, i: usize, port: &mut L2Port) {
+        port.voxel_in = self.voxel_out[i];
+        port.valid_in = self.valid_out[i];
+    }
+
+    /// Takes the inputs of port `i` from `port`'s wires
+    pub fn latch(&mut self, i: usize, port: &L2Port) {
+        self.addr_in[i] = port.addr_out;
+        self.read_enable_in[i] = port.read_enable_out;
+    }
+
     pub fn rising_clk_edge(&mut self) {
         // Reset
         if self.reset {
-            self.voxel_out = [Block::Air; P];
-            self.valid_out = [false; P];
-            self.entries = [None; S];
-            self.next_replacement = 0;
+            self.voxel_out.fill(Block::Air);
+            self.valid_out.fill(false);
+            self.entries.fill(None);
+            self.next_replacement = vec![0; self.sets()];
+            self.last_used.fill(0);
+            self.plru_tree = vec![0; self.sets()];
+            self.lfsr = 0xACE1;
+            self.cycle = 0;
+            self.pending.clear();
+            self.waiting.fill(None);
+            self.counters = L2Counters::new(self.ports(), self.comparators());
             return;
         }
 
-        let mut l3 = self.l3.borrow_mut();
+        self.cycle += 1;
+
+        let l3 = Rc::clone(&self.l3);
+        let mut l3 = l3.borrow_mut();
 
         // Replace cache entry with last query from L3, if valid
         if l3.valid_out {
+            let set = self.set_of(l3.addr_out);
+
             // Make sure there are no duplicates
-            assert!(self.entries.iter().flatten().find(|e| e.key == l3.addr_in).is_none());
-            
-            self.entries[self.next_replacement] = Some(Entry {
-                key: l3.addr_in,
+            assert!(!self.set_entries(set).iter().flatten().any(|e| e.key == l3.addr_out));
+
+            let way = self.victim(set);
+            let i = set * self.ways() + way;
+
+            self.pending.retain(|&addr| addr != l3.addr_out);
+            self.entries[i] = Some(Entry {
+                key: l3.addr_out,
                 value: l3.voxel_out,
             });
-            self.next_replacement = (self.next_replacement + 1) % S;
+            self.touch(set, way);
         }
-        
+
+        // Drop what the L3 changed, after the fill: what the L3 sent this cycle was read
+        // before the change
+        if self.invalidate_in != Invalidate::None {
+            for entry in self.entries.iter_mut() {
+                if entry.is_some_and(|e| self.invalidate_in.covers(e.key)) {
+                    *entry = None;
+                }
+            }
+        }
+
         // Respond to each port's query...
-        for ((&addr, re), (voxel, valid)) in zip(zip(&self.addr_in, &self.read_enable_in), zip(&mut self.voxel_out, &mut self.valid_out)) {
-            *valid = false;
-            if !re {
+        for port in 0..self.ports() {
+            self.valid_out[port] = false;
+            if !self.read_enable_in[port] {
+                self.waiting[port] = None;
                 continue;
             }
 
-            l2_cache_add_access();
+            // ...by checking every entry of the set it maps to
+            let addr = self.addr_in[port];
+            let retry = self.waiting[port] == Some(addr);
+            let set = self.set_of(addr);
+            let hit = self
+                .set_entries(set)
+                .iter()
+                .position(|entry| entry.is_some_and(|e| e.key == addr));
 
-            // ...by checking every entry
-            for entry in self.entries {
-                match entry {
-                    // Cache-hit!
-                    Some(Entry { value, key }) if key == addr => {
-                        *valid = true;
-                        *voxel = value;
+            // Cache-hit!
+            if let Some(way) = hit {
+                self.valid_out[port] = true;
+                self.voxel_out[port] = self.set_entries(set)[way].unwrap().value;
+                self.touch(set, way);
 
-                        l2_cache_add_hit();
-
-                        break;
-                    },
-                    _ => {}
-                }
+                self.counters.ports[port].hits += usize::from(!retry);
+                self.waiting[port] = None;
+            } else {
+                self.counters.ports[port].misses += usize::from(!retry);
+                self.counters.ports[port].stall_cycles += 1;
+                self.waiting[port] = Some(addr);
             }
         }
 
         // Cache-miss static priority arbitration:
         // Port 0 has priority, then port 1, port 2, etc...
-        match zip(&self.addr_in, zip(&self.valid_out, &self.read_enable_in)).find(|(_, (&valid, &re))| re && !valid) {
-            Some((&addr, _)) => {
+        // Misses already on their way from the L3 don't get requested twice
+        let miss = zip(&self.addr_in, zip(&self.valid_out, &self.read_enable_in))
+            .find(|&(addr, (&valid, &re))| re && !valid && !self.pending.contains(addr));
+
+        match miss {
+            Some((&addr, _)) if l3.ready_out => {
                 l3.addr_in = addr;
                 l3.read_enable_in = true;
+
+                self.pending.push(addr);
             },
-            _ => {
-                // If there's no cache-miss, don't bother querying the L3
+            miss => {
+                // If there's no new cache-miss (or the L3 is busy), don't bother querying the L3
                 l3.read_enable_in = false;
+
+                self.counters.l3_busy_cycles += usize::from(miss.is_some());
+            }
+        }
+    }
+
+    fn ports(&self) -> usize {
+        self.addr_in.len()
+    }
+
+    fn ways(&self) -> usize {
+        self.organization.ways(self.entries.len())
+    }
+
+    fn sets(&self) -> usize {
+        self.entries.len() / self.ways()
+    }
+
+    /// Set that a voxel maps to. In hardware, this is just a few XORs of the address'
+    /// low bits, so that neighbouring voxels land in different sets.
+    fn set_of(&self, addr: Vec3i) -> usize {
+        let hash = (addr.x ^ (addr.y << 1) ^ (addr.z << 2)) as u16;
+
+        hash as usize % self.sets()
+    }
+
+    fn set_entries(&self, set: usize) -> &[Option<Entry>] {
+        &self.entries[set * self.ways()..(set + 1) * self.ways()]
+    }
+
+    /// Way of `set` to evict for the next fill
+    fn victim(&mut self, set: usize) -> usize {
+        let ways = self.ways();
+
+        match self.replacement {
+            Replacement::Fifo => {
+                let way = self.next_replacement[set];
+                self.next_replacement[set] = (way + 1) % ways;
+                way
+            },
+            Replacement::Lru => (0..ways)
+                .min_by_key(|&way| self.last_used[set * ways + way])
+                .unwrap(),
+            Replacement::PseudoLru => {
+                // Follow the bits from the root, they point to the least recently used half
+                let mut node = 1;
+                while node < ways {
+                    node = 2 * node + ((self.plru_tree[set] >> node) & 1) as usize;
+                }
+                node - ways
+            },
+            Replacement::Random => {
+                // Empty entries get filled first
+                if let Some(way) = self.set_entries(set).iter().position(Option::is_none) {
+                    return way;
+                }
+
+                // Galois LFSR, x^16 + x^14 + x^13 + x^11 + 1
+                self.lfsr = (self.lfsr >> 1) ^ (0u16.wrapping_sub(self.lfsr & 1) & 0xB400);
+                self.lfsr as usize % ways
+            },
+        }
+    }
+
+    /// Marks an entry as just used
+    fn touch(&mut self, set: usize, way: usize) {
+        let ways = self.ways();
+
+        self.last_used[set * ways + way] = self.cycle;
+
+        if self.replacement != Replacement::PseudoLru {
+            return;
+        }
+
+        // Make every node on the way's path point away from it
+        let levels = ways.trailing_zeros();
+        let mut node = 1;
+        for level in (0..levels).rev() {
+            let bit = (way >> level) & 1;
+
+            self.plru_tree[set] = (self.plru_tree[set] & !(1 << node)) | (((bit ^ 1) as u64) << node);
+            node = 2 * node + bit;
+        }
+    }
+}
+
+impl Trace for L2Cache {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("l2", |t| {
+            t.bit("reset", self.reset);
+
+            for port in 0..self.ports() {
+                t.scope(format!("port{port}"), |t| {
+                    t.vec3i("addr_in", self.addr_in[port]);
+                    t.bit("read_enable_in", self.read_enable_in[port]);
+                    t.block("voxel_out", self.voxel_out[port]);
+                    t.bit("valid_out", self.valid_out[port]);
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{DdrTiming, Shift, Window, WindowUpdate};
+
+    use super::*;
+
+    /// A reset one-port `l2` in front of an L3 without latency
+    fn connect(l2: L2Cache) -> L2Cache {
+        let mut l2 = l2;
+        l2.l3 = Rc::new(RefCell::new(L3Cache::default().with_timing(DdrTiming::INSTANT)));
+        l2.reset = true;
+        l2.l3.borrow_mut().reset = true;
+        l2.rising_clk_edge();
+        l2.l3.borrow_mut().rising_clk_edge();
+        l2.reset = false;
+        l2.l3.borrow_mut().reset = false;
+        l2
+    }
+
+    /// Reads `addr` on port 0, clocking like the orchestrator does until it's there
+    fn read(l2: &mut L2Cache, addr: Vec3i) -> Block {
+        l2.addr_in[0] = addr;
+        l2.read_enable_in[0] = true;
+
+        for _ in 0..100 {
+            l2.rising_clk_edge();
+            l2.l3.borrow_mut().rising_clk_edge();
+            if l2.valid_out[0] {
+                l2.read_enable_in[0] = false;
+                return l2.voxel_out[0];
             }
         }
+        panic!("no answer for {addr:?}");
     }
-}
\ No newline at end of file
+
+    /// Clocks one cycle during which the L3's window goes through `update`, as the
+    /// orchestrator has it
+    fn update(l2: &mut L2Cache, update: WindowUpdate) {
+        let mut l3 = l2.l3.borrow_mut();
+        l3.window_in = update;
+        l2.invalidate_in = update.invalidation(l3.window_min_out);
+        drop(l3);
+
+        l2.rising_clk_edge();
+        l2.l3.borrow_mut().rising_clk_edge();
+
+        l2.l3.borrow_mut().window_in = WindowUpdate::None;
+        l2.invalidate_in = Invalidate::None;
+    }
+
+    #[test]
+    fn write_invalidates_the_voxel() {
+        let mut l2 = connect(L2Cache::default().with_size(1, 4));
+        let min = Window::default().min();
+        let (a, b) = (min, min + Vec3i::new(1, 0, 0));
+
+        let old = read(&mut l2, a);
+        assert_ne!(old, Block::Glass);
+        read(&mut l2, b);
+
+        update(&mut l2, WindowUpdate::Write(Vec3i::new(0, 0, 0), Block::Glass));
+        assert_eq!(read(&mut l2, a), Block::Glass);
+        read(&mut l2, b);
+
+        // Only `a` had to come from the L3 again
+        let counters = &l2.counters.ports[0];
+        assert_eq!((counters.misses, counters.hits), (3, 1));
+    }
+
+    #[test]
+    fn write_drops_a_fill_read_before_it() {
+        let mut l2 = connect(L2Cache::default().with_size(1, 4));
+        let a = Window::default().min();
+
+        // The L3 answers the miss before the write...
+        l2.addr_in[0] = a;
+        l2.read_enable_in[0] = true;
+        l2.rising_clk_edge();
+        l2.l3.borrow_mut().rising_clk_edge();
+        assert!(l2.l3.borrow().valid_out);
+
+        // ...and the L2 only gets its answer on the cycle of the write
+        update(&mut l2, WindowUpdate::Write(Vec3i::new(0, 0, 0), Block::Glass));
+        assert!(!l2.valid_out[0]);
+        assert_eq!(read(&mut l2, a), Block::Glass);
+    }
+
+    #[test]
+    fn shift_flushes_everything() {
+        let mut l2 = connect(L2Cache::default().with_size(1, 4));
+        let min = Window::default().min();
+        let (a, b) = (min, min + Vec3i::new(1, 0, 0));
+
+        read(&mut l2, a);
+        let block = read(&mut l2, b);
+
+        update(&mut l2, WindowUpdate::Shift(Shift::PosX));
+
+        // `a` went out of view, and `b` is still there but has to be read again
+        assert_eq!(read(&mut l2, a), Block::Air);
+        assert_eq!(read(&mut l2, b), block);
+
+        let counters = &l2.counters.ports[0];
+        assert_eq!((counters.misses, counters.hits), (4, 0));
+    }
+
+    #[test]
+    fn more_than_64_ways() {
+        let voxels = (0..128).map(|i| Vec3i::new(i % 32, i / 32, 0)).collect::<Vec<_>>();
+
+        for replacement in [Replacement::Fifo, Replacement::Lru] {
+            let mut l2 = connect(L2Cache::default()
+                .with_size(1, 128)
+                .with_policy(Organization::FullyAssociative, replacement)
+            );
+
+            for &voxel in &voxels {
+                assert_eq!(Some(read(&mut l2, voxel)), Window::default().query(voxel));
+            }
+            for &voxel in &voxels {
+                read(&mut l2, voxel);
+            }
+
+            // Every voxel fits, so the second time around they're all hits
+            let counters = &l2.counters.ports[0];
+            assert_eq!((counters.misses, counters.hits), (128, 128), "{replacement:?}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "pseudo-LRU supports at most 64 ways")]
+    fn pseudo_lru_at_most_64_ways() {
+        let _ = L2Cache::default()
+            .with_size(1, 128)
+            .with_policy(Organization::FullyAssociative, Replacement::PseudoLru);
+    }
+}
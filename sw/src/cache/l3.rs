@@ -1,6 +1,8 @@
-use crate::{block::Block, math::Vec3i};
+use std::collections::VecDeque;
 
-use super::MockCache;
+use crate::{block::Block, counters::L3Counters, math::Vec3i, trace::{Trace, Tracer}};
+
+use super::{Shift, Window};
 
 #[derive(Debug, Default)]
 pub struct L3Cache {
@@ -8,32 +10,361 @@ pub struct L3Cache {
     pub reset: bool,
     /// Voxel index being queried
     pub addr_in: Vec3i,
-    /// Whether [L3Cache::addr_in] should actually be queried
+    /// Whether [L3Cache::addr_in] should actually be queried. Only allowed when
+    /// [L3Cache::ready_out] is high
     pub read_enable_in: bool,
+    /// Whether a new read can be accepted this cycle
+    pub ready_out: bool,
     /// Voxel data being queried
     pub voxel_out: Block,
-    /// Whether [L3Cache::voxel_out] corresponds to the address inputted
+    /// Voxel index that [L3Cache::voxel_out] corresponds to
+    pub addr_out: Vec3i,
+    /// Whether [L3Cache::voxel_out] is valid this cycle
     pub valid_out: bool,
+    /// What the host's link does to the window of voxels this cycle
+    pub window_in: WindowUpdate,
+    /// Corner with the smallest coordinates of the window of voxels
+    pub window_min_out: Vec3i,
+    /// Performance counters, not part of the design
+    pub counters: L3Counters,
+
+    /// Timing of the DDR memory
+    timing: DdrTiming,
+    /// Reads waiting for the memory controller to issue them
+    queue: VecDeque<Vec3i>,
+    /// Reads issued to the DDR, and the cycle at which their data comes back
+    in_flight: VecDeque<(Vec3i, usize)>,
+    /// Row currently open in the DDR
+    open_row: Option<usize>,
+    /// Cycle at which the data bus is done with the last burst
+    bus_free: usize,
+    /// Free-running cycle counter
+    cycle: usize,
 
     /// Shhhh...
-    ddr_ram: MockCache,
+    ddr_ram: Window,
+}
+
+/// What the host's link does to the [Window] of an [L3Cache] on a cycle. One or the other,
+/// as they both come from the UART
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum WindowUpdate {
+    #[default]
+    None,
+    /// Slide the window, `control_trigger` in `l3_cache.sv`
+    Shift(Shift),
+    /// Write a voxel at an offset from the window's corner, `write_enable` in `l3_cache.sv`
+    Write(Vec3i, Block),
+}
+
+impl WindowUpdate {
+    /// What the caches in front of the L3 have to forget about when the window's corner
+    /// with the smallest coordinates is at `window_min` and this update happens
+    pub fn invalidation(self, window_min: Vec3i) -> Invalidate {
+        match self {
+            Self::None => Invalidate::None,
+            Self::Shift(_) => Invalidate::All,
+            Self::Write(offset, _) => Invalidate::Voxel(window_min + offset),
+        }
+    }
+}
+
+/// Entries that the [super::L2Cache] and [super::L1Cache] drop on a cycle, because the
+/// [L3Cache] behind them now holds something else for them. `l2_cache.sv` doesn't do this
+/// (yet): it keeps serving the voxels it had before the window changed
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Invalidate {
+    #[default]
+    None,
+    /// The window slid, which changes what's at the voxels that come into and go out of
+    /// view. Everything is dropped, by clearing the valid bits
+    All,
+    /// The voxel at these world coordinates was written
+    Voxel(Vec3i),
+}
+
+impl Invalidate {
+    /// Whether an entry for the voxel at `key` has to be dropped
+    pub fn covers(self, key: Vec3i) -> bool {
+        match self {
+            Self::None => false,
+            Self::All => true,
+            Self::Voxel(addr) => addr == key,
+        }
+    }
+}
+
+/// Timing of the DDR memory behind the [L3Cache], in clock cycles
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DdrTiming {
+    /// Cycles from issuing a read to an open row until its data comes back
+    pub read_latency: usize,
+    /// Extra cycles when a read goes to a different row than the open one (precharge + activate)
+    pub row_miss_penalty: usize,
+    /// Cycles the data bus is busy for every read (one burst)
+    pub burst_cycles: usize,
+    /// Number of consecutive voxels (in `chunk.bin` order) that share a row
+    pub row_size: usize,
+    /// Maximum number of reads issued to the DDR at once
+    pub max_outstanding: usize,
+    /// Number of reads that can wait in front of the memory controller
+    pub queue_depth: usize,
+}
+
+impl DdrTiming {
+    /// Memory that answers on the same cycle, i.e. the L2 gets the data on the next cycle
+    pub const INSTANT: Self = Self {
+        read_latency: 0,
+        row_miss_penalty: 0,
+        burst_cycles: 1,
+        row_size: 1,
+        max_outstanding: 1,
+        queue_depth: 1,
+    };
+
+    /// Roughly what the MIG DDR3 controller gives us at a 4:1 memory-to-user clock ratio
+    pub const DDR3: Self = Self {
+        read_latency: 20,
+        row_miss_penalty: 8,
+        burst_cycles: 1,
+        row_size: 2048,
+        max_outstanding: 8,
+        queue_depth: 4,
+    };
+
+    /// Parses a comma-separated list of `field=value` overrides on top of [DdrTiming::DDR3],
+    /// or `instant` for [DdrTiming::INSTANT]
+    pub fn parse(s: &str) -> Result<Self, String> {
+        if s == "instant" {
+            return Ok(Self::INSTANT);
+        }
+
+        let mut timing = Self::DDR3;
+        for kv in s.split(',').filter(|kv| !kv.is_empty()) {
+            let Some((key, value)) = kv.split_once('=') else {
+                return Err(format!("expected field=value in DDR timing, got {kv:?}"));
+            };
+            let value = value
+                .parse()
+                .map_err(|e| format!("invalid value for DDR timing {key}: {e}"))?;
+
+            *match key {
+                "read_latency" => &mut timing.read_latency,
+                "row_miss_penalty" => &mut timing.row_miss_penalty,
+                "burst_cycles" => &mut timing.burst_cycles,
+                "row_size" => &mut timing.row_size,
+                "max_outstanding" => &mut timing.max_outstanding,
+                "queue_depth" => &mut timing.queue_depth,
+                _ => return Err(format!("unknown DDR timing field {key:?}")),
+            } = value;
+        }
+
+        if timing.row_size == 0 || timing.max_outstanding == 0 || timing.queue_depth == 0 {
+            return Err("DDR row_size, max_outstanding and queue_depth must be at least 1".into());
+        }
+        Ok(timing)
+    }
+}
+
+impl Default for DdrTiming {
+    fn default() -> Self {
+        Self::DDR3
+    }
 }
 
 impl L3Cache {
+    pub fn with_timing(mut self, timing: DdrTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    /// Starts with `window` rather than the checked-in `chunk.bin`
+    pub fn with_window(mut self, window: Window) -> Self {
+        self.ddr_ram = window;
+        self
+    }
+
+    /// The voxels in the DDR, for the ray debugger. Not part of the design
+    pub fn window(&self) -> &Window {
+        &self.ddr_ram
+    }
+
     pub fn rising_clk_edge(&mut self) {
+        // The window isn't reset: it's the BRAM's contents, which the host only sends once
         if self.reset {
             self.voxel_out = Block::Air;
+            self.addr_out = Vec3i::default();
             self.valid_out = false;
+            self.ready_out = true;
+
+            self.queue.clear();
+            self.in_flight.clear();
+            self.open_row = None;
+            self.bus_free = 0;
+            self.cycle = 0;
+            self.counters = Default::default();
+            self.window_min_out = self.ddr_ram.min();
             return;
         }
 
-        if !self.read_enable_in {
-            self.valid_out = false;
-            return;
+        self.cycle += 1;
+
+        match self.window_in {
+            WindowUpdate::None => (),
+            WindowUpdate::Shift(shift) => {
+                self.ddr_ram.shift(shift);
+                self.counters.window_shifts += 1;
+            },
+            WindowUpdate::Write(offset, voxel) => {
+                self.ddr_ram.write(offset, voxel);
+                self.counters.window_writes += 1;
+            },
+        }
+
+        // Accept a new read
+        if self.read_enable_in {
+            assert!(self.queue.len() < self.timing.queue_depth, "L3 read while not ready");
+            self.queue.push_back(self.addr_in);
+            self.counters.reads += 1;
+        }
+
+        // Memory controller issues (at most) one read per cycle
+        if self.in_flight.len() < self.timing.max_outstanding {
+            if let Some(addr) = self.queue.pop_front() {
+                let row = self.ddr_ram
+                    .address(addr)
+                    .map(|a| a / self.timing.row_size);
+                let latency = match row == self.open_row {
+                    true => self.timing.read_latency,
+                    false => self.timing.read_latency + self.timing.row_miss_penalty,
+                };
+                self.counters.row_misses += usize::from(row != self.open_row);
+                self.open_row = row;
+
+                // The data bus only carries one burst at a time
+                let done = (self.cycle + latency).max(self.bus_free);
+                self.bus_free = done + self.timing.burst_cycles;
+
+                self.in_flight.push_back((addr, done));
+            }
+        }
+
+        // Data comes back in the order reads were issued
+        self.valid_out = false;
+        if let Some(&(addr, done)) = self.in_flight.front() {
+            if done <= self.cycle {
+                self.in_flight.pop_front();
+
+                self.voxel_out = self.ddr_ram.query(addr).unwrap_or_default();
+                self.addr_out = addr;
+                self.valid_out = true;
+            }
+        }
+
+        self.ready_out = self.queue.len() < self.timing.queue_depth;
+        self.window_min_out = self.ddr_ram.min();
+        self.counters.busy_cycles += usize::from(!self.queue.is_empty() || !self.in_flight.is_empty());
+    }
+}
+
+impl Trace for L3Cache {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("l3", |t| {
+            t.bit("reset", self.reset);
+            t.vec3i("addr_in", self.addr_in);
+            t.bit("read_enable_in", self.read_enable_in);
+            t.bit("ready_out", self.ready_out);
+            t.block("voxel_out", self.voxel_out);
+            t.vec3i("addr_out", self.addr_out);
+            t.bit("valid_out", self.valid_out);
+            t.bits("control_input", 4, match self.window_in {
+                WindowUpdate::Shift(shift) => shift.control().into(),
+                _ => 0,
+            });
+            t.bit("write_enable", matches!(self.window_in, WindowUpdate::Write(..)));
+            t.vec3i("window_min_out", self.window_min_out);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clocks a freshly reset L3 with DDR3 timing, issuing each of `reads` on its cycle
+    /// (the first one after reset is 1), and returns the cycle each read's data came back on
+    fn run(reads: &[(usize, Vec3i)]) -> Vec<(Vec3i, usize)> {
+        let mut l3 = L3Cache::default().with_timing(DdrTiming::DDR3);
+        l3.reset = true;
+        l3.rising_clk_edge();
+        l3.reset = false;
+
+        let mut done = vec![];
+        for cycle in 1..200 {
+            let read = reads.iter().find(|&&(c, _)| c == cycle);
+            l3.read_enable_in = read.is_some();
+            l3.addr_in = read.map_or(Vec3i::default(), |&(_, addr)| addr);
+            l3.rising_clk_edge();
+
+            if l3.valid_out {
+                done.push((l3.addr_out, cycle));
+            }
         }
+        done
+    }
+
+    /// Voxels in the first row of the DDR, and one in another row
+    fn voxels() -> (Vec3i, Vec3i, Vec3i) {
+        let min = Window::default().min();
+        let far = Window::SIZE as i16 - 1;
+
+        (min, min + Vec3i::new(1, 0, 0), min + Vec3i::new(0, 0, far))
+    }
+
+    #[test]
+    fn row_miss_then_hit() {
+        let DdrTiming { read_latency, row_miss_penalty, .. } = DdrTiming::DDR3;
+        let (a, b, _) = voxels();
+
+        // Nothing's open after reset, then the second read goes to the row the first opened
+        assert_eq!(run(&[(1, a), (50, b)]), [(a, 1 + read_latency + row_miss_penalty), (b, 50 + read_latency)]);
+    }
+
+    #[test]
+    fn row_miss_after_another_row() {
+        let DdrTiming { read_latency, row_miss_penalty, .. } = DdrTiming::DDR3;
+        let (a, _, c) = voxels();
 
-        // TODO: simulate DDR3 taking forever
-        self.voxel_out = self.ddr_ram.query(self.addr_in).unwrap_or_default();
-        self.valid_out = true;
+        assert_eq!(run(&[(1, a), (50, c)]), [(a, 1 + read_latency + row_miss_penalty), (c, 50 + read_latency + row_miss_penalty)]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn back_to_back() {
+        let DdrTiming { read_latency, row_miss_penalty, burst_cycles, .. } = DdrTiming::DDR3;
+        let (a, b, _) = voxels();
+
+        // The second read hits the open row, but has to wait for the first one's burst
+        let first = 1 + read_latency + row_miss_penalty;
+        assert_eq!(run(&[(1, a), (2, b)]), [(a, first), (b, first + burst_cycles)]);
+
+        // Without the row miss, the second read's latency is what it waits for
+        assert_eq!(run(&[(1, a), (2, b), (50, a), (51, b)])[2..], [(a, 50 + read_latency), (b, 51 + read_latency)]);
+    }
+
+    #[test]
+    fn instant() {
+        let mut l3 = L3Cache::default().with_timing(DdrTiming::INSTANT);
+        l3.reset = true;
+        l3.rising_clk_edge();
+        l3.reset = false;
+
+        let (a, ..) = voxels();
+        l3.addr_in = a;
+        l3.read_enable_in = true;
+        l3.rising_clk_edge();
+
+        assert!(l3.valid_out);
+        assert_eq!(l3.addr_out, a);
+        assert_eq!(Some(l3.voxel_out), Window::default().query(a));
+    }
+}
@@ -23,7 +23,19 @@ impl MockCache {
     /// Size of the cache in one dimension
     pub const SIZE: usize = 64;
 
+    /// Corner with the smallest coordinates, like [super::Window::min]
+    pub fn min(&self) -> Vec3i {
+        let min = -(Self::SIZE as i16) / 2;
+
+        Vec3i::new(min, min, min)
+    }
+
     pub fn query(&self, idx: Vec3i) -> Option<Block> {
+        Some(self.chunk[self.address(idx)?])
+    }
+
+    /// Where `idx` is stored in memory, or [None] if it's out of bounds
+    pub fn address(&self, idx: Vec3i) -> Option<usize> {
         const HALF_CHUNK_SIZE: i16 = (MockCache::SIZE as i16) / 2;
         
         // Re-center position in chunk
@@ -36,6 +48,6 @@ impl MockCache {
             return None;
         }
 
-        Some(self.chunk[Self::SIZE * (Self::SIZE * z + y) + x])
+        Some(Self::SIZE * (Self::SIZE * z + y) + x)
     }
 }
\ No newline at end of file
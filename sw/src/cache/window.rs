@@ -0,0 +1,132 @@
+use crate::{block::Block, math::Vec3i, world::World};
+
+use super::MockCache;
+
+/// Voxels of the [super::L3Cache]: a [Window::SIZE]³ box of the world, like `l3_cache.sv`'s
+/// BRAM. It slides along X and Z one voxel at a time, by moving a ring buffer's pointers
+/// rather than the data, and the voxels that come into view are written afterwards.
+/// Until they are, the slots they go in still hold the voxels that just went out of view.
+#[derive(Debug, Clone)]
+pub struct Window {
+    /// World coordinates of the voxel with the smallest coordinates
+    min: Vec3i,
+    /// Slot of [Window::min] along X and Z, `xpointer` and `zpointer` in the HDL
+    pointer: (usize, usize),
+    /// Slots, in `chunk.bin` order
+    blocks: Box<[Block]>,
+}
+
+/// Which way a [Window] slides, `control_input` in the HDL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shift {
+    PosX,
+    NegX,
+    PosZ,
+    NegZ,
+}
+
+impl Default for Window {
+    /// The checked-in `chunk.bin`, centred on the origin like [MockCache]
+    fn default() -> Self {
+        let cache = MockCache::default();
+
+        let mut window = Self {
+            min: Vec3i::new(-(Self::SIZE as i16) / 2, -(Self::SIZE as i16) / 2, -(Self::SIZE as i16) / 2),
+            pointer: (0, 0),
+            blocks: vec![Block::Air; Self::SIZE.pow(3)].into_boxed_slice(),
+        };
+        for (i, block) in window.blocks.iter_mut().enumerate() {
+            *block = cache.query(window.min + Self::position(i)).unwrap();
+        }
+        window
+    }
+}
+
+impl Window {
+    /// Size of the window in one dimension
+    pub const SIZE: usize = MockCache::SIZE;
+
+    /// The box of `world` around `center`, as if it had been loaded with the bitstream
+    pub fn from_world(world: &World, center: Vec3i) -> Self {
+        let half = (Self::SIZE / 2) as i16;
+
+        let mut window = Self {
+            min: center - Vec3i::new(half, half, half),
+            pointer: (0, 0),
+            blocks: vec![Block::Air; Self::SIZE.pow(3)].into_boxed_slice(),
+        };
+        for (i, block) in window.blocks.iter_mut().enumerate() {
+            let pos = window.min + Self::position(i);
+            *block = world.get([pos.x.into(), pos.y.into(), pos.z.into()]);
+        }
+        window
+    }
+
+    /// World coordinates of the voxel with the smallest coordinates
+    pub fn min(&self) -> Vec3i {
+        self.min
+    }
+
+    /// Voxel at `idx`, in world coordinates, or [None] if it's outside of the window
+    pub fn query(&self, idx: Vec3i) -> Option<Block> {
+        Some(self.blocks[self.address(idx)?])
+    }
+
+    /// Slot of `idx`, in world coordinates, or [None] if it's outside of the window
+    pub fn address(&self, idx: Vec3i) -> Option<usize> {
+        let offset = |x: i16, min: i16| usize::try_from(i32::from(x) - i32::from(min))
+            .ok()
+            .filter(|&x| x < Self::SIZE);
+
+        let x = offset(idx.x, self.min.x)?;
+        let y = offset(idx.y, self.min.y)?;
+        let z = offset(idx.z, self.min.z)?;
+
+        Some(self.slot(Vec3i::new(x as _, y as _, z as _)))
+    }
+
+    /// Slides the window by one voxel. The slots that come into view keep their old voxels
+    /// until they're written
+    pub fn shift(&mut self, shift: Shift) {
+        let (x, z) = &mut self.pointer;
+        match shift {
+            Shift::PosX => (self.min.x, *x) = (self.min.x + 1, (*x + 1) % Self::SIZE),
+            Shift::NegX => (self.min.x, *x) = (self.min.x - 1, (*x + Self::SIZE - 1) % Self::SIZE),
+            Shift::PosZ => (self.min.z, *z) = (self.min.z + 1, (*z + 1) % Self::SIZE),
+            Shift::NegZ => (self.min.z, *z) = (self.min.z - 1, (*z + Self::SIZE - 1) % Self::SIZE),
+        }
+    }
+
+    /// Writes the voxel at `offset` from [Window::min], each in `0..SIZE`, like `xwrite`,
+    /// `ywrite` and `zwrite` in the HDL
+    pub fn write(&mut self, offset: Vec3i, block: Block) {
+        let slot = self.slot(offset);
+        self.blocks[slot] = block;
+    }
+
+    /// Slot of the voxel at `offset` from [Window::min]
+    fn slot(&self, offset: Vec3i) -> usize {
+        let x = (self.pointer.0 + offset.x as usize) % Self::SIZE;
+        let z = (self.pointer.1 + offset.z as usize) % Self::SIZE;
+
+        Self::SIZE * (Self::SIZE * z + offset.y as usize) + x
+    }
+
+    /// Offset from [Window::min] of the `i`th slot, before any shift
+    fn position(i: usize) -> Vec3i {
+        let size = Self::SIZE;
+        Vec3i::new((i % size) as _, ((i / size) % size) as _, (i / (size * size)) as _)
+    }
+}
+
+impl Shift {
+    /// `control_input` of `l3_cache.sv`
+    pub fn control(self) -> u8 {
+        match self {
+            Self::PosX => 0b0001,
+            Self::NegX => 0b0010,
+            Self::PosZ => 0b0100,
+            Self::NegZ => 0b1000,
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use crate::{fixed, math::Vec3};
+
+/// Where the camera is and where it's looking, as the host would send it to the FPGA
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Pose {
+    /// Camera's position in world space
+    pub position: Vec3,
+    /// Rotation around the vertical axis, in radians
+    pub yaw: f32,
+    /// Rotation above or below the horizon, in radians
+    pub pitch: f32,
+    /// Rotation around the heading, in radians
+    pub roll: f32,
+}
+
+impl Pose {
+    /// Forward vector of the camera, normalized
+    pub fn heading(&self) -> Vec3 {
+        let [x, y, z] = self.heading_f32();
+
+        Vec3 {
+            x: fixed!(x),
+            y: fixed!(y),
+            z: fixed!(z),
+        }
+    }
+
+    /// [Pose::heading], before being converted to fixed point
+    pub fn heading_f32(&self) -> [f32; 3] {
+        let x = self.yaw.cos() * self.pitch.cos();
+        let y = self.pitch.sin();
+        let z = self.yaw.sin() * self.pitch.cos();
+        let m = (x*x + y*y + z*z).sqrt();
+
+        [x / m, y / m, z / m]
+    }
+
+    /// Up vector of the camera: the world's up, rotated around [Pose::heading] by the roll.
+    /// It isn't perpendicular to the heading when pitched, the
+    /// [crate::orchestrator::Orchestrator] only needs it to not be parallel.
+    pub fn up(&self) -> Vec3 {
+        let [x, y, z] = self.up_f32();
+
+        Vec3 {
+            x: fixed!(x),
+            y: fixed!(y),
+            z: fixed!(z),
+        }
+    }
+
+    /// [Pose::up], before being converted to fixed point
+    pub fn up_f32(&self) -> [f32; 3] {
+        let [x, y, z] = self.heading_f32();
+        let (sr, cr) = self.roll.sin_cos();
+
+        // Rodrigues' rotation of (0, 1, 0) around the heading
+        [
+            -z * sr + x * y * (1.0 - cr),
+            cr + y * y * (1.0 - cr),
+            x * sr + z * y * (1.0 - cr),
+        ]
+    }
+
+    /// Parses a pose written as `x y z yaw pitch roll`, where angles are in degrees.
+    /// `pitch` and `roll` can be omitted, in which case they're zero.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let nums = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|n| !n.is_empty())
+            .map(|n| n.parse::<f32>().map_err(|e| format!("invalid number {n:?} in pose {s:?}: {e}")))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let &[x, y, z, yaw, ref rest @ ..] = nums.as_slice() else {
+            return Err(format!("pose {s:?} should be \"x y z yaw [pitch [roll]]\""));
+        };
+        let (pitch, roll) = match rest {
+            [] => (0.0, 0.0),
+            [pitch] => (*pitch, 0.0),
+            [pitch, roll] => (*pitch, *roll),
+            _ => return Err(format!("pose {s:?} has too many values")),
+        };
+
+        Ok(Self {
+            position: Vec3::new(fixed!(x), fixed!(y), fixed!(z)),
+            yaw: yaw.to_radians(),
+            pitch: pitch.to_radians(),
+            roll: roll.to_radians(),
+        })
+    }
+
+    /// Reads a scene file: one pose per line (see [Pose::parse]). Blank lines and lines
+    /// starting with `#` are ignored.
+    pub fn parse_scene(s: &str) -> Result<Vec<Self>, String> {
+        s
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty() && !l.starts_with('#'))
+            .map(Self::parse)
+            .collect()
+    }
+}
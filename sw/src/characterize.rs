@@ -0,0 +1,283 @@
+//! Characterization of the hardware's [InvSqrt] and [RecipLte1] approximations: evaluates
+//! them on every input of their domain and compares them to `f64`, for LUT sizes and
+//! numbers of Newton steps other than the hardware's too. Can also write the LUTs as
+//! `.mem` files, for `$readmemh` in the HDL.
+
+use std::{fmt::Write as _, fs, panic::{self, AssertUnwindSafe}, path::{Path, PathBuf}};
+
+use crate::math::{approx::{InvSqrt, RecipLte1}, Fixed};
+
+pub const USAGE: &str = "\
+usage: fpgacraft characterize [options]
+
+Evaluates inv_sqrt on every positive number, and recip_lte1 on every nonzero number in
+[-1, 1], and compares them to the exact result. Errors are reported in absolute value,
+relative to the exact result, and in ULPs (multiples of the smallest fixed increment,
+1/256). Every combination of the LUT sizes and numbers of Newton steps below is reported.
+
+options:
+    --function inv_sqrt|recip|both  Which approximations (default: both)
+    --inv-sqrt-bits N,...           Bits after the leading one that index the LUT of
+                                    inv_sqrt, on top of the leading zeros (default: 0)
+    --recip-bits N,...              Fractional bits that index the LUT of recip_lte1, for
+                                    2^N entries (default: 6)
+    --iterations N,...              Newton steps after the LUT (default: 1)
+    --worst N                       Number of worst cases to list (default: 10)
+    --mem DIR                       Write the LUTs to DIR, as hex for $readmemh";
+
+/// Upper bounds (inclusive) of the buckets of the ULP histogram
+const BUCKETS: &[u64] = &[0, 1, 2, 3, 7, 15, 31, 63, 127, 255, u64::MAX];
+
+#[derive(Debug)]
+struct Options {
+    inv_sqrt: Vec<InvSqrt>,
+    recip: Vec<RecipLte1>,
+    worst: usize,
+    mem: Option<PathBuf>,
+}
+
+/// Result of one input
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    input: Fixed,
+    exact: f64,
+    /// [None] if the approximation overflowed
+    approx: Option<Fixed>,
+}
+
+impl Sample {
+    fn abs_error(&self) -> Option<f64> {
+        self.approx.map(|approx| (f64::from(approx.to_f32()) - self.exact).abs())
+    }
+
+    fn rel_error(&self) -> Option<f64> {
+        self.abs_error().map(|e| e / self.exact.abs())
+    }
+
+    fn ulps(&self) -> Option<u64> {
+        self.abs_error().map(|e| (e * (1 << Fixed::D) as f64).round() as u64)
+    }
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut function = "both";
+        let mut inv_sqrt_bits = vec![InvSqrt::HARDWARE.mantissa_bits];
+        let mut recip_bits = vec![RecipLte1::HARDWARE.lut_bits];
+        let mut iterations = vec![1];
+        let mut worst = 10;
+        let mut mem = None;
+
+        let list = |s: &str, what: &str| s
+            .split(',')
+            .map(|n| n.trim().parse::<u32>().map_err(|e| format!("invalid {what} {n:?}: {e}")))
+            .collect::<Result<Vec<_>, _>>();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args
+                .next()
+                .ok_or_else(|| format!("missing value for {arg}"));
+
+            match arg.as_str() {
+                "--function" => function = match value()?.as_str() {
+                    "inv_sqrt" => "inv_sqrt",
+                    "recip" => "recip",
+                    "both" => "both",
+                    f => return Err(format!("unknown function {f:?}")),
+                },
+                "--inv-sqrt-bits" => inv_sqrt_bits = list(value()?, "number of bits")?,
+                "--recip-bits" => recip_bits = list(value()?, "number of bits")?,
+                "--iterations" => iterations = list(value()?, "number of iterations")?,
+                "--worst" => worst = value()?.parse().map_err(|e| format!("invalid number of worst cases: {e}"))?,
+                "--mem" => mem = Some(value()?.into()),
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+
+        // More bits than that and the LUT doesn't fit in any BRAM anyway
+        if let Some(bits) = inv_sqrt_bits.iter().find(|&&bits| bits > 8) {
+            return Err(format!("inv_sqrt can't have {bits} mantissa bits, at most 8"));
+        }
+        if let Some(bits) = recip_bits.iter().find(|&&bits| bits as usize > Fixed::D) {
+            return Err(format!("recip_lte1 can't be indexed by {bits} bits, there are only {} fractional bits", Fixed::D));
+        }
+
+        let inv_sqrt = match function {
+            "recip" => vec![],
+            _ => inv_sqrt_bits
+                .iter()
+                .flat_map(|&mantissa_bits| iterations.iter().map(move |&iterations| InvSqrt { mantissa_bits, iterations }))
+                .collect(),
+        };
+        let recip = match function {
+            "inv_sqrt" => vec![],
+            _ => recip_bits
+                .iter()
+                .flat_map(|&lut_bits| iterations.iter().map(move |&iterations| RecipLte1 { lut_bits, iterations }))
+                .collect(),
+        };
+
+        Ok(Self { inv_sqrt, recip, worst, mem })
+    }
+}
+
+/// Entry point of `fpgacraft characterize`
+pub fn main(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let opts = Options::parse(args)?;
+    if let Some(dir) = &opts.mem {
+        fs::create_dir_all(dir).map_err(|e| format!("couldn't create {}: {e}", dir.display()))?;
+    }
+
+    // Overflows are reported with the rest, rather than printed
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| ()));
+
+    for inv_sqrt in &opts.inv_sqrt {
+        let samples = (1..=Fixed::MAX.to_raw())
+            .map(Fixed::from_raw)
+            .map(|input| Sample {
+                input,
+                exact: 1.0 / f64::from(input.to_f32()).sqrt(),
+                approx: panic::catch_unwind(AssertUnwindSafe(|| inv_sqrt.eval(input))).ok(),
+            })
+            .collect::<Vec<_>>();
+
+        let title = format!(
+            "inv_sqrt: {} mantissa bits ({} entries), {} Newton step(s)",
+            inv_sqrt.mantissa_bits, inv_sqrt.lut_size::<{Fixed::B as u32}>(), inv_sqrt.iterations,
+        );
+        print!("{}", report(&title, &samples, opts.worst));
+    }
+
+    let one = Fixed::from(1).to_raw();
+    for recip in &opts.recip {
+        let samples = (-one..=one)
+            .filter(|&raw| raw != 0)
+            .map(Fixed::from_raw)
+            .map(|input| Sample {
+                input,
+                exact: 1.0 / f64::from(input.to_f32()),
+                approx: panic::catch_unwind(AssertUnwindSafe(|| recip.eval(input))).ok(),
+            })
+            .collect::<Vec<_>>();
+
+        let title = format!(
+            "recip_lte1: {} bits ({} entries), {} Newton step(s)",
+            recip.lut_bits, recip.lut_size(), recip.iterations,
+        );
+        print!("{}", report(&title, &samples, opts.worst));
+    }
+
+    panic::set_hook(hook);
+
+    let Some(dir) = &opts.mem else {
+        return Ok(());
+    };
+
+    // The LUTs only depend on their size, not on the number of Newton steps
+    let mut inv_sqrt = opts.inv_sqrt.clone();
+    inv_sqrt.dedup_by_key(|inv_sqrt| inv_sqrt.mantissa_bits);
+    for inv_sqrt in inv_sqrt {
+        let path = dir.join(format!("inv_sqrt_{}.mem", inv_sqrt.mantissa_bits));
+        let header = format!(
+            "1/sqrt(x), addressed by {{leading zeros of x, {} bits after its leading one}}",
+            inv_sqrt.mantissa_bits,
+        );
+        write_mem(&path, &header, &inv_sqrt.lut())?;
+    }
+
+    let mut recip = opts.recip.clone();
+    recip.dedup_by_key(|recip| recip.lut_bits);
+    for recip in recip {
+        // `fixed_recip_lte1` stores 2*LUT and LUT^2, so that its Newton step is a single
+        // multiplication. LUT^2 saturates, as the entries for the smallest inputs overflow
+        let lut = recip.lut::<{Fixed::B as u32}, {Fixed::D as u32}>();
+        let double = lut
+            .iter()
+            .map(|&x| Fixed::from_raw(x.to_raw() << 1))
+            .collect::<Vec<_>>();
+        let square = lut
+            .iter()
+            .map(|&x| (i64::from(x.to_raw()).pow(2) >> Fixed::D).min(Fixed::MAX.to_raw().into()))
+            .map(|x| Fixed::from_raw(x as i32))
+            .collect::<Vec<_>>();
+
+        let bits = recip.lut_bits;
+        let path = dir.join(format!("recip_dbl_{bits}.mem"));
+        write_mem(&path, &format!("2/x, addressed by the {bits} top fractional bits of |x|"), &double)?;
+
+        let path = dir.join(format!("recip_sqr_{bits}.mem"));
+        write_mem(&path, &format!("1/x^2 (saturating), addressed by the {bits} top fractional bits of |x|"), &square)?;
+    }
+
+    Ok(())
+}
+
+/// Errors of `samples`, with a histogram of their ULPs and the `worst` largest ones
+fn report(title: &str, samples: &[Sample], worst: usize) -> String {
+    let mut s = format!("{title}\n");
+
+    let valid = samples.iter().filter(|s| s.approx.is_some()).collect::<Vec<_>>();
+    let overflows = samples.len() - valid.len();
+    let max = |f: fn(&Sample) -> Option<f64>| valid.iter().filter_map(|s| f(s)).fold(0.0, f64::max);
+    let mean = |f: fn(&Sample) -> Option<f64>| valid.iter().filter_map(|s| f(s)).sum::<f64>() / valid.len().max(1) as f64;
+
+    writeln!(s, "    inputs          {}", samples.len()).unwrap();
+    writeln!(s, "    overflows       {overflows}").unwrap();
+    writeln!(s, "    abs error       max {:.6}, mean {:.6}", max(Sample::abs_error), mean(Sample::abs_error)).unwrap();
+    writeln!(s, "    rel error       max {:.4}%, mean {:.4}%", 100.0 * max(Sample::rel_error), 100.0 * mean(Sample::rel_error)).unwrap();
+
+    writeln!(s, "    ULPs            inputs").unwrap();
+    let mut low = 0;
+    for &high in BUCKETS {
+        let count = valid
+            .iter()
+            .filter_map(|s| s.ulps())
+            .filter(|ulps| (low..=high).contains(ulps))
+            .count();
+
+        let range = match (low, high) {
+            (low, u64::MAX) => format!("{low}+"),
+            (low, high) if low == high => format!("{low}"),
+            (low, high) => format!("{low}-{high}"),
+        };
+        if count > 0 {
+            let percent = 100.0 * count as f64 / valid.len() as f64;
+            writeln!(s, "    {range:<15} {count:<8} {percent:>6.2}%").unwrap();
+        }
+        low = high.saturating_add(1);
+    }
+
+    let mut sorted = valid;
+    sorted.sort_by(|a, b| b.abs_error().partial_cmp(&a.abs_error()).unwrap());
+    writeln!(s, "    worst           input (raw)             exact        approx (raw)      ULPs").unwrap();
+    for sample in sorted.iter().take(worst) {
+        let approx = sample.approx.unwrap();
+        writeln!(
+            s,
+            "                    {:<12} ({:<8}) {:<12.6} {:<10} ({:<6}) {}",
+            sample.input.to_f32(), sample.input.to_raw(), sample.exact, approx.to_f32(), approx.to_raw(), sample.ulps().unwrap(),
+        ).unwrap();
+    }
+
+    s + "\n"
+}
+
+/// Writes `values` as hex to `path`, one per line, for `$readmemh`
+fn write_mem(path: &Path, header: &str, values: &[Fixed]) -> Result<(), String> {
+    let digits = Fixed::B.div_ceil(4);
+    let mask = (1u32 << Fixed::B) - 1;
+
+    let mut mem = format!("// {header}\n// Generated by `fpgacraft characterize`, {} entries of {} bits\n", values.len(), Fixed::B);
+    for value in values {
+        writeln!(mem, "{:0digits$x}", value.to_raw() as u32 & mask).unwrap();
+    }
+
+    fs::write(path, mem).map_err(|e| format!("couldn't write {}: {e}", path.display()))
+}
@@ -0,0 +1,99 @@
+//! Parameters of the design that can be changed without recompiling, e.g. to compare
+//! variants against each other. In Verilog, these would be module parameters.
+
+use crate::{cache::{DdrTiming, Organization, Replacement, Window}, display::{Buffering, VideoTiming}, texture::TextureAtlas, orchestrator::Resolution, top_level::{NUM_L2_ENTRIES, NUM_VTU}, vtu::RenderDistance};
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Number of VTUs tracing rays in parallel, each with its own port on the L2 cache
+    pub vtus: usize,
+    /// Number of entries in the L2 cache
+    pub l2_entries: usize,
+    /// Size of the frames
+    pub resolution: Resolution,
+    /// Timing of the DDR memory behind the L3 cache
+    pub ddr: DdrTiming,
+    /// How the L2 cache's entries are grouped into sets
+    pub l2_organization: Organization,
+    /// Which L2 entry gets evicted upon a cache miss
+    pub l2_replacement: Replacement,
+    /// Number of entries in each VTU's private L1 cache, 0 for no L1 at all
+    pub l1_entries: usize,
+    /// Vertical field of view of the camera, in degrees
+    pub fov: f32,
+    /// Textures of the blocks, or [None] for a flat colour per block
+    pub textures: Option<TextureAtlas>,
+    /// Cycles that a VTU may spend on a shadow ray before giving up and considering the
+    /// pixel lit, 0 for no shadows at all
+    pub shadow_cycles: usize,
+    /// How far rays go before giving up and showing the sky
+    pub render_distance: RenderDistance,
+    /// What the L3 cache holds after configuring the FPGA, i.e. `chunk.mem`
+    pub window: Window,
+    /// How the framebuffers are scanned out to HDMI, or [None] to leave the display out
+    pub display: Option<Buffering>,
+    /// Timing of the HDMI output, with [Config::display]
+    pub video: VideoTiming,
+    /// Threads that clock the VTUs. Not part of the design: any number gives the same
+    /// results, see [crate::parallel]
+    pub threads: usize,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            vtus: NUM_VTU,
+            l2_entries: NUM_L2_ENTRIES,
+            resolution: Default::default(),
+            ddr: Default::default(),
+            l2_organization: Default::default(),
+            l2_replacement: Default::default(),
+            l1_entries: Default::default(),
+            fov: 90.0,
+            textures: None,
+            shadow_cycles: 0,
+            render_distance: Default::default(),
+            window: Default::default(),
+            display: None,
+            video: Default::default(),
+            threads: 1,
+        }
+    }
+}
+
+impl Config {
+    /// Checks that the parameters make sense together
+    pub fn validate(&self) -> Result<(), String> {
+        let ways = self.l2_organization.ways(self.l2_entries);
+
+        if self.vtus == 0 {
+            return Err("there must be at least one VTU".into());
+        }
+        if self.threads == 0 {
+            return Err("the simulation needs at least one thread".into());
+        }
+        if self.l2_entries == 0 {
+            return Err("the L2 cache needs at least one entry".into());
+        }
+        if ways == 0 || !self.l2_entries.is_multiple_of(ways) {
+            return Err(format!("{ways} ways don't evenly divide {} L2 entries", self.l2_entries));
+        }
+        if self.l2_replacement == Replacement::PseudoLru && !ways.is_power_of_two() {
+            return Err(format!("pseudo-LRU needs a power-of-two number of ways, got {ways}"));
+        }
+        if self.l2_replacement == Replacement::PseudoLru && ways > 64 {
+            return Err(format!("pseudo-LRU supports at most 64 ways, got {ways}"));
+        }
+        let Resolution { width, height } = self.resolution;
+        if !(1..=Resolution::MAX).contains(&width) || !(1..=Resolution::MAX).contains(&height) {
+            return Err(format!("invalid resolution {}, width and height go from 1 to {}", self.resolution, Resolution::MAX));
+        }
+        if self.display.is_some() && (self.video.active_h_pixels < width || self.video.active_lines < height) {
+            return Err(format!("the active area of the video timing must fit a {} frame", self.resolution));
+        }
+        if !(self.fov > 0.0 && self.fov < 180.0) {
+            return Err(format!("field of view must be between 0 and 180 degrees, got {}", self.fov));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,593 @@
+//! Stimulus/response traces of a single module, so that the sw model and the HDL can be
+//! checked against each other cycle by cycle. A trace recorded here can be replayed on the
+//! HDL by the cocotb tests in `hw/src/sim` (see `cosim.py`), and the other way around.
+//!
+//! A trace is a CSV file. The header names every input (`in.` prefix) and output (`out.`
+//! prefix) of the module, then every row is one clock cycle: the inputs driven before the
+//! rising edge, and the outputs right after it. Values are signed integers: fixed-point
+//! numbers are their raw bits, blocks are their id, and 1-bit signals are 0 or 1.
+//!
+//! Each module is simulated on its own by a [Harness], which stands in for everything the
+//! module is connected to. E.g. the VTU's L2 port is just more inputs and outputs.
+
+use std::{cell::RefCell, collections::VecDeque, fmt::Write as _, fs, rc::Rc};
+
+use crate::{block::Block, cache::{L2Cache, L3Cache, MockCache}, fixed, math::{Fixed, Vec3, Vec3i}, top_level::NUM_L2_ENTRIES, vtu::VoxelTraversalUnit};
+
+pub const USAGE: &str = "\
+usage: fpgacraft cosim record vtu|l2 FILE [options]
+       fpgacraft cosim replay vtu|l2 FILE [options]
+
+Records the stimulus and response of a single module to FILE, or replays FILE and checks
+that the module responds the same way, cycle by cycle.
+
+options:
+    --count N       Rays (vtu) or reads per port (l2) to record (default: 64)
+    --latency N     Cycles the stand-in memory takes to answer a read (default: 2)
+    --seed N        Seed of the random rays/reads (default: 1)
+    --ports N       Number of L2 ports (default: 4)";
+
+/// Inputs and outputs of a module over a number of cycles
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Recording {
+    /// Names of the inputs, without the `in.` prefix
+    pub inputs: Vec<String>,
+    /// Names of the outputs, without the `out.` prefix
+    pub outputs: Vec<String>,
+    /// Value of every input and output, per cycle
+    pub cycles: Vec<(Vec<i64>, Vec<i64>)>,
+}
+
+impl Recording {
+    pub fn parse(csv: &str) -> Result<Self, String> {
+        let mut lines = csv
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty());
+
+        let Some((_, header)) = lines.next() else {
+            return Err("empty trace".into());
+        };
+
+        let mut recording = Self::default();
+        for column in header.split(',').map(str::trim) {
+            if let Some(name) = column.strip_prefix("in.") {
+                recording.inputs.push(name.into());
+            } else if let Some(name) = column.strip_prefix("out.") {
+                recording.outputs.push(name.into());
+            } else {
+                return Err(format!("column {column:?} is neither an input nor an output"));
+            }
+        }
+
+        for (i, line) in lines {
+            let values = line
+                .split(',')
+                .map(|v| v.trim().parse::<i64>())
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("line {}: {e}", i + 1))?;
+            if values.len() != recording.inputs.len() + recording.outputs.len() {
+                return Err(format!("line {}: expected {} values, got {}", i + 1, recording.inputs.len() + recording.outputs.len(), values.len()));
+            }
+
+            let (inputs, outputs) = values.split_at(recording.inputs.len());
+            recording.cycles.push((inputs.to_vec(), outputs.to_vec()));
+        }
+
+        Ok(recording)
+    }
+
+    pub fn to_csv(&self) -> String {
+        let header = self.inputs
+            .iter()
+            .map(|name| format!("in.{name}"))
+            .chain(self.outputs.iter().map(|name| format!("out.{name}")));
+
+        let mut csv = header.collect::<Vec<_>>().join(",");
+        csv.push('\n');
+
+        for (inputs, outputs) in &self.cycles {
+            let values = inputs.iter().chain(outputs).map(i64::to_string);
+            writeln!(csv, "{}", values.collect::<Vec<_>>().join(",")).unwrap();
+        }
+        csv
+    }
+}
+
+/// A module on its own, with everything it's connected to driven by the inputs
+pub trait Harness {
+    /// Names of every input, in the order [Harness::step] takes them
+    fn inputs(&self) -> Vec<String>;
+    /// Names of every output, in the order [Harness::step] returns them
+    fn outputs(&self) -> Vec<String>;
+    /// Drives the inputs, clocks the module once, and returns its outputs
+    fn step(&mut self, inputs: &[i64]) -> Vec<i64>;
+}
+
+/// A [VoxelTraversalUnit] on its own. Its L2 port (`l2.*`) is part of the inputs/outputs,
+/// i.e. the trace plays the part of the memory.
+#[derive(Debug, Default)]
+pub struct VtuHarness {
+    vtu: VoxelTraversalUnit,
+}
+
+impl Harness for VtuHarness {
+    fn inputs(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        names.push("reset".into());
+        names.extend(vec3_names("ray_origin_in"));
+        names.extend(vec3_names("ray_direction_in"));
+        names.push("ray_init_in".into());
+        names.push("l2.voxel_out".into());
+        names.push("l2.valid_out".into());
+        names
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        names.push("voxel_out".into());
+        names.extend(vec3_names("normal_out"));
+        names.push("valid_out".into());
+        names.extend(vec3_names("l2.addr_in"));
+        names.push("l2.read_enable_in".into());
+        names
+    }
+
+    fn step(&mut self, inputs: &[i64]) -> Vec<i64> {
+        let mut inputs = Values(inputs.iter());
+
+        self.vtu.reset = inputs.bit();
+        self.vtu.ray_origin_in = inputs.vec3();
+        self.vtu.ray_direction_in = inputs.vec3();
+        self.vtu.ray_init_in = inputs.bit();
+        self.vtu.l2.voxel_in = inputs.block();
+        self.vtu.l2.valid_in = inputs.bit();
+        // `vtu.sv` has no window input: its memory is `chunk.bin`, where [MockCache] has it
+        self.vtu.window_min_in = MockCache::default().min();
+
+        self.vtu.rising_clk_edge();
+
+        let mut outputs = Vec::new();
+        outputs.push(self.vtu.voxel_out as i64);
+        push_vec3(&mut outputs, self.vtu.normal_out);
+        outputs.push(self.vtu.valid_out.into());
+        push_vec3i(&mut outputs, self.vtu.l2.addr_out);
+        outputs.push(self.vtu.l2.read_enable_out.into());
+        outputs
+    }
+}
+
+/// An [L2Cache] on its own. The L3 side (`l3.*`) is part of the inputs/outputs, i.e. the
+/// trace plays the part of the L3.
+#[derive(Debug)]
+pub struct L2Harness {
+    /// Number of ports of the L2 cache
+    ports: usize,
+    l2: L2Cache,
+    /// Only used for its signals, never clocked
+    l3: Rc<RefCell<L3Cache>>,
+}
+
+impl L2Harness {
+    /// An L2 cache with `ports` ports
+    pub fn new(ports: usize) -> Self {
+        let l3 = Rc::new(RefCell::new(L3Cache::default()));
+        let mut l2 = L2Cache::default().with_size(ports, NUM_L2_ENTRIES);
+        l2.l3 = Rc::clone(&l3);
+
+        Self { ports, l2, l3 }
+    }
+}
+
+impl Harness for L2Harness {
+    fn inputs(&self) -> Vec<String> {
+        let mut names = vec!["reset".to_string()];
+        for port in 0..self.ports {
+            names.extend(vec3_names(&format!("port{port}.addr_in")));
+            names.push(format!("port{port}.read_enable_in"));
+        }
+        names.push("l3.ready_out".into());
+        names.push("l3.voxel_out".into());
+        names.extend(vec3_names("l3.addr_out"));
+        names.push("l3.valid_out".into());
+        names
+    }
+
+    fn outputs(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        for port in 0..self.ports {
+            names.push(format!("port{port}.voxel_out"));
+            names.push(format!("port{port}.valid_out"));
+        }
+        names.extend(vec3_names("l3.addr_in"));
+        names.push("l3.read_enable_in".into());
+        names
+    }
+
+    fn step(&mut self, inputs: &[i64]) -> Vec<i64> {
+        let mut inputs = Values(inputs.iter());
+
+        self.l2.reset = inputs.bit();
+        for port in 0..self.ports {
+            self.l2.addr_in[port] = inputs.vec3i();
+            self.l2.read_enable_in[port] = inputs.bit();
+        }
+        {
+            let mut l3 = self.l3.borrow_mut();
+            l3.ready_out = inputs.bit();
+            l3.voxel_out = inputs.block();
+            l3.addr_out = inputs.vec3i();
+            l3.valid_out = inputs.bit();
+        }
+
+        self.l2.rising_clk_edge();
+
+        let l3 = self.l3.borrow();
+        let mut outputs = Vec::new();
+        for port in 0..self.ports {
+            outputs.push(self.l2.voxel_out[port] as i64);
+            outputs.push(self.l2.valid_out[port].into());
+        }
+        push_vec3i(&mut outputs, l3.addr_in);
+        outputs.push(l3.read_enable_in.into());
+        outputs
+    }
+}
+
+/// Replays the inputs of `recording` on `harness`, returning a description of every cycle
+/// where the outputs differ (up to a handful of them)
+pub fn replay(harness: &mut impl Harness, recording: &Recording) -> Result<(), String> {
+    const MAX_REPORTED: usize = 10;
+
+    if recording.inputs != harness.inputs() || recording.outputs != harness.outputs() {
+        return Err(format!(
+            "trace doesn't match the module's interface\n  expected inputs: {:?}\n  expected outputs: {:?}",
+            harness.inputs(),
+            harness.outputs(),
+        ));
+    }
+
+    let mut mismatches = Vec::new();
+    let mut total = 0;
+    for (cycle, (inputs, expected)) in recording.cycles.iter().enumerate() {
+        let actual = harness.step(inputs);
+
+        for (name, (e, a)) in recording.outputs.iter().zip(expected.iter().zip(&actual)) {
+            if e != a {
+                total += 1;
+                if mismatches.len() < MAX_REPORTED {
+                    mismatches.push(format!("cycle {cycle}: {name} is {a}, trace says {e}"));
+                }
+            }
+        }
+    }
+
+    match total {
+        0 => Ok(()),
+        _ => Err(format!("{total} outputs differ from the trace, first ones:\n  {}", mismatches.join("\n  "))),
+    }
+}
+
+/// Records `count` random rays cast by a [VtuHarness], answering its reads after `latency`
+/// cycles from [MockCache]
+pub fn record_vtu(count: usize, latency: usize, seed: u64) -> Recording {
+    let mut harness = VtuHarness::default();
+    let mut rng = XorShift(seed.max(1));
+    let memory = MockCache::default();
+
+    let mut recording = Recording {
+        inputs: harness.inputs(),
+        outputs: harness.outputs(),
+        cycles: Vec::new(),
+    };
+    let mut step = |inputs: Vec<i64>| {
+        let outputs = harness.step(&inputs);
+        recording.cycles.push((inputs, outputs.clone()));
+        outputs
+    };
+
+    // Reset, then every ray until the VTU is done with it
+    step(vtu_inputs(true, Vec3::default(), Vec3::FORWARD, false, None));
+    for _ in 0..count {
+        let origin = Vec3::new(rng.fixed(-24.0, 24.0), rng.fixed(-8.0, 24.0), rng.fixed(-24.0, 24.0));
+        let direction = Vec3::new(rng.fixed(-1.0, 1.0), rng.fixed(-1.0, 1.0), rng.fixed(-1.0, 1.0));
+
+        let mut outputs = step(vtu_inputs(false, origin, direction, true, None));
+
+        // Same as the mock memory of `test_vtu.py`: holds a read for `latency` cycles, then
+        // answers it for a single cycle
+        let mut pending: Option<(Vec3i, usize)> = None;
+        loop {
+            let valid = outputs[4] != 0;
+            let read_enable = outputs[8] != 0;
+            let addr = Vec3i::new(outputs[5] as _, outputs[6] as _, outputs[7] as _);
+            if valid {
+                break;
+            }
+
+            pending = match pending {
+                _ if !read_enable => None,
+                Some((a, cycles)) if a == addr => Some((a, cycles.saturating_sub(1))),
+                _ => Some((addr, latency)),
+            };
+
+            let answer = match pending {
+                Some((addr, 0)) => {
+                    pending = None;
+                    Some(memory.query(addr).unwrap_or_default())
+                },
+                _ => None,
+            };
+            outputs = step(vtu_inputs(false, origin, direction, false, answer));
+        }
+    }
+
+    recording
+}
+
+fn vtu_inputs(reset: bool, origin: Vec3, direction: Vec3, init: bool, answer: Option<Block>) -> Vec<i64> {
+    let mut inputs = vec![reset.into()];
+    push_vec3(&mut inputs, origin);
+    push_vec3(&mut inputs, direction);
+    inputs.push(init.into());
+    inputs.push(answer.unwrap_or_default() as i64);
+    inputs.push(answer.is_some().into());
+    inputs
+}
+
+/// Records `count` random reads per port of an [L2Harness], answering its misses after
+/// `latency` cycles from [MockCache]
+pub fn record_l2(ports: usize, count: usize, latency: usize, seed: u64) -> Recording {
+    /// How many misses the stand-in L3 can hold at once
+    const L3_QUEUE_DEPTH: usize = 4;
+
+    let mut harness = L2Harness::new(ports);
+    let mut rng = XorShift(seed.max(1));
+    let memory = MockCache::default();
+
+    let mut recording = Recording {
+        inputs: harness.inputs(),
+        outputs: harness.outputs(),
+        cycles: Vec::new(),
+    };
+
+    // Address each port is reading (if any), and how many reads it has left
+    let mut reads: Vec<Option<Vec3i>> = vec![None; ports];
+    let mut left = vec![count; ports];
+    // Misses the L3 is working on, and the cycle they're answered at
+    let mut l3_queue: VecDeque<(Vec3i, usize)> = VecDeque::new();
+
+    let mut outputs: Option<Vec<i64>> = None;
+    for cycle in 0.. {
+        // Ports are done once their read is answered, and start a new one at random
+        if let Some(outputs) = &outputs {
+            for port in 0..ports {
+                if outputs[2 * port + 1] != 0 {
+                    reads[port] = None;
+                }
+            }
+            if outputs[2 * ports + 3] != 0 {
+                let addr = Vec3i::new(outputs[2 * ports] as _, outputs[2 * ports + 1] as _, outputs[2 * ports + 2] as _);
+                l3_queue.push_back((addr, cycle + latency));
+            }
+        }
+        for port in 0..ports {
+            if reads[port].is_none() && left[port] > 0 && rng.next().is_multiple_of(4) {
+                // A small region, so that ports share voxels and hit each other's entries
+                reads[port] = Some(Vec3i::new(rng.range(-3, 3), rng.range(-3, 3), rng.range(-3, 3)));
+                left[port] -= 1;
+            }
+        }
+
+        if reads.iter().all(Option::is_none) && left.iter().all(|&n| n == 0) && l3_queue.is_empty() {
+            break;
+        }
+
+        // The L3 answers its oldest miss once it's ready
+        let answer = l3_queue
+            .front()
+            .filter(|&&(_, ready)| ready <= cycle)
+            .copied();
+        if answer.is_some() {
+            l3_queue.pop_front();
+        }
+
+        let mut inputs = vec![(cycle == 0).into()];
+        for read in &reads {
+            push_vec3i(&mut inputs, read.unwrap_or_default());
+            inputs.push(read.is_some().into());
+        }
+        inputs.push((l3_queue.len() < L3_QUEUE_DEPTH).into());
+        inputs.push(answer.map(|(addr, _)| memory.query(addr).unwrap_or_default()).unwrap_or_default() as i64);
+        push_vec3i(&mut inputs, answer.map(|(addr, _)| addr).unwrap_or_default());
+        inputs.push(answer.is_some().into());
+
+        let step = harness.step(&inputs);
+        recording.cycles.push((inputs, step.clone()));
+        outputs = Some(step);
+    }
+
+    recording
+}
+
+/// Entry point of `fpgacraft cosim`
+pub fn main(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let [command, module, path, options @ ..] = args else {
+        return Err("expected a command, a module and a file".into());
+    };
+
+    let mut count = 64;
+    let mut latency = 2;
+    let mut seed = 1;
+    let mut ports = 4;
+
+    let mut options = options.iter();
+    while let Some(arg) = options.next() {
+        let value = options
+            .next()
+            .ok_or_else(|| format!("missing value for {arg}"))?
+            .parse()
+            .map_err(|e| format!("invalid value for {arg}: {e}"))?;
+
+        match arg.as_str() {
+            "--count" => count = value,
+            "--latency" => latency = value,
+            "--seed" => seed = value as u64,
+            "--ports" => ports = value,
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+
+    if ports == 0 {
+        return Err("an L2 cache needs at least one port".into());
+    }
+
+    match command.as_str() {
+        "record" => {
+            let recording = match module.as_str() {
+                "vtu" => record_vtu(count, latency, seed),
+                "l2" => record_l2(ports, count, latency, seed),
+                _ => return Err(format!("unknown module {module:?}, expected vtu or l2")),
+            };
+
+            fs::write(path, recording.to_csv()).map_err(|e| format!("couldn't write {path}: {e}"))?;
+            println!("Recorded {} cycles to {path}", recording.cycles.len());
+        },
+        "replay" => {
+            let csv = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+            let recording = Recording::parse(&csv).map_err(|e| format!("{path}: {e}"))?;
+
+            match module.as_str() {
+                "vtu" => replay(&mut VtuHarness::default(), &recording)?,
+                "l2" => replay(&mut L2Harness::new(ports), &recording)?,
+                _ => return Err(format!("unknown module {module:?}, expected vtu or l2")),
+            }
+            println!("All {} cycles match {path}", recording.cycles.len());
+        },
+        _ => return Err(format!("unknown command {command:?}, expected record or replay")),
+    }
+
+    Ok(())
+}
+
+/// Reads the values of a trace in order
+struct Values<'a>(std::slice::Iter<'a, i64>);
+
+impl Values<'_> {
+    fn int(&mut self) -> i64 {
+        *self.0.next().expect("not enough inputs")
+    }
+
+    fn bit(&mut self) -> bool {
+        self.int() != 0
+    }
+
+    fn block(&mut self) -> Block {
+        Block::try_from(self.int() as u8).unwrap_or_default()
+    }
+
+    fn fixed(&mut self) -> Fixed {
+        Fixed::from_raw(self.int() as i32)
+    }
+
+    fn vec3(&mut self) -> Vec3 {
+        Vec3::new(self.fixed(), self.fixed(), self.fixed())
+    }
+
+    fn vec3i(&mut self) -> Vec3i {
+        Vec3i::new(self.int() as _, self.int() as _, self.int() as _)
+    }
+}
+
+fn vec3_names(name: &str) -> [String; 3] {
+    ["x", "y", "z"].map(|c| format!("{name}.{c}"))
+}
+
+fn push_vec3(values: &mut Vec<i64>, v: Vec3) {
+    values.extend([v.x, v.y, v.z].map(|c| c.to_raw() as i64));
+}
+
+fn push_vec3i(values: &mut Vec<i64>, v: Vec3i) {
+    values.extend([v.x, v.y, v.z].map(i64::from));
+}
+
+/// Tiny deterministic PRNG, so that recordings are reproducible
+#[derive(Debug)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Uniform in `lo..=hi`
+    fn range(&mut self, lo: i16, hi: i16) -> i16 {
+        lo + (self.next() % (hi - lo + 1) as u64) as i16
+    }
+
+    /// Uniform in `lo..hi`
+    fn fixed(&mut self, lo: f32, hi: f32) -> Fixed {
+        let t = (self.next() >> 40) as f32 / (1u64 << 24) as f32;
+        fixed!(lo + t * (hi - lo))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vtu_round_trip() {
+        let recording = record_vtu(64, 2, 1);
+        let parsed = Recording::parse(&recording.to_csv()).unwrap();
+
+        assert_eq!(parsed, recording);
+        assert_eq!(replay(&mut VtuHarness::default(), &parsed), Ok(()));
+    }
+
+    #[test]
+    fn vtu_rays_from_negative_origins() {
+        let recording = record_vtu(64, 2, 1);
+
+        // Every ray ends on what the instant ray cast finds, wherever it starts
+        let mut ray = None;
+        let mut negative = 0;
+        for (inputs, outputs) in &recording.cycles {
+            let mut values = Values(inputs.iter());
+            let (reset, origin, direction, init) = (values.bit(), values.vec3(), values.vec3(), values.bit());
+            if init && !reset {
+                negative += usize::from(origin.x < fixed!(0.0) || origin.y < fixed!(0.0) || origin.z < fixed!(0.0));
+                ray = Some((origin, direction));
+            }
+
+            if outputs[4] == 0 {
+                continue;
+            }
+            let Some((origin, direction)) = ray.take() else {
+                continue;
+            };
+
+            let mut vtu = VoxelTraversalUnit::default();
+            vtu.ray_origin_in = origin;
+            vtu.ray_direction_in = direction;
+            vtu.mock_cast();
+            assert_eq!(outputs[0], vtu.voxel_out as i64, "ray from {origin} along {direction}");
+        }
+        assert!(negative > 32, "only {negative} rays with a negative origin");
+    }
+
+    #[test]
+    fn l2_round_trip() {
+        let recording = record_l2(3, 16, 2, 1);
+
+        assert_eq!(replay(&mut L2Harness::new(3), &Recording::parse(&recording.to_csv()).unwrap()), Ok(()));
+    }
+}
@@ -0,0 +1,277 @@
+//! Performance counters. Every module counts its own events in a public `counters` field,
+//! which isn't part of the design, just a window into it. At the end of every frame, the
+//! [crate::orchestrator::Orchestrator] gathers them into [FrameCounters] and starts over.
+
+use std::fmt::Write;
+
+/// Counters of one [crate::vtu::VoxelTraversalUnit]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct VtuCounters {
+    /// Rays started
+    pub rays: usize,
+    /// Voxels stepped through
+    pub steps: usize,
+    /// Cycles spent waiting on memory
+    pub stall_cycles: usize,
+    /// Cycles spent done with a ray, waiting for the next one
+    pub idle_cycles: usize,
+    /// Shadow rays started, on top of [VtuCounters::rays]
+    pub shadow_rays: usize,
+    /// Shadow rays given up on because they ran out of cycles
+    pub shadow_timeouts: usize,
+    /// Rays that ended on a block
+    pub hits: usize,
+    /// Rays that went past the render distance without hitting anything
+    pub distance_exits: usize,
+    /// Rays that left the L3 cache's window without hitting anything
+    pub bounds_exits: usize,
+}
+
+/// Counters of a cache, or of one port of a cache
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CacheCounters {
+    /// Reads that were answered right away
+    pub hits: usize,
+    /// Reads that had to go to the next level. A read that misses only counts once, no
+    /// matter how long it waits
+    pub misses: usize,
+    /// Cycles spent waiting on the next level
+    pub stall_cycles: usize,
+}
+
+/// Counters of the [crate::cache::L2Cache]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct L2Counters {
+    /// Per port
+    pub ports: Vec<CacheCounters>,
+    /// Cycles where a miss couldn't be sent because the L3 wasn't ready
+    pub l3_busy_cycles: usize,
+    /// Number of tag comparators this L2 needs in hardware. Constant, but handy when
+    /// comparing designs
+    pub comparators: usize,
+}
+
+/// Counters of the [crate::cache::L3Cache]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct L3Counters {
+    /// Reads accepted
+    pub reads: usize,
+    /// Reads that had to open a new DDR row
+    pub row_misses: usize,
+    /// Cycles with at least one read queued or in flight
+    pub busy_cycles: usize,
+    /// Times the window of voxels slid by one
+    pub window_shifts: usize,
+    /// Voxels written into the window by the host
+    pub window_writes: usize,
+}
+
+/// Counters of the [crate::display::Display]. Refreshes don't line up with frames, so each
+/// frame gets the ones that ended while it was being drawn
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayCounters {
+    /// Refreshes of the monitor
+    pub refreshes: usize,
+    /// Refreshes that showed pixels of more than one frame
+    pub torn_refreshes: usize,
+    /// Times the front and back buffers were swapped
+    pub swaps: usize,
+    /// Cycles where a finished frame waited for vsync, and the orchestrator with it
+    pub swap_wait_cycles: usize,
+}
+
+/// Counters of the [crate::orchestrator::Orchestrator] itself
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct OrchestratorCounters {
+    /// Cycles from the first cycle of the frame until it's done, inclusively
+    pub cycles: usize,
+    /// Pixels written to the framebuffer
+    pub pixels: usize,
+    /// Cycles where no pixel was written to the framebuffer
+    pub idle_cycles: usize,
+    /// Texels read from the texture atlas
+    pub texture_reads: usize,
+    /// Size of the texture atlas in BRAM, in bits, 0 without textures. Constant, but handy
+    /// when comparing designs
+    pub texture_bits: usize,
+}
+
+/// Everything that was counted while rendering one frame
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct FrameCounters {
+    /// Index of the frame since reset
+    pub frame: usize,
+    pub orchestrator: OrchestratorCounters,
+    /// Per VTU
+    pub vtu: Vec<VtuCounters>,
+    /// Per VTU, empty if VTUs don't have an L1 cache
+    pub l1: Vec<CacheCounters>,
+    pub l2: L2Counters,
+    pub l3: L3Counters,
+    /// All zeroes without a display
+    pub display: DisplayCounters,
+}
+
+impl CacheCounters {
+    pub fn accesses(&self) -> usize {
+        self.hits + self.misses
+    }
+
+    /// Fraction of accesses that were hits, zero if there weren't any
+    pub fn hit_ratio(&self) -> f32 {
+        match self.accesses() {
+            0 => 0.0,
+            n => self.hits as f32 / n as f32,
+        }
+    }
+}
+
+impl std::ops::Add for CacheCounters {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self {
+            hits: self.hits + rhs.hits,
+            misses: self.misses + rhs.misses,
+            stall_cycles: self.stall_cycles + rhs.stall_cycles,
+        }
+    }
+}
+
+impl L2Counters {
+    pub fn new(ports: usize, comparators: usize) -> Self {
+        Self {
+            ports: vec![Default::default(); ports],
+            l3_busy_cycles: 0,
+            comparators,
+        }
+    }
+
+    /// Sum over every port
+    pub fn total(&self) -> CacheCounters {
+        self.ports
+            .iter()
+            .fold(CacheCounters::default(), |a, &b| a + b)
+    }
+}
+
+impl FrameCounters {
+    /// Sum over every VTU's L1 cache
+    pub fn l1_total(&self) -> CacheCounters {
+        self.l1
+            .iter()
+            .fold(CacheCounters::default(), |a, &b| a + b)
+    }
+
+    /// Every counter as a flat list of `(name, value)`, totals first. This is a row of
+    /// [FrameCounters::to_csv].
+    pub fn fields(&self) -> Vec<(String, String)> {
+        let mut fields = Vec::new();
+        let mut field = |name: String, value: String| fields.push((name, value));
+
+        let l1 = self.l1_total();
+        let l2 = self.l2.total();
+
+        field("frame".into(), self.frame.to_string());
+        field("cycles".into(), self.orchestrator.cycles.to_string());
+        field("pixels".into(), self.orchestrator.pixels.to_string());
+        field("orchestrator_idle_cycles".into(), self.orchestrator.idle_cycles.to_string());
+        field("texture_reads".into(), self.orchestrator.texture_reads.to_string());
+        field("texture_bits".into(), self.orchestrator.texture_bits.to_string());
+        field("l1_accesses".into(), l1.accesses().to_string());
+        field("l1_hit_ratio".into(), l1.hit_ratio().to_string());
+        field("l2_accesses".into(), l2.accesses().to_string());
+        field("l2_hit_ratio".into(), l2.hit_ratio().to_string());
+        field("l2_comparators".into(), self.l2.comparators.to_string());
+        field("l2_l3_busy_cycles".into(), self.l2.l3_busy_cycles.to_string());
+        field("l3_reads".into(), self.l3.reads.to_string());
+        field("l3_row_misses".into(), self.l3.row_misses.to_string());
+        field("l3_busy_cycles".into(), self.l3.busy_cycles.to_string());
+        field("l3_window_shifts".into(), self.l3.window_shifts.to_string());
+        field("l3_window_writes".into(), self.l3.window_writes.to_string());
+        field("display_refreshes".into(), self.display.refreshes.to_string());
+        field("display_torn_refreshes".into(), self.display.torn_refreshes.to_string());
+        field("display_swaps".into(), self.display.swaps.to_string());
+        field("display_swap_wait_cycles".into(), self.display.swap_wait_cycles.to_string());
+
+        for (i, vtu) in self.vtu.iter().enumerate() {
+            field(format!("vtu{i}_rays"), vtu.rays.to_string());
+            field(format!("vtu{i}_steps"), vtu.steps.to_string());
+            field(format!("vtu{i}_stall_cycles"), vtu.stall_cycles.to_string());
+            field(format!("vtu{i}_idle_cycles"), vtu.idle_cycles.to_string());
+            field(format!("vtu{i}_shadow_rays"), vtu.shadow_rays.to_string());
+            field(format!("vtu{i}_shadow_timeouts"), vtu.shadow_timeouts.to_string());
+            field(format!("vtu{i}_hits"), vtu.hits.to_string());
+            field(format!("vtu{i}_distance_exits"), vtu.distance_exits.to_string());
+            field(format!("vtu{i}_bounds_exits"), vtu.bounds_exits.to_string());
+        }
+        for (i, l1) in self.l1.iter().enumerate() {
+            field(format!("l1_{i}_hits"), l1.hits.to_string());
+            field(format!("l1_{i}_misses"), l1.misses.to_string());
+            field(format!("l1_{i}_stall_cycles"), l1.stall_cycles.to_string());
+        }
+        for (i, port) in self.l2.ports.iter().enumerate() {
+            field(format!("l2_port{i}_hits"), port.hits.to_string());
+            field(format!("l2_port{i}_misses"), port.misses.to_string());
+            field(format!("l2_port{i}_stall_cycles"), port.stall_cycles.to_string());
+        }
+
+        fields
+    }
+
+    /// One header line, then one line per frame
+    pub fn to_csv(frames: &[Self]) -> String {
+        let mut csv = String::new();
+
+        for (i, frame) in frames.iter().enumerate() {
+            let fields = frame.fields();
+
+            if i == 0 {
+                let names = fields.iter().map(|(name, _)| name.as_str());
+                writeln!(csv, "{}", names.collect::<Vec<_>>().join(",")).unwrap();
+            }
+            let values = fields.iter().map(|(_, value)| value.as_str());
+            writeln!(csv, "{}", values.collect::<Vec<_>>().join(",")).unwrap();
+        }
+        csv
+    }
+
+    /// An array with one object per frame
+    pub fn to_json(frames: &[Self]) -> String {
+        fn cache(c: &CacheCounters) -> String {
+            format!(r#"{{"hits":{},"misses":{},"stall_cycles":{}}}"#, c.hits, c.misses, c.stall_cycles)
+        }
+        fn list<T>(items: &[T], f: impl Fn(&T) -> String) -> String {
+            format!("[{}]", items.iter().map(f).collect::<Vec<_>>().join(","))
+        }
+
+        let frames = frames.iter().map(|f| {
+            let o = &f.orchestrator;
+            let vtu = list(&f.vtu, |v| format!(
+                concat!(
+                    r#"{{"rays":{},"steps":{},"stall_cycles":{},"idle_cycles":{},"shadow_rays":{},"shadow_timeouts":{},"#,
+                    r#""hits":{},"distance_exits":{},"bounds_exits":{}}}"#,
+                ),
+                v.rays, v.steps, v.stall_cycles, v.idle_cycles, v.shadow_rays, v.shadow_timeouts,
+                v.hits, v.distance_exits, v.bounds_exits,
+            ));
+
+            format!(
+                concat!(
+                    r#"{{"frame":{},"cycles":{},"pixels":{},"idle_cycles":{},"#,
+                    r#""texture_reads":{},"texture_bits":{},"vtu":{},"l1":{},"#,
+                    r#""l2":{{"comparators":{},"l3_busy_cycles":{},"ports":{}}},"#,
+                    r#""l3":{{"reads":{},"row_misses":{},"busy_cycles":{},"window_shifts":{},"window_writes":{}}},"#,
+                    r#""display":{{"refreshes":{},"torn_refreshes":{},"swaps":{},"swap_wait_cycles":{}}}}}"#,
+                ),
+                f.frame, o.cycles, o.pixels, o.idle_cycles,
+                o.texture_reads, o.texture_bits, vtu, list(&f.l1, cache),
+                f.l2.comparators, f.l2.l3_busy_cycles, list(&f.l2.ports, cache),
+                f.l3.reads, f.l3.row_misses, f.l3.busy_cycles, f.l3.window_shifts, f.l3.window_writes,
+                f.display.refreshes, f.display.torn_refreshes, f.display.swaps, f.display.swap_wait_cycles,
+            )
+        });
+
+        format!("[\n{}\n]\n", frames.collect::<Vec<_>>().join(",\n"))
+    }
+}
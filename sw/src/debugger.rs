@@ -0,0 +1,399 @@
+//! Ray debugger for the viewer: when a pixel looks wrong, re-casts its ray through a
+//! [VoxelTraversalUnit] of its own and records every voxel it visits on the way, with the
+//! VTU's registers and where each read was answered in the cache hierarchy.
+//!
+//! The VTU gets the same parameters as the viewer's and its own L1, L2 and L3 caches, the
+//! L3 starting with the window the viewer's holds. The caches start out empty, so whether
+//! a read hits only depends on the voxels this ray read before, not on the pixels before it.
+
+use std::{cell::RefCell, fmt, rc::Rc};
+
+use nannou::{color::{rgba, Rgba}, geom::{pt2, Point2}, Draw};
+
+use crate::{block::Block, cache::{L2Cache, L3Cache}, config::Config, math::{Fixed, Vec3, Vec3i}, orchestrator::{Orchestrator, Resolution}, vtu::{Axis, Traversal, VoxelTraversalUnit}};
+
+/// Cycles a ray gets before the debugger gives up on it
+const MAX_CYCLES: usize = 1_000_000;
+
+/// Everything a ray went through, from its dispatch until the VTU was done with it
+#[derive(Debug, Clone)]
+pub struct RayTrace {
+    /// Pixel the ray was cast for
+    pub pixel: usize,
+    /// Size of the frame the pixel is in
+    pub resolution: Resolution,
+    /// Where the ray begins, i.e. the camera's position
+    pub origin: Vec3,
+    /// Heading of the ray as dispatched to the VTU, not normalized
+    pub direction: Vec3,
+    /// Every voxel visited, in order: the primary ray's, then the shadow ray's, if any
+    pub steps: Vec<Step>,
+    /// Voxel hit, [Block::Air] for the sky
+    pub voxel: Block,
+    /// Surface normal of the voxel hit
+    pub normal: Vec3,
+    /// Whether the hit was in the shadow
+    pub shadowed: bool,
+    /// Cycles from the dispatch until the VTU was done
+    pub cycles: usize,
+    /// Whether the debugger gave up on the ray after [MAX_CYCLES]
+    pub timed_out: bool,
+}
+
+/// One voxel a ray went through
+#[derive(Debug, Clone)]
+pub struct Step {
+    /// The VTU's registers once it got into the voxel
+    pub traversal: Traversal,
+    /// What the L3 cache's window holds there, [None] outside of it
+    pub block: Option<Block>,
+    /// How the VTU's read of the voxel went in its L1 cache, if it has one
+    pub l1: Access,
+    pub l2: Access,
+    /// How the read went in the DDR behind the L3 cache: a hit if its row was already open
+    pub l3: Access,
+    /// Cycles the VTU spent on the voxel
+    pub cycles: usize,
+}
+
+/// How a read went at one level of the cache hierarchy
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    /// It never got there: the level above answered it, or the ray was done before
+    #[default]
+    None,
+    Hit,
+    Miss,
+}
+
+/// State of the viewer's debug mode
+#[derive(Debug, Default)]
+pub struct Debugger {
+    /// Whether a whole frame was rendered with the current camera inputs since the viewer
+    /// went into debug mode, so that the frame on screen is the one rays get cast from
+    pub settled: bool,
+    /// Last ray cast
+    pub trace: Option<RayTrace>,
+    /// First step shown in the panel
+    pub scroll: usize,
+}
+
+/// Counters that steps are told apart by, at some cycle
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+struct Snapshot {
+    l1: (usize, usize),
+    l2: (usize, usize),
+    l3: (usize, usize),
+}
+
+/// Casts the ray of `pixel` as `orchestrator` would dispatch it for a frame started with its
+/// current camera inputs, see the module's documentation
+pub fn cast(orchestrator: &Orchestrator, config: &Config, pixel: usize) -> RayTrace {
+    let (origin, direction) = orchestrator.pixel_ray(pixel);
+    let window = orchestrator.window();
+
+    let l3 = Rc::new(RefCell::new(L3Cache::default().with_timing(config.ddr).with_window(window.clone())));
+    let l2 = Rc::new(RefCell::new(L2Cache::default()
+        .with_size(1, config.l2_entries)
+        .with_policy(config.l2_organization, config.l2_replacement)
+    ));
+    let mut vtu = VoxelTraversalUnit::default()
+        .with_l1(config.l1_entries)
+        .with_shadows(config.shadow_cycles)
+        .with_render_distance(config.render_distance);
+    l2.borrow_mut().l3 = Rc::clone(&l3);
+
+    // Same order as the orchestrator
+    let clock = |vtu: &mut VoxelTraversalUnit, reset: bool| {
+        l2.borrow_mut().reset = reset;
+        l3.borrow_mut().reset = reset;
+        vtu.reset = reset;
+        vtu.sun_in = Orchestrator::SUN;
+        vtu.window_min_in = l3.borrow().window_min_out;
+
+        l2.borrow_mut().rising_clk_edge();
+        l3.borrow_mut().rising_clk_edge();
+        l2.borrow().drive(0, &mut vtu.l2);
+        vtu.rising_clk_edge();
+        l2.borrow_mut().latch(0, &vtu.l2);
+    };
+    let snapshot = |vtu: &mut VoxelTraversalUnit| {
+        let l1 = vtu.l1_counters_mut().map_or((0, 0), |c| (c.hits, c.misses));
+        let l2 = &l2.borrow().counters.ports[0];
+        let l3 = &l3.borrow().counters;
+
+        Snapshot {
+            l1,
+            l2: (l2.hits, l2.misses),
+            l3: (l3.reads - l3.row_misses, l3.row_misses),
+        }
+    };
+    let step = |traversal: Traversal| Step {
+        traversal,
+        block: window.query(traversal.position),
+        l1: Access::None,
+        l2: Access::None,
+        l3: Access::None,
+        cycles: 0,
+    };
+
+    clock(&mut vtu, true);
+
+    vtu.ray_origin_in = origin;
+    vtu.ray_direction_in = direction;
+    vtu.ray_init_in = true;
+    clock(&mut vtu, false);
+    vtu.ray_init_in = false;
+
+    let mut steps = vec![step(vtu.traversal())];
+    let mut last = snapshot(&mut vtu);
+    let mut cycles = 1;
+
+    while !vtu.valid_out && cycles < MAX_CYCLES {
+        clock(&mut vtu, false);
+        cycles += 1;
+
+        // Whatever the caches did this cycle was for the read of the voxel the VTU was in
+        let now = snapshot(&mut vtu);
+        let current = steps.last_mut().unwrap();
+        current.l1.update(now.l1, last.l1);
+        current.l2.update(now.l2, last.l2);
+        current.l3.update(now.l3, last.l3);
+        current.cycles += 1;
+        last = now;
+
+        let (before, after) = (current.traversal, vtu.traversal());
+        if after.steps != before.steps || after.shadow_ray != before.shadow_ray {
+            steps.push(step(after));
+        }
+    }
+
+    RayTrace {
+        pixel,
+        resolution: orchestrator.resolution(),
+        origin,
+        direction,
+        steps,
+        voxel: vtu.voxel_out,
+        normal: vtu.normal_out,
+        shadowed: vtu.shadowed_out,
+        cycles,
+        timed_out: !vtu.valid_out,
+    }
+}
+
+impl Access {
+    /// Takes the `(hits, misses)` that a level counted on a cycle into account
+    fn update(&mut self, now: (usize, usize), last: (usize, usize)) {
+        if now.1 > last.1 {
+            *self = Self::Miss;
+        } else if now.0 > last.0 {
+            *self = Self::Hit;
+        }
+    }
+}
+
+impl fmt::Display for Access {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::None => "-",
+            Self::Hit => "hit",
+            Self::Miss => "miss",
+        })
+    }
+}
+
+impl RayTrace {
+    /// Column headers of [RayTrace::rows]
+    const COLUMNS: [&'static str; 10] = ["#", "voxel", "block", "t", "t_max", "axis", "L1", "L2", "L3", "cycles"];
+
+    /// One line about the ray as a whole
+    pub fn summary(&self) -> String {
+        let (x, y) = (self.pixel % self.resolution.width, self.pixel / self.resolution.width);
+        let end = match (self.timed_out, self.voxel) {
+            (true, _) => "still going".to_string(),
+            (false, Block::Air) => "sky".to_string(),
+            (false, voxel) => format!("{voxel:?}, normal {}{}", self.normal, if self.shadowed { ", shadowed" } else { "" }),
+        };
+
+        format!("pixel ({x}, {y}): {end} after {} voxels and {} cycles", self.steps.len(), self.cycles)
+    }
+
+    /// The steps as a table, one row per step and one string per column
+    fn rows(&self) -> Vec<[String; 10]> {
+        let fixed = |x: Fixed| match x == Fixed::MAX {
+            true => "inf".to_string(),
+            false => format!("{:.2}", f32::from(x)),
+        };
+
+        self.steps
+            .iter()
+            .enumerate()
+            .map(|(i, step)| {
+                let Traversal { position: p, t, t_max, last_step, shadow_ray, .. } = step.traversal;
+
+                [
+                    format!("{i}{}", if shadow_ray { "s" } else { "" }),
+                    format!("({}, {}, {})", p.x, p.y, p.z),
+                    step.block.map_or("-".to_string(), |block| format!("{block:?}")),
+                    fixed(t),
+                    format!("({}, {}, {})", fixed(t_max.x), fixed(t_max.y), fixed(t_max.z)),
+                    match last_step {
+                        Axis::None => "-",
+                        Axis::X => "x",
+                        Axis::Y => "y",
+                        Axis::Z => "z",
+                    }.to_string(),
+                    step.l1.to_string(),
+                    step.l2.to_string(),
+                    step.l3.to_string(),
+                    step.cycles.to_string(),
+                ]
+            })
+            .collect()
+    }
+
+    /// Draws the trace over the viewer's window, which spans `width` by `height` pixels and
+    /// shows the frame scaled up `scale` times. The table starts at step `scroll`
+    pub fn draw(&self, draw: &Draw, width: f32, height: f32, scale: f32, scroll: usize) {
+        let (left, top) = (-width / 2.0, height / 2.0);
+        let line = 11.0;
+
+        // Where the pixel is on the frame
+        let (x, y) = (self.pixel % self.resolution.width, self.pixel / self.resolution.width);
+        draw.rect()
+            .x_y(left + (x as f32 + 0.5) * scale, top - (y as f32 + 0.5) * scale)
+            .w_h(scale + 2.0, scale + 2.0)
+            .no_fill()
+            .stroke(rgba(1.0, 0.0, 0.0, 1.0))
+            .stroke_weight(1.0);
+
+        // Table of the steps at the top, the summary and the map at the bottom
+        let map = height / 3.0 - 8.0;
+        let rows = ((height - map - 4.0 * line) / line) as usize;
+        draw.rect()
+            .x_y(0.0, 0.0)
+            .w_h(width, height)
+            .color(rgba(0.0, 0.0, 0.0, 0.75));
+
+        let text = |s: &str, x: f32, y: f32, w: f32, color: Rgba| {
+            draw.text(s)
+                .font_size(9)
+                .left_justify()
+                .align_text_top()
+                .x_y(x + w / 2.0, y - height / 2.0)
+                .w_h(w, height)
+                .color(color);
+        };
+        let white = rgba(1.0, 1.0, 1.0, 1.0);
+
+        let steps = self.rows();
+        let widths = [22.0, 78.0, 58.0, 36.0, 112.0, 24.0, 28.0, 28.0, 28.0, 32.0];
+        let mut x = left + 4.0;
+        for (column, (header, w)) in Self::COLUMNS.iter().zip(widths).enumerate() {
+            let cells = steps
+                .iter()
+                .skip(scroll)
+                .take(rows)
+                .map(|row| row[column].as_str())
+                .collect::<Vec<_>>();
+
+            text(header, x, top - 4.0, w, rgba(0.7, 0.7, 0.7, 1.0));
+            text(&cells.join("\n"), x, top - 4.0 - line, w, white);
+            x += w;
+        }
+
+        let bottom = -height / 2.0 + map + 8.0;
+        text(&self.summary(), left + 4.0, bottom, width - map - 12.0, white);
+        text(
+            "steps ending in s are the shadow ray's\nL1/L2: hit or miss, L3: DDR row hit or miss\n\
+             up/down to scroll, click another pixel, D to resume",
+            left + 4.0,
+            bottom - 3.0 * line,
+            width - map - 12.0,
+            rgba(0.7, 0.7, 0.7, 1.0),
+        );
+
+        self.draw_map(draw, pt2(width / 2.0 - map / 2.0 - 4.0, -height / 2.0 + map / 2.0 + 4.0), map);
+    }
+
+    /// Draws the ray's path seen from the top, X to the right and Z downwards, in a square of
+    /// side `size` centred on `center`. Each voxel is coloured by where its read was answered:
+    /// green in the L1 cache, yellow in the L2, red in the L3, grey if it wasn't read at all.
+    /// The voxel hit is outlined in white
+    fn draw_map(&self, draw: &Draw, center: Point2, size: f32) {
+        let origin = self.origin.floor();
+        let positions = self.steps.iter().map(|step| step.traversal.position).chain([origin]);
+        let (min, max) = positions.fold((origin, origin), |(min, max), p| (
+            Vec3i::new(min.x.min(p.x), 0, min.z.min(p.z)),
+            Vec3i::new(max.x.max(p.x), 0, max.z.max(p.z)),
+        ));
+        let cells = (max.x - min.x).max(max.z - min.z) as f32 + 1.0;
+        let cell = size / cells;
+
+        draw.rect()
+            .xy(center)
+            .w_h(size, size)
+            .color(rgba(0.1, 0.1, 0.1, 1.0));
+
+        // Top-left corner of the voxel at (x, z), in blocks
+        let corner = pt2(center.x - size / 2.0, center.y + size / 2.0);
+        let at = |x: f32, z: f32| pt2(corner.x + (x - min.x as f32) * cell, corner.y - (z - min.z as f32) * cell);
+
+        // Last voxel of the primary ray
+        let last = self.steps
+            .iter()
+            .rposition(|step| !step.traversal.shadow_ray)
+            .unwrap_or_default();
+
+        for (i, step) in self.steps.iter().enumerate() {
+            let p = step.traversal.position;
+            let alpha = if step.traversal.shadow_ray { 0.4 } else { 0.8 };
+            let (r, g, b) = match (step.l1, step.l2, step.l3) {
+                (Access::Hit, ..) => (0.3, 0.9, 0.3),
+                (_, Access::Hit, _) => (0.9, 0.9, 0.3),
+                (_, _, Access::None) => (0.5, 0.5, 0.5),
+                _ => (0.9, 0.3, 0.3),
+            };
+
+            let rect = draw.rect()
+                .xy(at(p.x as f32 + 0.5, p.z as f32 + 0.5))
+                .w_h(cell, cell)
+                .color(rgba(r, g, b, alpha));
+
+            if i == last && self.voxel != Block::Air && !self.timed_out {
+                rect.stroke(rgba(1.0, 1.0, 1.0, 1.0)).stroke_weight(1.0);
+            }
+        }
+
+        // The primary ray itself, up to where it entered its last voxel
+        let t = f32::from(self.steps[last].traversal.t);
+        let direction = self.direction.normalized();
+        let (ox, oz) = (f32::from(self.origin.x), f32::from(self.origin.z));
+        let (dx, dz) = (f32::from(direction.x), f32::from(direction.z));
+
+        draw.line()
+            .start(at(ox, oz))
+            .end(at(ox + dx * t, oz + dz * t))
+            .weight(1.0)
+            .color(rgba(1.0, 1.0, 1.0, 1.0));
+        draw.ellipse()
+            .xy(at(ox, oz))
+            .radius(2.0)
+            .color(rgba(1.0, 0.0, 0.0, 1.0));
+    }
+}
+
+impl fmt::Display for RayTrace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.summary())?;
+        writeln!(f, "origin {}, direction {}", self.origin, self.direction)?;
+        writeln!(f, "{}", Self::COLUMNS.join("\t"))?;
+
+        for row in self.rows() {
+            writeln!(f, "{}", row.join("\t"))?;
+        }
+        Ok(())
+    }
+}
+
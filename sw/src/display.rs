@@ -0,0 +1,545 @@
+//! The HDMI side of the design: the framebuffers the [crate::orchestrator::Orchestrator]
+//! draws into, and the scanout that reads them out to the monitor, paced by `vsg.sv`'s
+//! timing on the pixel clock. Lets us see tearing and measure frame rates against the
+//! refresh rate before touching the HDL.
+
+use crate::{counters::DisplayCounters, math::Rgb565, orchestrator::Resolution, trace::{Trace, Tracer}};
+
+/// How the framebuffers are swapped
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Buffering {
+    /// One framebuffer, drawn while it's being scanned out, like `top_level.sv` for now
+    #[default]
+    Single,
+    /// Front and back buffers, swapped on vsync. The orchestrator waits for the swap
+    /// before drawing the next frame
+    Double,
+    /// Front and back buffers, swapped as soon as a frame is done, even mid-scanout
+    Immediate,
+}
+
+/// Parameters of `vsg.sv`, and the clocks on either side of the framebuffers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VideoTiming {
+    pub active_h_pixels: usize,
+    pub h_front_porch: usize,
+    pub h_sync_width: usize,
+    pub h_back_porch: usize,
+    pub active_lines: usize,
+    pub v_front_porch: usize,
+    pub v_sync_width: usize,
+    pub v_back_porch: usize,
+    /// Frequency of the pixel clock, in Hz
+    pub pixel_hz: usize,
+    /// Frequency of the clock the orchestrator runs on, in Hz
+    pub clock_hz: usize,
+}
+
+impl Buffering {
+    /// Parses `single`, `double` or `immediate`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s {
+            "single" => Ok(Self::Single),
+            "double" => Ok(Self::Double),
+            "immediate" => Ok(Self::Immediate),
+            _ => Err(format!("unknown buffering {s:?}, expected single, double or immediate")),
+        }
+    }
+}
+
+impl VideoTiming {
+    /// 720p at 60Hz, `vsg.sv`'s defaults, with `hdmi_clk_wiz_720p`'s 74.25MHz pixel clock and
+    /// the design's 100MHz clock
+    pub const HD720: Self = Self {
+        active_h_pixels: 1280,
+        h_front_porch: 110,
+        h_sync_width: 40,
+        h_back_porch: 220,
+        active_lines: 720,
+        v_front_porch: 5,
+        v_sync_width: 5,
+        v_back_porch: 20,
+        pixel_hz: 74_250_000,
+        clock_hz: 100_000_000,
+    };
+
+    /// Parses a comma-separated list of `field=value` overrides on top of [VideoTiming::HD720]
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut timing = Self::HD720;
+
+        for kv in s.split(',').filter(|kv| !kv.is_empty()) {
+            let Some((key, value)) = kv.split_once('=') else {
+                return Err(format!("expected field=value in video timing, got {kv:?}"));
+            };
+            let value = value
+                .parse()
+                .map_err(|e| format!("invalid value for video timing {key}: {e}"))?;
+
+            *match key {
+                "active_h_pixels" => &mut timing.active_h_pixels,
+                "h_front_porch" => &mut timing.h_front_porch,
+                "h_sync_width" => &mut timing.h_sync_width,
+                "h_back_porch" => &mut timing.h_back_porch,
+                "active_lines" => &mut timing.active_lines,
+                "v_front_porch" => &mut timing.v_front_porch,
+                "v_sync_width" => &mut timing.v_sync_width,
+                "v_back_porch" => &mut timing.v_back_porch,
+                "pixel_hz" => &mut timing.pixel_hz,
+                "clock_hz" => &mut timing.clock_hz,
+                _ => return Err(format!("unknown video timing field {key:?}")),
+            } = value;
+        }
+
+        if timing.h_sync_width == 0 || timing.v_sync_width == 0 || timing.pixel_hz == 0 || timing.clock_hz == 0 {
+            return Err("video timing sync widths and clocks must be at least 1".into());
+        }
+        Ok(timing)
+    }
+
+    /// Pixel clock cycles per line, active or not
+    pub fn total_pixels(&self) -> usize {
+        self.active_h_pixels + self.h_front_porch + self.h_sync_width + self.h_back_porch
+    }
+
+    /// Lines per refresh, active or not
+    pub fn total_lines(&self) -> usize {
+        self.active_lines + self.v_front_porch + self.v_sync_width + self.v_back_porch
+    }
+
+    /// Refreshes per second
+    pub fn refresh_hz(&self) -> f32 {
+        self.pixel_hz as f32 / (self.total_pixels() * self.total_lines()) as f32
+    }
+
+    /// How many times every pixel of a framebuffer at `resolution` is repeated along each
+    /// axis of the screen, the 4x of `top_level.sv`'s "Scale-up frame buffer"
+    pub fn scale(&self, resolution: Resolution) -> usize {
+        usize::min(
+            self.active_h_pixels / resolution.width,
+            self.active_lines / resolution.height,
+        )
+    }
+}
+
+impl Default for VideoTiming {
+    fn default() -> Self {
+        Self::HD720
+    }
+}
+
+/// `video_sig_gen` of `vsg.sv`, clocked by the pixel clock
+#[derive(Debug, Default)]
+pub struct VideoSigGen {
+    pub reset: bool,
+    /// Pixel in the line, active or not
+    pub hcount_out: usize,
+    /// Line in the refresh, active or not
+    pub vcount_out: usize,
+    /// Vertical sync
+    pub vs_out: bool,
+    /// Horizontal sync
+    pub hs_out: bool,
+    /// Whether [VideoSigGen::hcount_out] and [VideoSigGen::vcount_out] are in the active
+    /// area, i.e. get drawn
+    pub ad_out: bool,
+    /// High for one cycle right after the last active pixel of a refresh
+    pub nf_out: bool,
+    /// Refreshes since reset, modulo 64
+    pub fc_out: u8,
+
+    timing: VideoTiming,
+}
+
+impl VideoSigGen {
+    pub fn with_timing(mut self, timing: VideoTiming) -> Self {
+        self.timing = timing;
+        self
+    }
+
+    pub fn rising_clk_edge(&mut self) {
+        let t = &self.timing;
+
+        if self.reset {
+            self.hcount_out = 0;
+            self.vcount_out = 0;
+            self.vs_out = false;
+            self.hs_out = false;
+            self.ad_out = true;
+            self.nf_out = false;
+            self.fc_out = 0;
+            return;
+        }
+
+        self.hcount_out += 1;
+        if self.hcount_out == t.total_pixels() {
+            self.hcount_out = 0;
+            self.vcount_out = (self.vcount_out + 1) % t.total_lines();
+        }
+        let (h, v) = (self.hcount_out, self.vcount_out);
+
+        // New frame on the pixel right after the last active one
+        self.nf_out = h == t.active_h_pixels && v == t.active_lines;
+        if self.nf_out {
+            self.fc_out = (self.fc_out + 1) % 64;
+        }
+
+        let h_sync = t.active_h_pixels + t.h_front_porch;
+        let v_sync = t.active_lines + t.v_front_porch;
+        self.hs_out = (h_sync..h_sync + t.h_sync_width).contains(&h);
+        self.vs_out = (v_sync..v_sync + t.v_sync_width).contains(&v);
+        self.ad_out = h < t.active_h_pixels && v < t.active_lines;
+    }
+}
+
+/// The framebuffers and their scanout. Runs on the orchestrator's clock, and steps its
+/// [VideoSigGen] as many times as the pixel clock ticks in between
+#[derive(Debug)]
+pub struct Display {
+    pub reset: bool,
+    /// Framebuffer write port of the orchestrator, into the back buffer
+    pub pixel_write_in: Option<(usize, Rgb565)>,
+    /// The orchestrator just finished drawing a frame into the back buffer
+    pub frame_done_in: bool,
+    /// Whether the orchestrator may start drawing its next frame, i.e. the back buffer
+    /// isn't waiting to be shown
+    pub frame_start_out: bool,
+    /// What the monitor showed last, at the framebuffer's resolution: every pixel as it
+    /// was read when the scanout last went through it. Tearing shows up here
+    pub screen_out: Box<[Rgb565]>,
+    /// Performance counters, not part of the design
+    pub counters: DisplayCounters,
+
+    buffering: Buffering,
+    timing: VideoTiming,
+    /// Size of the frames in the framebuffers
+    resolution: Resolution,
+    vsg: VideoSigGen,
+    /// Both framebuffers. Single buffering only uses the first
+    buffers: [Box<[Rgb565]>; 2],
+    /// Index of the buffer being scanned out
+    front: usize,
+    /// Whether the back buffer holds a finished frame, waiting for the swap
+    back_ready: bool,
+    /// Pixel clock edges owed, in units of [VideoTiming::clock_hz]: every cycle adds
+    /// [VideoTiming::pixel_hz] to it
+    pixel_phase: usize,
+    /// Vertical sync on the last pixel clock edge, to find its rising edge
+    last_vs: bool,
+
+    /// Which frame every pixel of each buffer was drawn by, not part of the design
+    frame_ids: [Box<[usize]>; 2],
+    /// Frames the orchestrator finished since reset
+    frames: usize,
+    /// Oldest and newest frame scanned out so far in this refresh
+    refresh_frames: Option<(usize, usize)>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Self {
+            reset: false,
+            pixel_write_in: None,
+            frame_done_in: false,
+            frame_start_out: true,
+            screen_out: Default::default(),
+            counters: Default::default(),
+            buffering: Default::default(),
+            timing: Default::default(),
+            resolution: Default::default(),
+            vsg: Default::default(),
+            buffers: Default::default(),
+            front: 0,
+            back_ready: false,
+            pixel_phase: 0,
+            last_vs: false,
+            frame_ids: Default::default(),
+            frames: 0,
+            refresh_frames: None,
+        }
+        .with_resolution(Default::default())
+    }
+}
+
+impl Display {
+    pub fn with_buffering(mut self, buffering: Buffering) -> Self {
+        self.buffering = buffering;
+        self
+    }
+
+    pub fn with_timing(mut self, timing: VideoTiming) -> Self {
+        self.timing = timing;
+        self.vsg = VideoSigGen::default().with_timing(timing);
+        self
+    }
+
+    /// Framebuffers for frames of `resolution`, which must fit in the active area
+    pub fn with_resolution(mut self, resolution: Resolution) -> Self {
+        let buffer = || vec![Rgb565::default(); resolution.pixels()].into_boxed_slice();
+        let ids = || vec![0; resolution.pixels()].into_boxed_slice();
+
+        self.resolution = resolution;
+        self.screen_out = buffer();
+        self.buffers = [buffer(), buffer()];
+        self.frame_ids = [ids(), ids()];
+        self
+    }
+
+    /// Buffer the orchestrator draws into
+    fn back(&self) -> usize {
+        match self.buffering {
+            Buffering::Single => self.front,
+            Buffering::Double | Buffering::Immediate => 1 - self.front,
+        }
+    }
+
+    pub fn rising_clk_edge(&mut self) {
+        self.vsg.reset = self.reset;
+
+        if self.reset {
+            self.vsg.rising_clk_edge();
+
+            self.frame_start_out = true;
+            self.screen_out.fill(Rgb565::default());
+            self.counters = Default::default();
+            for buffer in &mut self.buffers {
+                buffer.fill(Rgb565::default());
+            }
+            self.front = 0;
+            self.back_ready = false;
+            self.pixel_phase = 0;
+            self.last_vs = false;
+            for ids in &mut self.frame_ids {
+                ids.fill(0);
+            }
+            self.frames = 0;
+            self.refresh_frames = None;
+            return;
+        }
+
+        // Framebuffer write port
+        if let Some((pixel, color)) = self.pixel_write_in {
+            let back = self.back();
+            self.buffers[back][pixel] = color;
+            self.frame_ids[back][pixel] = self.frames + 1;
+        }
+
+        if self.frame_done_in {
+            self.frames += 1;
+
+            match self.buffering {
+                Buffering::Single => (),
+                Buffering::Double => self.back_ready = true,
+                Buffering::Immediate => self.swap(),
+            }
+        }
+
+        // Scanout, on the pixel clock
+        self.pixel_phase += self.timing.pixel_hz;
+        while self.pixel_phase >= self.timing.clock_hz {
+            self.pixel_phase -= self.timing.clock_hz;
+            self.pixel_clk_edge();
+        }
+
+        self.frame_start_out = !self.back_ready;
+        self.counters.swap_wait_cycles += usize::from(self.back_ready);
+    }
+
+    /// One edge of the pixel clock, reading the pixel that [VideoSigGen] is at, then moving
+    /// it on to the next one
+    fn pixel_clk_edge(&mut self) {
+        let vsg = &self.vsg;
+        let scale = self.timing.scale(self.resolution);
+        let (x, y) = (vsg.hcount_out / scale, vsg.vcount_out / scale);
+
+        // Only the first of the repeated lines of every framebuffer line gets recorded
+        let sampled = vsg.ad_out && vsg.hcount_out.is_multiple_of(scale) && vsg.vcount_out.is_multiple_of(scale);
+        if sampled && x < self.resolution.width && y < self.resolution.height {
+            let pixel = x + y * self.resolution.width;
+            let frame = self.frame_ids[self.front][pixel];
+
+            self.screen_out[pixel] = self.buffers[self.front][pixel];
+            self.refresh_frames = Some(match self.refresh_frames {
+                Some((oldest, newest)) => (oldest.min(frame), newest.max(frame)),
+                None => (frame, frame),
+            });
+        }
+
+        // End of the active area, the monitor has the whole refresh
+        if vsg.nf_out {
+            self.counters.refreshes += 1;
+            if self.refresh_frames.is_some_and(|(oldest, newest)| oldest != newest) {
+                self.counters.torn_refreshes += 1;
+            }
+            self.refresh_frames = None;
+        }
+
+        // Swap on the rising edge of vsync, in the blanking interval
+        if vsg.vs_out && !self.last_vs && self.back_ready {
+            self.swap();
+            self.back_ready = false;
+        }
+        self.last_vs = self.vsg.vs_out;
+
+        self.vsg.rising_clk_edge();
+    }
+
+    /// Shows the back buffer
+    fn swap(&mut self) {
+        self.front = 1 - self.front;
+        self.counters.swaps += 1;
+    }
+}
+
+impl Trace for Display {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("display", |t| {
+            t.bit("reset", self.reset);
+            t.bit("frame_done_in", self.frame_done_in);
+            t.bit("frame_start_out", self.frame_start_out);
+            t.bit("front", self.front == 1);
+            t.bit("back_ready", self.back_ready);
+
+            // Sampled on the orchestrator's clock, so some pixel clock edges are skipped
+            self.vsg.trace(t);
+        });
+    }
+}
+
+impl Trace for VideoSigGen {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("vsg", |t| {
+            t.bit("reset", self.reset);
+            t.bits("hcount_out", 11, self.hcount_out as u64);
+            t.bits("vcount_out", 10, self.vcount_out as u64);
+            t.bit("vs_out", self.vs_out);
+            t.bit("hs_out", self.hs_out);
+            t.bit("ad_out", self.ad_out);
+            t.bit("nf_out", self.nf_out);
+            t.bits("fc_out", 6, self.fc_out.into());
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: Rgb565 = Rgb565::new(255, 0, 0);
+
+    /// A reset display of 4x2 pixels, with a pixel of blanking on either side of hsync, a
+    /// line on either side of vsync, and a pixel clock as fast as its own: 35 cycles per
+    /// refresh, of which vsync is the 4th line
+    fn display(buffering: Buffering) -> Display {
+        let timing = VideoTiming {
+            active_h_pixels: 4,
+            h_front_porch: 1,
+            h_sync_width: 1,
+            h_back_porch: 1,
+            active_lines: 2,
+            v_front_porch: 1,
+            v_sync_width: 1,
+            v_back_porch: 1,
+            pixel_hz: 1,
+            clock_hz: 1,
+        };
+        let mut display = Display::default()
+            .with_buffering(buffering)
+            .with_timing(timing)
+            .with_resolution(Resolution { width: 4, height: 2 });
+
+        display.reset = true;
+        display.rising_clk_edge();
+        display.reset = false;
+        display
+    }
+
+    fn clock(display: &mut Display, pixel_write: Option<(usize, Rgb565)>, frame_done: bool) {
+        display.pixel_write_in = pixel_write;
+        display.frame_done_in = frame_done;
+        display.rising_clk_edge();
+    }
+
+    /// Draws every pixel in `color`, one per cycle, then finishes the frame on the next one
+    fn draw(display: &mut Display, color: Rgb565) {
+        for pixel in 0..display.resolution.pixels() {
+            clock(display, Some((pixel, color)), false);
+        }
+        clock(display, None, true);
+    }
+
+    /// Clocks `display` until the monitor has had `refreshes` in all
+    fn refresh(display: &mut Display, refreshes: usize) {
+        while display.counters.refreshes < refreshes {
+            clock(display, None, false);
+        }
+    }
+
+    #[test]
+    fn double_swaps_on_vsync() {
+        let mut display = display(Buffering::Double);
+
+        // Done in the middle of the first refresh's active area
+        draw(&mut display, RED);
+        assert!(display.vsg.ad_out);
+
+        let mut waited = 0;
+        loop {
+            assert!(!display.frame_start_out);
+            waited += 1;
+
+            let rising_vsync = display.vsg.vs_out && !display.last_vs;
+            clock(&mut display, None, false);
+            assert_eq!(display.counters.swaps, usize::from(rising_vsync));
+            if rising_vsync {
+                break;
+            }
+        }
+        assert!(display.frame_start_out);
+        assert_eq!(display.counters.swap_wait_cycles, waited);
+
+        // Neither refresh mixes frames, the first is all black and the second all red
+        assert_eq!(display.screen_out.iter().filter(|&&c| c == RED).count(), 0);
+        refresh(&mut display, 2);
+        assert!(display.screen_out.iter().all(|&c| c == RED));
+        assert_eq!((display.counters.torn_refreshes, display.counters.swap_wait_cycles), (0, waited));
+    }
+
+    #[test]
+    fn immediate_swaps_mid_scanout() {
+        let mut display = display(Buffering::Immediate);
+
+        draw(&mut display, RED);
+        assert!(display.vsg.ad_out);
+        assert_eq!(display.counters.swaps, 1);
+        assert!(display.frame_start_out);
+
+        // The first line was scanned out of the old front buffer, the rest of the new one
+        refresh(&mut display, 1);
+        assert_eq!(display.counters.torn_refreshes, 1);
+        assert_eq!(display.screen_out[0], Rgb565::default());
+        assert_eq!(display.screen_out[7], RED);
+        assert_eq!(display.counters.swap_wait_cycles, 0);
+    }
+
+    #[test]
+    fn single_tears_while_drawing() {
+        let mut display = display(Buffering::Single);
+
+        // The scanout gets to the first pixel before it's drawn, and the last one after
+        clock(&mut display, None, false);
+        clock(&mut display, None, false);
+        draw(&mut display, RED);
+        assert!(display.frame_start_out);
+
+        refresh(&mut display, 1);
+        assert_eq!(display.counters.torn_refreshes, 1);
+        assert_eq!(display.screen_out[0], Rgb565::default());
+        assert_eq!(display.screen_out[7], RED);
+        assert_eq!((display.counters.swaps, display.counters.swap_wait_cycles), (0, 0));
+
+        // The next refresh is all of the same frame
+        refresh(&mut display, 2);
+        assert!(display.screen_out.iter().all(|&c| c == RED));
+        assert_eq!(display.counters.torn_refreshes, 1);
+    }
+}
@@ -0,0 +1,305 @@
+//! Fixed-point format exploration: renders the same poses with the ray caster's maths done
+//! in several [FixedPoint] formats, and in `f32` as the reference, then reports how far
+//! each format's pixels are from the reference. Meant to pick the bit widths of `fixed.sv`
+//! from data rather than by eye.
+//!
+//! The renderer is the instant one of [VoxelTraversalUnit::mock_cast], with the camera of
+//! [Orchestrator::basis], both in any [Scalar], and lit like the orchestrator does, with
+//! flat colours. Overflows are recorded by the [overflow] checker rather than wrapping
+//! around silently or panicking, and the pixels they happen in are counted as overflows.
+
+use std::{fmt::Write as _, fs, path::{Path, PathBuf}};
+
+use crate::{block::{Block, Material}, cache::MockCache, camera::Pose, config::Config, fixed, headless::to_image, math::{overflow::{self, Kind}, FixedPoint, Rgb565, Scalar, Vec3}, orchestrator::{Orchestrator, Resolution}, vtu::{RenderDistance, VoxelTraversalUnit}};
+
+pub const USAGE: &str = "\
+usage: fpgacraft formats [options]
+
+Renders every pose in each fixed-point format below and in f32, and writes how far each
+format is from f32 to formats.csv, one row per format and pose. Errors are per pixel, the
+largest difference of its 8-bit channels.
+
+formats (total bits/fractional bits): 16/6, 18/6, 18/8, 20/6, 20/8 (the hardware's),
+20/10, 22/10, 24/12, 32/16
+
+options:
+    --pose \"X Y Z YAW [PITCH [ROLL]]\"
+                                 Camera pose, angles in degrees (default: \"0 0 0 0 0 0\")
+    --scene FILE                 File with one pose per line
+    --fov DEGREES                Vertical field of view (default: 90)
+    --render-distance FIELD=N,...
+                                 How far rays go, same as `fpgacraft render`
+    --out DIR                    Where to write formats.csv (default: formats)
+    --images                     Also write every render as a PNG, the reference's too, and
+                                 a diff with the reference where differing or overflowing
+                                 pixels are red";
+
+/// Rendered pixel: the block that was hit and its colour, or [None] if the maths overflowed
+type Pixel = Option<(Block, Rgb565)>;
+
+/// Colour of the pixels that overflowed, and of the ones that differ in diff images
+const OVERFLOW: Rgb565 = Rgb565::new(255, 0, 0);
+
+/// A format to try, and the renderer that does its maths in it
+#[derive(Debug, Clone, Copy)]
+struct Format {
+    bits: u32,
+    frac_bits: u32,
+    render: fn(&Pose, &Config) -> Vec<Pixel>,
+}
+
+const FORMATS: &[Format] = &[
+    Format::new::<16, 6>(),
+    Format::new::<18, 6>(),
+    Format::new::<18, 8>(),
+    Format::new::<20, 6>(),
+    Format::new::<20, 8>(),
+    Format::new::<20, 10>(),
+    Format::new::<22, 10>(),
+    Format::new::<24, 12>(),
+    Format::new::<32, 16>(),
+];
+
+/// How a format's render compares to the reference
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Error {
+    /// Largest error of any pixel
+    max: u8,
+    /// Average error over the pixels that didn't overflow
+    mean: f32,
+    /// Pixels that aren't exactly the same colour
+    differing: usize,
+    /// Pixels where a different block was hit
+    wrong_block: usize,
+    /// Pixels whose maths overflowed
+    overflows: usize,
+}
+
+impl Format {
+    const fn new<const B: u32, const D: u32>() -> Self {
+        Self {
+            bits: B,
+            frac_bits: D,
+            render: render::<FixedPoint<B, D>>,
+        }
+    }
+}
+
+impl Error {
+    fn new(reference: &[Pixel], pixels: &[Pixel]) -> Self {
+        let mut error = Self::default();
+        let mut total = 0;
+
+        for (expected, got) in reference.iter().zip(pixels) {
+            let (Some((expected_block, expected)), Some((block, got))) = (expected, got) else {
+                error.overflows += 1;
+                continue;
+            };
+
+            let e = difference(*expected, *got);
+            error.max = error.max.max(e);
+            error.differing += usize::from(e > 0);
+            error.wrong_block += usize::from(expected_block != block);
+            total += usize::from(e);
+        }
+
+        let rendered = reference.len() - error.overflows;
+        error.mean = if rendered > 0 { total as f32 / rendered as f32 } else { 0.0 };
+        error
+    }
+}
+
+#[derive(Debug)]
+struct Options {
+    poses: Vec<Pose>,
+    out: PathBuf,
+    images: bool,
+    config: Config,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut poses = vec![Pose::default()];
+        let mut out = PathBuf::from("formats");
+        let mut images = false;
+        let mut config = Config::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args
+                .next()
+                .ok_or_else(|| format!("missing value for {arg}"));
+
+            match arg.as_str() {
+                "--pose" => poses = vec![Pose::parse(value()?)?],
+                "--scene" => {
+                    let path = value()?;
+                    let scene = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+
+                    poses = Pose::parse_scene(&scene)?;
+                    if poses.is_empty() {
+                        return Err(format!("scene {path} has no poses"));
+                    }
+                },
+                "--fov" => config.fov = value()?.parse().map_err(|e| format!("invalid field of view: {e}"))?,
+                "--render-distance" => config.render_distance = RenderDistance::parse(value()?)?,
+                "--out" => out = value()?.into(),
+                "--images" => images = true,
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+
+        config.validate()?;
+
+        Ok(Self { poses, out, images, config })
+    }
+}
+
+/// Entry point of `fpgacraft formats`
+pub fn main(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let opts = Options::parse(args)?;
+    fs::create_dir_all(&opts.out).map_err(|e| format!("couldn't create {}: {e}", opts.out.display()))?;
+
+    // Overflows are expected with the narrow formats, and they're counted rather than fatal
+    overflow::enable();
+
+    let mut csv = String::from("bits,frac_bits,pose,max_error,mean_error,differing_pixels,wrong_block_pixels,overflow_pixels\n");
+    println!("{:>6} {:>10} {:>10} {:>10} {:>12} {:>10}", "format", "max error", "mean error", "differing", "wrong block", "overflows");
+
+    let references = opts.poses
+        .iter()
+        .map(|pose| render::<f32>(pose, &opts.config))
+        .collect::<Vec<_>>();
+
+    if opts.images {
+        for (i, reference) in references.iter().enumerate() {
+            save(&opts.out.join(format!("f32_{i:04}.png")), reference.iter().map(|pixel| pixel.map_or(OVERFLOW, |(_, color)| color)))?;
+        }
+    }
+
+    for format in FORMATS {
+        let mut summary = Error::default();
+
+        for (i, (pose, reference)) in opts.poses.iter().zip(&references).enumerate() {
+            let pixels = (format.render)(pose, &opts.config);
+            let error = Error::new(reference, &pixels);
+
+            writeln!(
+                csv,
+                "{},{},{i},{},{:.3},{},{},{}",
+                format.bits, format.frac_bits, error.max, error.mean, error.differing, error.wrong_block, error.overflows,
+            ).unwrap();
+
+            summary.max = summary.max.max(error.max);
+            summary.mean += error.mean / opts.poses.len() as f32;
+            summary.differing += error.differing;
+            summary.wrong_block += error.wrong_block;
+            summary.overflows += error.overflows;
+
+            if opts.images {
+                let name = format!("{}_{}_{i:04}", format.bits, format.frac_bits);
+                let render = pixels.iter().map(|pixel| pixel.map_or(OVERFLOW, |(_, color)| color));
+                let diff = reference.iter().zip(&pixels).map(|(expected, got)| match (expected, got) {
+                    (Some((_, expected)), Some((_, got))) if expected == got => {
+                        Rgb565::new(expected.r() / 4, expected.g() / 4, expected.b() / 4)
+                    },
+                    _ => OVERFLOW,
+                });
+
+                save(&opts.out.join(format!("{name}.png")), render)?;
+                save(&opts.out.join(format!("{name}.diff.png")), diff)?;
+            }
+        }
+
+        println!(
+            "{:>6} {:>10} {:>10.3} {:>10} {:>12} {:>10}",
+            format!("{}/{}", format.bits, format.frac_bits), summary.max, summary.mean, summary.differing, summary.wrong_block, summary.overflows,
+        );
+    }
+
+    let path = opts.out.join("formats.csv");
+    fs::write(&path, csv).map_err(|e| format!("couldn't write {}: {e}", path.display()))
+}
+
+/// Renders a frame from `pose` with the maths done in `T`. Overflows are checked after the
+/// camera vectors and after every pixel, so that one only loses the pixels it affects.
+fn render<T: Scalar>(pose: &Pose, config: &Config) -> Vec<Pixel> {
+    let cache = MockCache::default();
+    let position = Vec3::<T>::from_f32([pose.position.x, pose.position.y, pose.position.z].map(f32::from));
+    overflow::take();
+
+    // Camera vectors, as the orchestrator computes them at the start of a frame
+    let viewport_height = 2.0 * (config.fov.to_radians() / 2.0).tan();
+    let viewport_width = viewport_height * Orchestrator::FRAME_WIDTH as f32 / Orchestrator::FRAME_HEIGHT as f32;
+
+    let (u, v, w) = Orchestrator::basis(Vec3::<T>::from_f32(pose.heading_f32()), Vec3::from_f32(pose.up_f32()));
+
+    let viewport_u = u * T::from_f32(viewport_width);
+    let viewport_v = -v * T::from_f32(viewport_height);
+    let delta_u = u * T::from_f32(viewport_width / Orchestrator::FRAME_WIDTH as f32);
+    let delta_v = -v * T::from_f32(viewport_height / Orchestrator::FRAME_HEIGHT as f32);
+
+    let viewport_corner = position - w - (viewport_u + viewport_v) * T::from_f32(0.5);
+    let pixel0 = viewport_corner + (delta_u + delta_v) * T::from_f32(0.5);
+    if overflowed() {
+        return vec![None; Orchestrator::NUM_PIXELS];
+    }
+
+    (0..Orchestrator::NUM_PIXELS)
+        .map(|i| {
+            let x = T::from_int((i % Orchestrator::FRAME_WIDTH) as i16);
+            let y = T::from_int((i / Orchestrator::FRAME_WIDTH) as i16);
+            let pixel = pixel0 + delta_u * x + delta_v * y;
+
+            let hit = VoxelTraversalUnit::mock_trace(&cache, &config.render_distance, position, pixel - position);
+            let color = match hit.block {
+                Block::Air => Block::Air.color(),
+                block if matches!(block.material(), Material::Translucent { .. }) => Rgb565::default(),
+                block => {
+                    let light = T::from_f32(0.4) + T::from_f32(0.2) * hit.normal.dot(sun::<T>());
+
+                    // The lighting itself is in the hardware's format, only the ray's maths is explored
+                    block.color() * fixed!(light.to_f32())
+                },
+            };
+            let color = hit.tint + color * fixed!(hit.transmittance.to_f32());
+
+            (!overflowed()).then_some((hit.block, color))
+        })
+        .collect()
+}
+
+/// Whether anything overflowed since the last call. Underflows are only a loss of precision,
+/// which the errors already measure
+fn overflowed() -> bool {
+    overflow::take()
+        .iter()
+        .any(|summary| summary.kind == Kind::Overflow)
+}
+
+/// [Orchestrator::SUN], normalized
+fn sun<T: Scalar>() -> Vec3<T> {
+    let sun = Orchestrator::SUN;
+
+    Vec3::from_f32([sun.x, sun.y, sun.z].map(f32::from)).normalized()
+}
+
+/// Largest difference in between the 8-bit channels of two colours
+fn difference(a: Rgb565, b: Rgb565) -> u8 {
+    [(a.r(), b.r()), (a.g(), b.g()), (a.b(), b.b())]
+        .into_iter()
+        .map(|(a, b)| a.abs_diff(b))
+        .max()
+        .unwrap()
+}
+
+fn save(path: &Path, pixels: impl Iterator<Item = Rgb565>) -> Result<(), String> {
+    to_image(&pixels.collect::<Vec<_>>(), Resolution::default())
+        .save(path)
+        .map_err(|e| format!("couldn't write {}: {e}", path.display()))
+}
@@ -0,0 +1,171 @@
+//! Golden-image regression tests: renders a fixed set of camera poses against the
+//! checked-in `chunk.bin` and compares them to the images stored in `golden/`.
+//!
+//! When a change is _supposed_ to alter the renders, regenerate the images with
+//! `UPDATE_GOLDEN=1 cargo test golden` and commit them. On failure, the render and a diff
+//! (mismatching pixels in red) are written to `target/golden-diff/`.
+
+use std::path::{Path, PathBuf};
+
+use nannou::image::{self, Rgb, RgbImage};
+
+use crate::{camera::Pose, config::Config, headless::{render_frame, reset_top_level, to_image}, orchestrator::Orchestrator, texture::TextureAtlas};
+
+/// Maximum difference allowed per color channel (8-bit), i.e. one LSB of the 5-bit channels
+const TOLERANCE: u8 = 8;
+
+/// Camera poses that get rendered, and the name of their golden image
+const POSES: &[(&str, &str)] = &[
+    ("level", "0.5 3 0.5 0 0"),
+    ("level_turned", "0.5 3 0.5 90 0"),
+    ("high_tilted", "-4.5 12 2.5 200 -35"),
+    ("low_tilted", "3.5 2 -6.5 300 20"),
+];
+
+fn poses() -> impl Iterator<Item = (&'static str, Pose)> {
+    POSES
+        .iter()
+        .map(|&(name, pose)| (name, Pose::parse(pose).unwrap()))
+}
+
+/// Compares `actual` to the golden image `golden/{name}.png`, returning a description of the
+/// mismatch if there is one
+fn check(name: &str, actual: &RgbImage) -> Result<(), String> {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let golden_path = root.join("golden").join(format!("{name}.png"));
+
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_path.parent().unwrap()).unwrap();
+        actual.save(&golden_path).unwrap();
+        return Ok(());
+    }
+
+    let golden = image::open(&golden_path)
+        .map_err(|e| format!("{name}: couldn't open {}: {e}", golden_path.display()))?
+        .to_rgb8();
+    if golden.dimensions() != actual.dimensions() {
+        return Err(format!("{name}: golden is {:?} but render is {:?}", golden.dimensions(), actual.dimensions()));
+    }
+
+    // Mismatches are red, everything else is the golden image dimmed down
+    let mut diff = RgbImage::new(golden.width(), golden.height());
+    let mut mismatches = 0;
+    for ((expected, got), out) in golden.pixels().zip(actual.pixels()).zip(diff.pixels_mut()) {
+        let off = expected.0
+            .iter()
+            .zip(got.0)
+            .any(|(&e, g)| e.abs_diff(g) > TOLERANCE);
+
+        *out = if off {
+            mismatches += 1;
+            Rgb([255, 0, 0])
+        } else {
+            Rgb(expected.0.map(|c| c / 4))
+        };
+    }
+
+    if mismatches == 0 {
+        return Ok(());
+    }
+
+    let out_dir = diff_dir();
+    std::fs::create_dir_all(&out_dir).unwrap();
+    actual.save(out_dir.join(format!("{name}.png"))).unwrap();
+    diff.save(out_dir.join(format!("{name}.diff.png"))).unwrap();
+
+    Err(format!("{name}: {mismatches} pixels differ from the golden image, see {}", out_dir.display()))
+}
+
+/// `target/golden-diff/`
+fn diff_dir() -> PathBuf {
+    let target = std::env::var_os("CARGO_TARGET_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| Path::new(env!("CARGO_MANIFEST_DIR")).join("target"));
+
+    target.join("golden-diff")
+}
+
+/// Fails with every mismatch, rather than only the first one
+fn assert_all(results: impl Iterator<Item = Result<(), String>>) {
+    let errors = results
+        .filter_map(Result::err)
+        .collect::<Vec<_>>();
+
+    assert!(errors.is_empty(), "{}", errors.join("\n"));
+}
+
+#[test]
+fn cycle_accurate() {
+    let mut top_level = reset_top_level(Default::default());
+
+    assert_all(poses().map(|(name, pose)| {
+        render_frame(&mut top_level, &pose);
+        check(&format!("vtu_{name}"), &to_image(&top_level.orchestrator.frame_buffer_out, top_level.orchestrator.resolution()))
+    }));
+}
+
+#[test]
+fn textured() {
+    let mut top_level = reset_top_level(Config {
+        textures: Some(TextureAtlas::builtin()),
+        ..Default::default()
+    });
+
+    assert_all(poses().map(|(name, pose)| {
+        render_frame(&mut top_level, &pose);
+        check(&format!("textured_{name}"), &to_image(&top_level.orchestrator.frame_buffer_out, top_level.orchestrator.resolution()))
+    }));
+}
+
+#[test]
+fn shadows() {
+    let mut top_level = reset_top_level(Config {
+        shadow_cycles: 2000,
+        ..Default::default()
+    });
+
+    assert_all(poses().map(|(name, pose)| {
+        render_frame(&mut top_level, &pose);
+        check(&format!("shadows_{name}"), &to_image(&top_level.orchestrator.frame_buffer_out, top_level.orchestrator.resolution()))
+    }));
+}
+
+#[test]
+fn mock_render() {
+    let mut orchestrator = Orchestrator::default();
+
+    assert_all(poses().map(|(name, pose)| {
+        orchestrator.camera_pos_in = pose.position;
+        orchestrator.camera_heading_in = pose.heading();
+        orchestrator.camera_up_in = pose.up();
+        orchestrator.mock_render();
+
+        check(&format!("mock_{name}"), &to_image(&orchestrator.frame_buffer_out, orchestrator.resolution()))
+    }));
+}
+
+#[test]
+fn parallel() {
+    let config = Config {
+        vtus: 8,
+        ..Default::default()
+    };
+    let mut sequential = reset_top_level(config.clone());
+    let mut parallel = reset_top_level(Config {
+        threads: 3,
+        ..config
+    });
+
+    // Cycle for cycle the same, not merely within the tolerance
+    assert_all(poses().take(2).map(|(name, pose)| {
+        let counters = render_frame(&mut sequential, &pose);
+        if render_frame(&mut parallel, &pose) != counters {
+            return Err(format!("{name}: the counters differ between 1 and 3 threads"));
+        }
+        if parallel.orchestrator.frame_buffer_out != sequential.orchestrator.frame_buffer_out {
+            return Err(format!("{name}: the frames differ between 1 and 3 threads"));
+        }
+
+        check(&format!("vtu_{name}"), &to_image(&parallel.orchestrator.frame_buffer_out, parallel.orchestrator.resolution()))
+    }));
+}
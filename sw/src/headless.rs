@@ -0,0 +1,371 @@
+//! Renders frames without opening a window, so the simulator can run in scripts and on
+//! machines without a display. Everything here is deterministic: the same poses always
+//! produce the same images and the same cycle counts.
+
+use std::{fmt::Write as _, fs, io::BufWriter, path::PathBuf};
+
+use nannou::image::RgbImage;
+
+use crate::{cache::{DdrTiming, Organization, Replacement, Window}, camera::Pose, config::Config, counters::FrameCounters, display::{Buffering, VideoTiming}, math::{overflow::{self, Kind, Summary}, Rgb565}, orchestrator::Resolution, serial::{self, VirtualSerial}, stream::{self, Streamer, UartLink}, texture::TextureAtlas, top_level::TopLevel, trace::Vcd, vtu::RenderDistance, world::{World, WorldOptions}};
+
+pub const USAGE: &str = "\
+usage: fpgacraft render [options]
+
+options:
+    --pose \"X Y Z YAW [PITCH [ROLL]]\"
+                                 Camera pose, angles in degrees (default: \"0 0 0 0 0 0\")
+    --scene FILE                 File with one pose per line, rendered in order
+    --frames N                   Number of frames to render (default: one per pose)
+    --out DIR                    Where to write images and stats (default: render)
+    --fov DEGREES                Vertical field of view (default: 90)
+    --resolution WIDTHxHEIGHT    Size of the frames (default: 160x128)
+    --vtus N                     Number of VTUs tracing rays in parallel, each with its own
+                                 L2 port (default: 1)
+    --threads N                  Clock the VTUs on N threads, which gives the same
+                                 results, only sooner with many VTUs (default: 1)
+    --format png|ppm             Image format (default: png)
+    --stats csv|json             Format of the per-frame performance counters, written to
+                                 stats.csv or stats.json (default: csv)
+    --ddr instant|FIELD=N,...    DDR timing behind the L3 cache, either no latency at all
+                                 or overrides of the DDR3 defaults. Fields: read_latency,
+                                 row_miss_penalty, burst_cycles, row_size, max_outstanding,
+                                 queue_depth
+    --l2-entries N               Size of the L2 cache (default: 16)
+    --l2-organization ORG        full, direct or N-way (default: full)
+    --l2-replacement POLICY      fifo, lru, plru or random (default: fifo)
+    --l1-entries N               Size of each VTU's private L1 cache, 0 for none (default: 0)
+    --shadows CYCLES             Trace a shadow ray towards the sun after every hit, giving
+                                 up after CYCLES cycles (default: 0, no shadows)
+    --render-distance FIELD=N,...
+                                 How far rays go before showing the sky, whichever limit
+                                 comes first. Fields: steps (voxels, default: 110) and t
+                                 (blocks from the camera, default: no limit)
+    --textures builtin|FILE      Texture the blocks, with the builtin atlas or a PNG with
+                                 3 tiles of 16x16 per row (top, side, bottom) and one row
+                                 per block id (default: flat colours)
+    --world PATH                 Stream the world from a dump or a directory of dumps (see
+                                 README) into the L3 cache as the camera moves, rather than
+                                 only having the checked-in chunk.bin
+    --anvil DIR                  Same, from a box of a Minecraft 1.16 (Anvil) world
+    --anvil-center \"X Y Z\"       Block of the Anvil world that ends up at the origin
+                                 (default: \"0 64 0\")
+    --anvil-size N               Size of the box read out of the Anvil world (default: 64)
+    --serial ADDR                Be a virtual FPGA for the plugin: listen on ADDR (e.g.
+                                 127.0.0.1:7878) and write the chunks it sends into the
+                                 L3 cache, see README. Frames come out as fast as they
+                                 render, so they depend on when the plugin sends
+    --baud N                     Speed of the UART that streams the world, or that the
+                                 plugin's bytes come in at (default: 115200)
+    --display single|double|immediate
+                                 Scan the framebuffer out to HDMI like vsg.sv, with one
+                                 buffer, two swapped on vsync, or two swapped as soon as
+                                 a frame is done. Also writes what the monitor showed
+                                 when each frame was done, as screen_NNNN
+    --video FIELD=N,...          Overrides of the 720p60 HDMI timing, see README
+    --check-overflow             Record every fixed-point operation that overflows (wraps
+                                 around) or underflows (a product truncated to zero), and
+                                 where, to overflow.csv. Without it, additions wrap silently
+                                 and multiplications panic
+    --vcd FILE                   Record the inputs/outputs of every module, every cycle, to
+                                 a VCD file. These get big quickly, see --vcd-cycles
+    --vcd-cycles N               Only record the first N cycles (default: all)";
+
+#[derive(Debug)]
+struct Options {
+    poses: Vec<Pose>,
+    frames: Option<usize>,
+    out: PathBuf,
+    format: &'static str,
+    stats: &'static str,
+    vcd: Option<PathBuf>,
+    vcd_cycles: Option<u64>,
+    check_overflow: bool,
+    world: Option<World>,
+    serial: Option<String>,
+    link: UartLink,
+    config: Config,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut poses = vec![Pose::default()];
+        let mut frames = None;
+        let mut out = PathBuf::from("render");
+        let mut format = "png";
+        let mut stats = "csv";
+        let mut vcd = None;
+        let mut vcd_cycles = None;
+        let mut check_overflow = false;
+        let mut world = WorldOptions::default();
+        let mut serial = None;
+        let mut link = UartLink::default();
+        let mut config = Config::default();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args
+                .next()
+                .ok_or_else(|| format!("missing value for {arg}"));
+
+            match arg.as_str() {
+                "--pose" => poses = vec![Pose::parse(value()?)?],
+                "--scene" => {
+                    let path = value()?;
+                    let scene = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+
+                    poses = Pose::parse_scene(&scene)?;
+                    if poses.is_empty() {
+                        return Err(format!("scene {path} has no poses"));
+                    }
+                },
+                "--frames" => frames = Some(value()?.parse().map_err(|e| format!("invalid frame count: {e}"))?),
+                "--out" => out = value()?.into(),
+                "--format" => format = match value()?.as_str() {
+                    "png" => "png",
+                    "ppm" => "ppm",
+                    f => return Err(format!("unsupported image format {f:?}")),
+                },
+                "--stats" => stats = match value()?.as_str() {
+                    "csv" => "csv",
+                    "json" => "json",
+                    f => return Err(format!("unsupported stats format {f:?}")),
+                },
+                "--vcd" => vcd = Some(value()?.into()),
+                "--vcd-cycles" => vcd_cycles = Some(value()?.parse().map_err(|e| format!("invalid cycle count: {e}"))?),
+                "--check-overflow" => check_overflow = true,
+                arg if world.parse_arg(arg, &mut value)? => (),
+                "--serial" => serial = Some(value()?.clone()),
+                "--baud" => link.baud = value()?.parse().map_err(|e| format!("invalid baud rate: {e}"))?,
+                "--fov" => config.fov = value()?.parse().map_err(|e| format!("invalid field of view: {e}"))?,
+                "--resolution" => config.resolution = Resolution::parse(value()?)?,
+                "--vtus" => config.vtus = value()?.parse().map_err(|e| format!("invalid number of VTUs: {e}"))?,
+                "--threads" => config.threads = value()?.parse().map_err(|e| format!("invalid number of threads: {e}"))?,
+                "--ddr" => config.ddr = DdrTiming::parse(value()?)?,
+                "--l2-entries" => config.l2_entries = value()?.parse().map_err(|e| format!("invalid L2 size: {e}"))?,
+                "--l2-organization" => config.l2_organization = Organization::parse(value()?)?,
+                "--l2-replacement" => config.l2_replacement = Replacement::parse(value()?)?,
+                "--l1-entries" => config.l1_entries = value()?.parse().map_err(|e| format!("invalid L1 size: {e}"))?,
+                "--shadows" => config.shadow_cycles = value()?.parse().map_err(|e| format!("invalid shadow budget: {e}"))?,
+                "--render-distance" => config.render_distance = RenderDistance::parse(value()?)?,
+                "--display" => config.display = Some(Buffering::parse(value()?)?),
+                "--video" => config.video = VideoTiming::parse(value()?)?,
+                "--textures" => config.textures = Some(match value()?.as_str() {
+                    "builtin" => TextureAtlas::builtin(),
+                    path => TextureAtlas::load(path.as_ref())?,
+                }),
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+
+        if link.baud == 0 {
+            return Err("baud rate must be at least 1".into());
+        }
+        config.validate()?;
+        if check_overflow && config.threads > 1 {
+            return Err("--check-overflow only sees the simulation's own thread, it needs --threads 1".into());
+        }
+
+        // The FPGA starts with the window around the first pose, as if it had been
+        // configured while the camera was there
+        let world = world.load()?;
+        if world.is_some() && serial.is_some() {
+            return Err("the window comes either from --world/--anvil or from --serial, not both".into());
+        }
+        if let Some(world) = &world {
+            config.window = Window::from_world(world, poses[0].position.floor());
+        }
+
+        Ok(Self { poses, frames, out, format, stats, vcd, vcd_cycles, check_overflow, world, serial, link, config })
+    }
+}
+
+/// Entry point of `fpgacraft render`
+pub fn main(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let opts = Options::parse(args)?;
+    let frames = opts.frames.unwrap_or(opts.poses.len());
+
+    fs::create_dir_all(&opts.out).map_err(|e| format!("couldn't create {}: {e}", opts.out.display()))?;
+
+    if opts.check_overflow {
+        overflow::enable();
+    }
+
+    let mut streamer = opts.world.map(|world| Streamer::new(world, &opts.config.window, opts.link));
+    let mut serial = opts.serial.map(|addr| VirtualSerial::listen(&addr, opts.link)).transpose()?;
+    let (display, video, resolution) = (opts.config.display, opts.config.video, opts.config.resolution);
+    let mut top_level = reset_top_level(opts.config);
+    let mut counters = Vec::with_capacity(frames);
+    let mut overflows = String::from("frame,module,index,kind,op,count,first_pixel,first_x,first_y,first_step,lhs,rhs\n");
+
+    let mut vcd = match &opts.vcd {
+        Some(path) => {
+            let file = fs::File::create(path).map_err(|e| format!("couldn't create {}: {e}", path.display()))?;
+            let vcd = Vcd::new(BufWriter::new(file));
+
+            Some(match opts.vcd_cycles {
+                Some(cycles) => vcd.with_max_cycles(cycles),
+                None => vcd,
+            })
+        },
+        None => None,
+    };
+
+    for (i, pose) in opts.poses.iter().cycle().take(frames).enumerate() {
+        counters.push(match (&mut vcd, &mut streamer, &mut serial) {
+            (None, None, None) => render_frame(&mut top_level, pose),
+            (vcd, streamer, serial) => render_frame_with(&mut top_level, pose, |top_level| {
+                if let Some(vcd) = vcd {
+                    vcd.sample(&*top_level);
+                }
+                if let Some(streamer) = streamer {
+                    stream::clock(streamer, top_level);
+                }
+                if let Some(serial) = serial {
+                    serial::clock(serial, top_level);
+                }
+            }),
+        });
+
+        if let Some(streamer) = &streamer {
+            let counters = &counters[i].l3;
+            eprintln!(
+                "frame {i}: window slid {} times, {} voxels streamed, {} slices behind",
+                counters.window_shifts, counters.window_writes, streamer.backlog(),
+            );
+        }
+        if let Some(serial) = &serial {
+            eprintln!(
+                "frame {i}: {} voxels received, {} framing errors so far, {} bytes behind",
+                counters[i].l3.window_writes, serial.framing_errors_out, serial.backlog(),
+            );
+        }
+
+        if opts.check_overflow {
+            let summaries = overflow::take();
+            write_overflows(&mut overflows, i, resolution, &summaries);
+
+            let count = |kind| summaries.iter().filter(|s| s.kind == kind).map(|s| s.count).sum::<u64>();
+            eprintln!("frame {i}: {} overflows, {} underflows", count(Kind::Overflow), count(Kind::Underflow));
+        }
+
+        let path = opts.out.join(format!("frame_{i:04}.{}", opts.format));
+        to_image(&top_level.orchestrator.frame_buffer_out, resolution)
+            .save(&path)
+            .map_err(|e| format!("couldn't write {}: {e}", path.display()))?;
+
+        if let Some(display) = &top_level.display {
+            let path = opts.out.join(format!("screen_{i:04}.{}", opts.format));
+            to_image(&display.screen_out, resolution)
+                .save(&path)
+                .map_err(|e| format!("couldn't write {}: {e}", path.display()))?;
+        }
+    }
+
+    if display.is_some() {
+        let cycles = counters.iter().map(|c| c.orchestrator.cycles).sum::<usize>();
+        let refreshes = counters.iter().map(|c| c.display.refreshes).sum::<usize>();
+        let torn = counters.iter().map(|c| c.display.torn_refreshes).sum::<usize>();
+        let fps = counters.len() as f32 * video.clock_hz as f32 / cycles as f32;
+
+        eprintln!(
+            "{} frames at {fps:.2} fps on a {:.2} Hz display, {torn} of {refreshes} refreshes torn",
+            counters.len(), video.refresh_hz(),
+        );
+    }
+
+    if let (Some(vcd), Some(path)) = (vcd, &opts.vcd) {
+        vcd.finish().map_err(|e| format!("couldn't write {}: {e}", path.display()))?;
+    }
+
+    if opts.check_overflow {
+        let path = opts.out.join("overflow.csv");
+        fs::write(&path, overflows).map_err(|e| format!("couldn't write {}: {e}", path.display()))?;
+    }
+
+    let stats = match opts.stats {
+        "json" => FrameCounters::to_json(&counters),
+        _ => FrameCounters::to_csv(&counters),
+    };
+    let path = opts.out.join(format!("stats.{}", opts.stats));
+    fs::write(&path, stats).map_err(|e| format!("couldn't write {}: {e}", path.display()))
+}
+
+/// One line of `overflow.csv` per [Summary] of frame `frame`
+fn write_overflows(csv: &mut String, frame: usize, resolution: Resolution, summaries: &[Summary]) {
+    let optional = |x: Option<usize>| x.map_or(String::new(), |x| x.to_string());
+
+    for s in summaries {
+        let pixel = s.first.pixel;
+        writeln!(
+            csv,
+            "{frame},{},{},{},{},{},{},{},{},{},{},{}",
+            s.module,
+            optional(s.index),
+            s.kind,
+            s.op,
+            s.count,
+            optional(pixel),
+            optional(pixel.map(|p| p % resolution.width)),
+            optional(pixel.map(|p| p / resolution.width)),
+            optional(s.first.step),
+            s.operands.0,
+            s.operands.1,
+        ).unwrap();
+    }
+}
+
+/// A [TopLevel] that went through a reset cycle and is ready to render its first frame
+pub fn reset_top_level(config: Config) -> TopLevel {
+    let mut top_level = TopLevel {
+        reset: true,
+        ..TopLevel::new(config)
+    };
+
+    top_level.rising_clk_edge();
+    top_level.reset = false;
+    top_level
+}
+
+/// Renders one frame from `pose`, clocking `top_level` until the frame is done. The frame
+/// is left in [crate::orchestrator::Orchestrator::frame_buffer_out], and what was counted while rendering it is
+/// returned.
+///
+/// `top_level` must be in between frames, i.e. freshly reset or just done with a frame.
+pub fn render_frame(top_level: &mut TopLevel, pose: &Pose) -> FrameCounters {
+    render_frame_with(top_level, pose, |_| ())
+}
+
+/// Like [render_frame], calling `on_cycle` after every clock edge, e.g. to record waveforms
+/// or drive more inputs
+pub fn render_frame_with(top_level: &mut TopLevel, pose: &Pose, mut on_cycle: impl FnMut(&mut TopLevel)) -> FrameCounters {
+    // Inputs get latched on the first cycle of the frame
+    top_level.orchestrator.camera_pos_in = pose.position;
+    top_level.orchestrator.camera_heading_in = pose.heading();
+    top_level.orchestrator.camera_up_in = pose.up();
+
+    let mut cycles = 0;
+    while !top_level.orchestrator.frame_done_out || cycles == 0 {
+        top_level.rising_clk_edge();
+        on_cycle(top_level);
+        cycles += 1;
+    }
+
+    let counters = top_level.orchestrator.counters_out.clone();
+    debug_assert_eq!(counters.orchestrator.cycles, cycles);
+    counters
+}
+
+/// Converts a frame buffer of frames at `resolution` to an 8-bit RGB image
+pub fn to_image(frame: &[Rgb565], resolution: Resolution) -> RgbImage {
+    let data = frame
+        .iter()
+        .flat_map(|rgb| [rgb.r(), rgb.g(), rgb.b()])
+        .collect();
+
+    RgbImage::from_raw(resolution.width as _, resolution.height as _, data).unwrap()
+}
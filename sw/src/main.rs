@@ -5,18 +5,69 @@ mod vtu;
 mod math;
 mod block;
 mod cache;
+mod camera;
+mod characterize;
+mod config;
+mod cosim;
+mod counters;
+mod debugger;
+mod display;
+mod formats;
+mod headless;
+mod texture;
+mod trace;
 mod top_level;
 mod orchestrator;
+mod parallel;
+mod anvil;
+mod serial;
+mod stream;
+mod sweep;
+mod world;
+#[cfg(test)]
+mod golden;
 
+use std::f32::consts::FRAC_PI_2;
+
+use camera::Pose;
+use config::Config;
+use debugger::Debugger;
+use display::Buffering;
 use math::Vec3;
-use nannou::{image::{DynamicImage, ImageBuffer}, prelude::*, winit::dpi::PhysicalPosition};
+use nannou::{image::DynamicImage, prelude::*, winit::dpi::PhysicalPosition};
 use orchestrator::Orchestrator;
+use serial::VirtualSerial;
+use stream::{Streamer, UartLink};
+use texture::TextureAtlas;
 use top_level::TopLevel;
+use world::{World, WorldOptions};
 
 const WIDTH: usize = Orchestrator::FRAME_WIDTH;
 const HEIGHT: usize = Orchestrator::FRAME_HEIGHT;
 
+/// Entry point of a subcommand, given the arguments after its name
+type Subcommand = fn(&[String]) -> Result<(), String>;
+
 fn main() {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+
+    // `fpgacraft render ...` and the other subcommands run without a window
+    let subcommand: Option<(Subcommand, &str)> = match args.first().map(String::as_str) {
+        Some("render") => Some((headless::main, headless::USAGE)),
+        Some("cosim") => Some((cosim::main, cosim::USAGE)),
+        Some("formats") => Some((formats::main, formats::USAGE)),
+        Some("characterize") => Some((characterize::main, characterize::USAGE)),
+        Some("sweep") => Some((sweep::main, sweep::USAGE)),
+        _ => None,
+    };
+    if let Some((main, usage)) = subcommand {
+        if let Err(e) = main(&args[1..]) {
+            eprintln!("error: {e}\n\n{usage}");
+            std::process::exit(1);
+        }
+        return;
+    }
+
     nannou::app(model)
         .update(update)
         .run();
@@ -25,8 +76,19 @@ fn main() {
 #[derive(Debug, Default)]
 struct Model {
     top_level: TopLevel,
+    /// Streams the world into the L3 cache, with `--world`
+    streamer: Option<Streamer>,
+    /// Takes chunks from the plugin into the L3 cache, with `--serial`
+    serial: Option<VirtualSerial>,
+    /// What [Model::top_level] was built with, for the ray debugger
+    config: Config,
+    /// Debug mode, `D` to toggle: the simulation stops, and clicking a pixel traces its ray
+    debugger: Option<Debugger>,
     input: (f32, f32, f32),
-    heading: (f32, f32),
+    /// Yaw, pitch and roll, in radians
+    heading: (f32, f32, f32),
+    /// Roll speed, from the keyboard
+    roll: f32,
     velocity: Vec3,
 }
 
@@ -39,22 +101,88 @@ fn model(app: &App) -> Model {
         .key_pressed(key_pressed)
         .key_released(key_released)
         .mouse_wheel(mouse_wheel)
+        .mouse_pressed(mouse_pressed)
         .build()
         .unwrap();
 
-    let mut top_level = TopLevel::default();
+    // `fpgacraft --world PATH` (or `--anvil DIR`) streams a world in as the camera moves,
+    // and `fpgacraft --serial ADDR` takes chunks from the plugin instead
+    let mut config = Config {
+        textures: Some(TextureAtlas::builtin()),
+        ..Default::default()
+    };
+    let (world, serial) = parse_args(&mut config).unwrap_or_else(|e| {
+        eprintln!("error: {e}\n\nsee the --world, --anvil, --serial, --baud, --display and --threads options of `fpgacraft render --help`");
+        std::process::exit(1);
+    });
 
-    top_level.reset = true;
-    top_level.rising_clk_edge();
-    top_level.reset = false;
+    if let Some(world) = &world {
+        config.window = cache::Window::from_world(world, Default::default());
+    }
 
     Model {
-        top_level,
+        streamer: world.map(|world| Streamer::new(world, &config.window, Default::default())),
+        serial,
+        top_level: headless::reset_top_level(config.clone()),
+        config,
         ..Default::default()
     }
 }
 
+/// World given on the command line, or the virtual FPGA's link to the plugin, if any. How
+/// the framebuffers are scanned out goes straight into `config`
+fn parse_args(config: &mut Config) -> Result<(Option<World>, Option<VirtualSerial>), String> {
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut options = WorldOptions::default();
+    let mut serial = None;
+    let mut link = UartLink::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args
+            .next()
+            .ok_or_else(|| format!("missing value for {arg}"));
+
+        match arg.as_str() {
+            arg if options.parse_arg(arg, &mut value)? => (),
+            "--serial" => serial = Some(value()?.clone()),
+            "--baud" => link.baud = value()?.parse().map_err(|e| format!("invalid baud rate: {e}"))?,
+            "--display" => config.display = Some(Buffering::parse(value()?)?),
+            "--threads" => config.threads = value()?.parse().map_err(|e| format!("invalid number of threads: {e}"))?,
+            _ => return Err(format!("unknown argument {arg:?}")),
+        }
+    }
+
+    if link.baud == 0 {
+        return Err("baud rate must be at least 1".into());
+    }
+    let world = options.load()?;
+    if world.is_some() && serial.is_some() {
+        return Err("the window comes either from --world/--anvil or from --serial, not both".into());
+    }
+
+    let serial = serial.map(|addr| VirtualSerial::listen(&addr, link)).transpose()?;
+    Ok((world, serial))
+}
+
 fn update(_: &App, model: &mut Model, update: Update) {    
+    // Nothing moves while debugging, once the frame on screen is one that rays can be cast
+    // from again: the current one might have started before the camera last moved
+    if let Some(debugger) = &mut model.debugger {
+        if !debugger.settled {
+            debugger.settled = true;
+
+            while !model.top_level.orchestrator.frame_done_out {
+                clock(model);
+            }
+            clock(model);
+            while !model.top_level.orchestrator.frame_done_out {
+                clock(model);
+            }
+        }
+        return;
+    }
+
     // First person camera    
     let speed = fixed!(8.0);
     let dir = Vec3 {
@@ -66,32 +194,75 @@ fn update(_: &App, model: &mut Model, update: Update) {
 
     model.velocity = fixed!(0.3) * model.velocity + fixed!(0.7) * dir;
     model.top_level.orchestrator.camera_pos_in += speed * model.velocity * dt;
+
+    if model.roll != 0.0 {
+        model.heading.2 += model.roll * update.since_last.as_secs_f32();
+        update_heading(model);
+    }
     
     // Step "FPGA" loop
     let mut i = 0;
     let start = std::time::Instant::now();
     
     while !model.top_level.orchestrator.frame_done_out {
-        model.top_level.rising_clk_edge();
+        clock(model);
         i += 1;
     }
     while model.top_level.orchestrator.frame_done_out {
-        model.top_level.rising_clk_edge();
+        clock(model);
     }
 
     let duration = std::time::Instant::now() - start;
 
-    println!("Took {i} cycles, simulation ran for {}ms", duration.as_millis());
+    let counters = &model.top_level.orchestrator.counters_out;
+    println!(
+        "Took {i} cycles ({:.1}% L2 hit ratio), simulation ran for {}ms",
+        counters.l2.total().hit_ratio() * 100.0,
+        duration.as_millis(),
+    );
+}
+
+/// Clocks the "FPGA", and whatever feeds its L3 cache
+fn clock(model: &mut Model) {
+    model.top_level.rising_clk_edge();
+    if let Some(streamer) = &mut model.streamer {
+        stream::clock(streamer, &mut model.top_level);
+    }
+    if let Some(serial) = &mut model.serial {
+        serial::clock(serial, &mut model.top_level);
+    }
 }
 
 fn key_pressed(_: &App, model: &mut Model, key: Key) {
+    if let Some(debugger) = &mut model.debugger {
+        let steps = debugger.trace.as_ref().map_or(0, |trace| trace.steps.len());
+
+        match key {
+            Key::Up => debugger.scroll = debugger.scroll.saturating_sub(1),
+            Key::Down => debugger.scroll = (debugger.scroll + 1).min(steps.saturating_sub(1)),
+            Key::PageUp => debugger.scroll = debugger.scroll.saturating_sub(10),
+            Key::PageDown => debugger.scroll = (debugger.scroll + 10).min(steps.saturating_sub(1)),
+            Key::D => model.debugger = None,
+            _ => {}
+        }
+        return;
+    }
+
     match key {
+        Key::D => {
+            model.debugger = Some(Debugger::default());
+            model.input = Default::default();
+            model.roll = 0.0;
+            model.velocity = Vec3::default();
+        },
         Key::I => model.input.2 = -1.0,
         Key::K => model.input.2 = 1.0,
         Key::L => model.input.0 = 1.0,
         Key::J => model.input.0 = -1.0,
         Key::Space => model.input.1 = 1.0,
         Key::M => model.input.1 = -1.0,
+        Key::U => model.roll = -1.0,
+        Key::O => model.roll = 1.0,
         _ => {}
     }
 }
@@ -101,42 +272,69 @@ fn key_released(_: &App, model: &mut Model, key: Key) {
         Key::I | Key::K => model.input.2 = 0.0,
         Key::L | Key::J => model.input.0 = 0.0,
         Key::Space | Key::M => model.input.1 = 0.0,
+        Key::U | Key::O => model.roll = 0.0,
         _ => {}
     }
 }
 
+fn mouse_pressed(app: &App, model: &mut Model, button: MouseButton) {
+    let Some(debugger) = &mut model.debugger else {
+        return;
+    };
+    if button != MouseButton::Left || !debugger.settled {
+        return;
+    }
+
+    // Window coordinates are centred, with Y up
+    let x = (app.mouse.x + (WIDTH * 3) as f32 / 2.0) / 3.0;
+    let y = ((HEIGHT * 3) as f32 / 2.0 - app.mouse.y) / 3.0;
+    if !(0.0..WIDTH as f32).contains(&x) || !(0.0..HEIGHT as f32).contains(&y) {
+        return;
+    }
+
+    let pixel = (y as usize) * WIDTH + (x as usize);
+    let trace = debugger::cast(&model.top_level.orchestrator, &model.config, pixel);
+    println!("{trace}");
+
+    debugger.trace = Some(trace);
+    debugger.scroll = 0;
+}
+
 fn mouse_wheel(_: &App, model: &mut Model, scroll: MouseScrollDelta, _: TouchPhase) {
     let MouseScrollDelta::PixelDelta(PhysicalPosition { x, y }) = scroll else {
         return;
     };
+    if model.debugger.is_some() {
+        return;
+    }
     
     model.heading.0 += (x as f32) * 0.005;
-    // model.heading.1 += (y as f32) * 0.005;
+    model.heading.1 = (model.heading.1 + (y as f32) * 0.005).clamp(-FRAC_PI_2, FRAC_PI_2);
 
-    let x = model.heading.0.cos() * model.heading.1.cos();
-    let y = model.heading.1.sin();
-    let z = model.heading.0.sin() * model.heading.1.cos();
-    let m = (x*x + y*y + z*z).sqrt();
+    update_heading(model);
+}
 
-    model.top_level.orchestrator.camera_heading_in = Vec3 {
-        x: fixed!(x / m),
-        y: fixed!(y / m),
-        z: fixed!(z / m),
+fn update_heading(model: &mut Model) {
+    let pose = Pose {
+        yaw: model.heading.0,
+        pitch: model.heading.1,
+        roll: model.heading.2,
+        ..Default::default()
     };
+
+    model.top_level.orchestrator.camera_heading_in = pose.heading();
+    model.top_level.orchestrator.camera_up_in = pose.up();
 }
 
 fn view(app: &App, model: &Model, frame: Frame) {
-    // Get the frame from the FPGA
-    let frame_out = &model.top_level.orchestrator.frame_buffer_out;
+    // Get the frame from the FPGA, as it's on the HDMI output if the display is modelled
+    let frame_out = match &model.top_level.display {
+        Some(display) => &display.screen_out,
+        None => &model.top_level.orchestrator.frame_buffer_out,
+    };
 
     // Convert to something WGPU can use (rgb565 -> rgb8)
-    let frame_wgpu = frame_out
-        .iter()
-        .flat_map(|rgb| [rgb.r(), rgb.g(), rgb.b()])
-        .map(|n| n as u8)
-        .collect::<Vec<_>>();
-    let frame_wgpu = ImageBuffer::from_vec(WIDTH as _, HEIGHT as _, frame_wgpu).unwrap();
-    let frame_wgpu = DynamicImage::ImageRgb8(frame_wgpu);
+    let frame_wgpu = DynamicImage::ImageRgb8(headless::to_image(frame_out, model.top_level.orchestrator.resolution()));
     let texture = wgpu::Texture::from_image(app, &frame_wgpu);
 
     // Draw onto the screen
@@ -145,5 +343,9 @@ fn view(app: &App, model: &Model, frame: Frame) {
     frame.clear(BLACK);
     draw.texture(&texture).w((WIDTH * 3) as _).h((HEIGHT * 3) as _);
 
+    if let Some(Debugger { trace: Some(trace), scroll, .. }) = &model.debugger {
+        trace.draw(&draw, (WIDTH * 3) as _, (HEIGHT * 3) as _, 3.0, *scroll);
+    }
+
     draw.to_frame(app, &frame).unwrap();
 }
\ No newline at end of file
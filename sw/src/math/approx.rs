@@ -0,0 +1,128 @@
+//! The LUT + Newton's method approximations of [FixedPoint::inv_sqrt] and
+//! [FixedPoint::recip_lte1], with the size of their LUT and their number of Newton steps as
+//! parameters, so that other sizes can be characterized before changing `fixed.sv`.
+
+use super::FixedPoint;
+
+/// Inverse square root: a LUT indexed by the number of leading zeros (and optionally the
+/// bits after the leading one), then Newton steps.
+///
+/// <https://www.shironekolabs.com/posts/efficient-approximate-square-roots-and-division-in-verilog/>
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InvSqrt {
+    /// Bits after the leading one that also index the LUT. With 0, each entry is for the
+    /// middle of an octave
+    pub mantissa_bits: u32,
+    /// Newton steps after the LUT
+    pub iterations: u32,
+}
+
+/// Reciprocal of numbers in `(0, 1]`: a LUT indexed by the top fractional bits of the
+/// absolute value, then Newton steps
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RecipLte1 {
+    /// Fractional bits that index the LUT, which has `2^lut_bits` entries
+    pub lut_bits: u32,
+    /// Newton steps after the LUT
+    pub iterations: u32,
+}
+
+impl InvSqrt {
+    /// What `fixed_inv_sqrt` does: one entry per number of leading zeros, one Newton step
+    pub const HARDWARE: Self = Self { mantissa_bits: 0, iterations: 1 };
+
+    /// Number of entries in the LUT of a `B`-bit format
+    pub fn lut_size<const B: u32>(&self) -> usize {
+        (B as usize) << self.mantissa_bits
+    }
+
+    /// Every entry of the LUT, in order of address: the number of leading zeros of the
+    /// input, then the bits after its leading one
+    pub fn lut<const B: u32, const D: u32>(&self) -> Vec<FixedPoint<B, D>> {
+        (0..B)
+            .flat_map(|lz| (0..1 << self.mantissa_bits).map(move |m| (lz, m)))
+            .map(|(lz, m)| self.entry(lz, m))
+            .collect()
+    }
+
+    pub fn eval<const B: u32, const D: u32>(&self, x: FixedPoint<B, D>) -> FixedPoint<B, D> {
+        // return FixedPoint::from_f32(1.0 / x.to_f32().sqrt());
+
+        // First iteration (LUT). Zero gets the same entry as the smallest number, like
+        // the `default` of the HDL's LUT
+        let lz = x.leading_zeros().min(B - 1);
+        let p = B - 1 - lz;
+        let raw = i64::from(x.to_raw());
+        let m = match p >= self.mantissa_bits {
+            true => raw >> (p - self.mantissa_bits),
+            false => raw << (self.mantissa_bits - p),
+        };
+        let mut iter = self.entry(lz, (m & ((1 << self.mantissa_bits) - 1)) as u32);
+
+        // Next iterations (Newton's method)
+        // x(n+1) = x(n) * (1.5 - (0.5 * val * x(n)^2))
+        let half = FixedPoint::from_raw(x.to_raw() >> 1);
+        for _ in 0..self.iterations {
+            iter = iter * (FixedPoint::from_f32(1.5) - (half * (iter * iter)));
+        }
+
+        iter
+    }
+
+    /// Entry for numbers with `lz` leading zeros and `m` as the bits after their leading
+    /// one: the inverse square root of the middle of that range
+    fn entry<const B: u32, const D: u32>(&self, lz: u32, m: u32) -> FixedPoint<B, D> {
+        let k = self.mantissa_bits;
+        let p = B - 1 - lz;
+
+        // (1 + (m + 0.5) / 2^k) * 2^p, which is 0b11 << (p - 1) without mantissa bits. Too
+        // big for the format when there are no leading zeros, but then the input is negative
+        let middle = ((i64::from(1u32 << (k + 1) | m << 1 | 1)) << p) >> (k + 1);
+        let middle = FixedPoint::<B, D>::from_raw(middle as i32);
+
+        FixedPoint::from_f32(1.0 / middle.to_f32().sqrt())
+    }
+}
+
+impl RecipLte1 {
+    /// What `fixed_recip_lte1` does: 64 entries, one Newton step
+    pub const HARDWARE: Self = Self { lut_bits: 6, iterations: 1 };
+
+    /// Number of entries in the LUT
+    pub fn lut_size(&self) -> usize {
+        1 << self.lut_bits
+    }
+
+    /// Every entry of the LUT, in order of address
+    pub fn lut<const B: u32, const D: u32>(&self) -> Vec<FixedPoint<B, D>> {
+        (0..self.lut_size() as i32).map(|i| self.entry(i)).collect()
+    }
+
+    pub fn eval<const B: u32, const D: u32>(&self, x: FixedPoint<B, D>) -> FixedPoint<B, D> {
+        // return FixedPoint::from_f32(1.0 / x.to_f32());
+
+        // First iteration (LUT)
+        let index = (x.abs().to_raw() >> (D - self.lut_bits)) & (self.lut_size() as i32 - 1);
+        let mut iter = FixedPoint::from_raw(self.entry::<B, D>(index).to_raw().wrapping_mul(x.to_raw().signum()));
+
+        // Next iterations (Newton's method)
+        // x(n+1) = 2*x(n) - val * x(n)^2
+        // ORDER MATTERS for multiplication to avoid overflows
+        for _ in 0..self.iterations {
+            iter = FixedPoint::from_raw(iter.to_raw() << 1) - (iter * (x * iter));
+        }
+
+        iter
+    }
+
+    /// Entry for numbers whose top fractional bits are `i`, and 1 for 0 (i.e. 1.0)
+    fn entry<const B: u32, const D: u32>(&self, i: i32) -> FixedPoint<B, D> {
+        assert!(self.lut_bits <= D, "the LUT can't be indexed by more than the {D} fractional bits");
+
+        if i == 0 {
+            FixedPoint::from_f32(1.0)
+        } else {
+            FixedPoint::from_f32(1.0 / FixedPoint::<B, D>::from_raw(i << (D - self.lut_bits)).to_f32())
+        }
+    }
+}
@@ -1,179 +1,220 @@
 use std::{fmt, ops};
 
-use aint::Aint;
+use super::{approx::{InvSqrt, RecipLte1}, overflow::{self, Kind}};
 
-/// Fixed point number
+/// Signed fixed point number of `B` bits in total, `D` of which are fractional, as in
+/// `fixed.sv`. It behaves like a `B`-bit register: additions and subtractions wrap around,
+/// while multiplications that overflow panic. Unless [overflow] checking is enabled, in
+/// which case everything wraps around and gets recorded.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
-pub struct Fixed(Repr);
+pub struct FixedPoint<const B: u32, const D: u32>(i32);
 
-/// Inner representation of [Fixed]
-pub type Repr = Aint<i32, {Fixed::B as _}>;
+/// Format used by the hardware
+pub type Fixed = FixedPoint<20, 8>;
 
 /// Converts a floating point to a fixed
 #[macro_export]
 macro_rules! fixed {
     ($x:expr) => {
-        {
-            let x: f32 = $x;
-            let f = (x * ((1 << crate::math::Fixed::D) as f32));
-            let i = crate::math::fixed::Repr::new_wrapping(f as i32);
-
-            crate::math::Fixed::from_raw(i)
-        }
-    };
-}
-
-macro_rules! f32 {
-    ($x:expr) => {
-        // Identity is used for type checking
-        (std::convert::identity::<Fixed>($x).0.repr() as f32) / ((1 << Fixed::D) as f32)
+        $crate::math::Fixed::from_f32($x)
     };
 }
 
-impl Default for Fixed {
+impl<const B: u32, const D: u32> Default for FixedPoint<B, D> {
     fn default() -> Self {
-        Self(Repr::new_wrapping(0))
+        Self(0)
     }
 }
 
-impl Fixed {
+impl<const B: u32, const D: u32> FixedPoint<B, D> {
     /// Total number of bits
-    pub const B: usize = 20;
+    pub const B: usize = B as _;
 
     /// Number of fractional bits
-    pub const D: usize = 8;
+    pub const D: usize = D as _;
+
+    pub const MAX: Self = Self(((1i64 << (B - 1)) - 1) as _);
+    pub const MIN: Self = Self((-1i64 << (B - 1)) as _);
 
-    pub const MAX: Self = Self(Repr::MAX);
-    pub const MIN: Self = Self(Repr::MIN);
+    /// Fixed from its bits, of which only the lower `B` are kept
+    pub const fn from_raw(x: i32) -> Self {
+        Self::wrapping(x as _)
+    }
+
+    /// Bits of the fixed, sign-extended
+    pub const fn to_raw(self) -> i32 {
+        self.0
+    }
 
-    pub const fn from_raw(x: Repr) -> Self {
-        Self(x)
+    /// Converts a floating point, truncating towards zero and wrapping around if it's out of range
+    pub const fn from_f32(x: f32) -> Self {
+        Self::wrapping((x * (1i64 << D) as f32) as i32 as _)
+    }
+
+    pub const fn to_f32(self) -> f32 {
+        self.0 as f32 / (1i64 << D) as f32
     }
 
     pub fn floor(self) -> i16 {
-        (self.0 >> Fixed::D).repr() as _
+        (self.0 >> D) as _
     }
 
     pub fn abs(self) -> Self {
-        Self(self.0.abs())
+        if self == Self::MIN {
+            let checking = overflow::report(Kind::Overflow, "abs", self.to_f32(), 0.0);
+            debug_assert!(checking, "attempt to calculate absolute value with overflow");
+        }
+        Self::wrapping(i64::from(self.0).abs())
     }
 
     /// Inverse square root, as would be implemented in hardware
     pub fn inv_sqrt(self) -> Self {
-        // return fixed!(1.0 / f32!(self).sqrt());
-        
-        // https://www.shironekolabs.com/posts/efficient-approximate-square-roots-and-division-in-verilog/
-        fn lut(i: u32) -> Fixed {
-            if i == (Fixed::B as u32) - 1 {
-                fixed!(1.0 / f32!(Fixed(Repr::new_wrapping(0b1))).sqrt())
-            } else {
-                fixed!(1.0 / f32!(Fixed(Repr::new_wrapping(0b11) << ((Fixed::B as u32) - 2 - i))).sqrt())
-            }
-        }
-
-        // First iteration (LUT):
-        let iter0 = lut(self.0.leading_zeros());
-
-        // Second and third iterations (Newton's method)
-        // x(n+1) = x(n) * (1.5 - (0.5 * val * x(n)^2))
-        let iter1 = iter0 * (fixed!(1.5) - (Fixed(self.0 >> 1) * (iter0 * iter0)));
-        // let iter2 = iter1 * (fixed!(1.5) - (Fixed(self.0 >> 1) * (iter1 * iter1)));
-
-        iter1
+        InvSqrt::HARDWARE.eval(self)
     }
 
     /// Reciprocal for 0 < value <= 1, as would be implemented in hardware
     pub fn recip_lte1(self) -> Self {
-        // return fixed!(1.0 / f32!(self));
-
-        // Emulate a size 64 LUT
-        fn lut(i: Repr) -> Fixed {
-            if i.repr() == 0 {
-                fixed!(1.0)
-            } else {
-                fixed!(1.0 / f32!(Fixed(i << (Fixed::D - 6))))
-            }
+        RecipLte1::HARDWARE.eval(self)
+    }
+
+    /// Number of leading zeros of the `B` bits, 0 for negative numbers
+    pub(super) fn leading_zeros(self) -> u32 {
+        if self.0 < 0 {
+            0
+        } else {
+            self.0.leading_zeros() - (32 - B)
         }
+    }
 
-        // First iteration (LUT)
-        let iter0 = Self(lut((self.0.abs() >> (Fixed::D - 6)) & Repr::new_wrapping(63)).0 * self.0.signum());
+    /// Keeps the lower `B` bits of `x`, like a `B`-bit register would
+    const fn wrapping(x: i64) -> Self {
+        const { assert!(B <= 32 && D >= 6 && D < B, "fixed point needs B <= 32 and 6 <= D < B") };
 
-        // Second iteration (Newton's method)
-        // x(n+1) = 2*x(n) - val * x(n)^2
-        // ORDER MATTERS for multiplication to avoid overflows
-        let iter1 = Fixed(iter0.0 << 1) - (iter0 * (self * iter0));
+        Self(((x << (64 - B)) >> (64 - B)) as _)
+    }
 
-        iter1
+    /// `x` if it fits in `B` bits
+    fn checked(x: i64) -> Option<Self> {
+        (x >= Self::MIN.0 as i64 && x <= Self::MAX.0 as i64).then_some(Self(x as _))
     }
 }
 
-impl ops::Add for Fixed {
+impl<const B: u32, const D: u32> ops::Add for FixedPoint<B, D> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
-        Self(self.0 + rhs.0)
+        let sum = i64::from(self.0) + i64::from(rhs.0);
+        if Self::checked(sum).is_none() {
+            let checking = overflow::report(Kind::Overflow, "add", self.to_f32(), rhs.to_f32());
+            debug_assert!(checking, "attempt to add with overflow");
+        }
+
+        Self::wrapping(sum)
     }
 }
 
-impl ops::Sub for Fixed {
+impl<const B: u32, const D: u32> ops::Sub for FixedPoint<B, D> {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        Self(self.0 - rhs.0)
+        let difference = i64::from(self.0) - i64::from(rhs.0);
+        if Self::checked(difference).is_none() {
+            let checking = overflow::report(Kind::Overflow, "sub", self.to_f32(), rhs.to_f32());
+            debug_assert!(checking, "attempt to subtract with overflow");
+        }
+
+        Self::wrapping(difference)
     }
 }
 
-impl ops::Mul for Fixed {
+impl<const B: u32, const D: u32> ops::Mul for FixedPoint<B, D> {
     type Output = Self;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        let a: Aint<i64, {Self::B as u32 * 2}> = self.0.repr().into();
-        let b: Aint<i64, {Self::B as u32 * 2}> = rhs.0.repr().into();
+        let wide = i64::from(self.0) * i64::from(rhs.0);
+        if wide != 0 && wide.abs() < 1 << D {
+            overflow::report(Kind::Underflow, "mul", self.to_f32(), rhs.to_f32());
+        }
 
-        Self(Repr::new(((a * b) >> Self::D).repr() as _).unwrap())
+        Self::checked(wide >> D).unwrap_or_else(|| {
+            let checking = overflow::report(Kind::Overflow, "mul", self.to_f32(), rhs.to_f32());
+            assert!(checking, "attempt to multiply with overflow");
+
+            Self::wrapping(wide >> D)
+        })
     }
 }
 
-impl ops::Neg for Fixed {
+impl<const B: u32, const D: u32> ops::Neg for FixedPoint<B, D> {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
-        Self(-self.0)
+        if self == Self::MIN {
+            let checking = overflow::report(Kind::Overflow, "neg", self.to_f32(), 0.0);
+            debug_assert!(checking, "attempt to negate with overflow");
+        }
+        Self::wrapping(-i64::from(self.0))
     }
 }
 
-impl ops::AddAssign for Fixed {
+impl<const B: u32, const D: u32> ops::AddAssign for FixedPoint<B, D> {
     fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs;
     }
 }
 
-impl ops::SubAssign for Fixed {
+impl<const B: u32, const D: u32> ops::SubAssign for FixedPoint<B, D> {
     fn sub_assign(&mut self, rhs: Self) {
         *self = *self - rhs;
     }
 }
 
-impl ops::MulAssign for Fixed {
+impl<const B: u32, const D: u32> ops::MulAssign for FixedPoint<B, D> {
     fn mul_assign(&mut self, rhs: Self) {
         *self = *self * rhs;
     }
 }
 
-impl From<i16> for Fixed {
+impl<const B: u32, const D: u32> From<i16> for FixedPoint<B, D> {
     fn from(value: i16) -> Self {
-        Self(Repr::new((value as i32) << Self::D).unwrap())
+        Self::checked(i64::from(value) << D).unwrap_or_else(|| {
+            let checking = overflow::report(Kind::Overflow, "from", value.into(), 0.0);
+            assert!(checking, "integer out of range of the fixed point format");
+
+            Self::wrapping(i64::from(value) << D)
+        })
     }
 }
 
-impl From<Fixed> for f32 {
-    fn from(value: Fixed) -> Self {
-        f32!(value)
+impl<const B: u32, const D: u32> From<FixedPoint<B, D>> for f32 {
+    fn from(value: FixedPoint<B, D>) -> Self {
+        value.to_f32()
     }
 }
 
-impl fmt::Display for Fixed {
+impl<const B: u32, const D: u32> fmt::Display for FixedPoint<B, D> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", f32!(*self))
+        write!(f, "{}", self.to_f32())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic(expected = "attempt to subtract with overflow"))]
+    fn sub_overflow_wraps_in_release() {
+        assert_eq!(Fixed::MIN - fixed!(1.0 / 256.0), Fixed::MAX);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn sub_overflow_is_reported_when_checking() {
+        overflow::enable();
+        assert_eq!(Fixed::MIN - fixed!(1.0 / 256.0), Fixed::MAX);
+
+        let summaries = overflow::take();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!((summaries[0].kind, summaries[0].op), (Kind::Overflow, "sub"));
+    }
+}
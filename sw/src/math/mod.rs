@@ -1,11 +1,13 @@
+pub mod approx;
 pub mod fixed;
+pub mod overflow;
+mod scalar;
 mod vec3;
 mod vec3i;
 mod rgb565;
-// mod mock;
 
-pub use fixed::Fixed;
-// pub use mock::Fixed;
+pub use fixed::{Fixed, FixedPoint};
+pub use scalar::Scalar;
 pub use vec3::Vec3;
 pub use vec3i::Vec3i;
-pub use rgb565::Rgb565;
\ No newline at end of file
+pub use rgb565::Rgb565;
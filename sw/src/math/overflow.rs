@@ -0,0 +1,119 @@
+//! Checked mode of [super::FixedPoint]: when enabled, every operation whose result doesn't
+//! fit in the format is recorded along with where it happened, instead of wrapping silently
+//! (or panicking, for multiplications). Products of non-zero numbers that truncate to zero
+//! are recorded too, as underflows.
+//!
+//! Operations can't know which module they're part of, so the modules tell the checker as
+//! they're clocked, with [locate] and [at_step]. Everything is per thread, and only costs
+//! anything when an operation actually overflows.
+
+use std::{cell::RefCell, collections::BTreeMap, fmt};
+
+thread_local! {
+    static CHECKER: RefCell<Option<Checker>> = const { RefCell::new(None) };
+}
+
+/// Where an operation happened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Location {
+    /// Module doing the maths, e.g. `vtu` or `orchestrator`
+    pub module: &'static str,
+    /// Which instance of the module, if there are several
+    pub index: Option<usize>,
+    /// Pixel being computed, if any
+    pub pixel: Option<usize>,
+    /// Voxels the ray had gone through, for the VTUs
+    pub step: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    /// The result didn't fit in the format, and wrapped around
+    Overflow,
+    /// A product of non-zero numbers was truncated to zero
+    Underflow,
+}
+
+/// Every time an operation overflowed in the same module, the same way
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub module: &'static str,
+    pub index: Option<usize>,
+    pub kind: Kind,
+    /// `add`, `mul`, ...
+    pub op: &'static str,
+    pub count: u64,
+    /// Where it happened first
+    pub first: Location,
+    /// Operands the first time, as real numbers
+    pub operands: (f32, f32),
+}
+
+#[derive(Debug, Default)]
+struct Checker {
+    location: Location,
+    summaries: BTreeMap<(&'static str, Option<usize>, Kind, &'static str), Summary>,
+}
+
+/// Starts recording overflows on this thread
+pub fn enable() {
+    CHECKER.with_borrow_mut(|checker| *checker = Some(Checker::default()));
+}
+
+/// Tells the checker which module does the next operations, and for which pixel
+pub fn locate(module: &'static str, index: Option<usize>, pixel: Option<usize>) {
+    CHECKER.with_borrow_mut(|checker| if let Some(checker) = checker {
+        checker.location = Location { module, index, pixel, step: None };
+    });
+}
+
+/// Tells the checker how far along its ray the VTU being clocked is
+pub fn at_step(step: usize) {
+    CHECKER.with_borrow_mut(|checker| if let Some(checker) = checker {
+        checker.location.step = Some(step);
+    });
+}
+
+/// Everything recorded since the last call, e.g. during the last frame, and starts over
+pub fn take() -> Vec<Summary> {
+    CHECKER.with_borrow_mut(|checker| match checker {
+        Some(checker) => std::mem::take(&mut checker.summaries).into_values().collect(),
+        None => Vec::new(),
+    })
+}
+
+/// Records that `op` overflowed with operands `lhs` and `rhs`, if checking. Returns whether
+/// it's checking, i.e. whether the overflow is expected to be dealt with later rather than
+/// right away
+pub(super) fn report(kind: Kind, op: &'static str, lhs: f32, rhs: f32) -> bool {
+    CHECKER.with_borrow_mut(|checker| {
+        let Some(checker) = checker else {
+            return false;
+        };
+
+        let location = checker.location;
+        checker.summaries
+            .entry((location.module, location.index, kind, op))
+            .or_insert(Summary {
+                module: location.module,
+                index: location.index,
+                kind,
+                op,
+                count: 0,
+                first: location,
+                operands: (lhs, rhs),
+            })
+            .count += 1;
+
+        true
+    })
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Kind::Overflow => "overflow",
+            Kind::Underflow => "underflow",
+        })
+    }
+}
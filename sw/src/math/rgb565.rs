@@ -1,7 +1,5 @@
 use std::ops;
 
-use crate::fixed;
-
 use super::Fixed;
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -17,6 +15,11 @@ impl Rgb565 {
         Self(((r >> 3) << 11) | ((g >> 2) << 5) | (b >> 3))
     }
 
+    /// The 16 bits, as stored in the framebuffer
+    pub const fn to_raw(self) -> u16 {
+        self.0
+    }
+
     /// Red channel, scaled back to 0..=255
     pub const fn r(&self) -> u8 {
         ((self.0 >> 11) as u8) << 3
@@ -33,6 +36,25 @@ impl Rgb565 {
     }
 }
 
+impl ops::Add for Rgb565 {
+    type Output = Self;
+
+    /// Per channel, saturating
+    fn add(self, rhs: Self) -> Self::Output {
+        Self::new(
+            self.r().saturating_add(rhs.r()),
+            self.g().saturating_add(rhs.g()),
+            self.b().saturating_add(rhs.b()),
+        )
+    }
+}
+
+impl ops::AddAssign for Rgb565 {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
 impl ops::Mul<Fixed> for Rgb565 {
     type Output = Self;
 
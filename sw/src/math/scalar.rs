@@ -0,0 +1,91 @@
+use std::{fmt, ops};
+
+use super::FixedPoint;
+
+/// Number that the ray caster can do its maths with: any [FixedPoint] format, or `f32` as
+/// the reference that they're compared to
+pub trait Scalar:
+    Copy
+    + PartialOrd
+    + fmt::Debug
+    + ops::Add<Output = Self>
+    + ops::Sub<Output = Self>
+    + ops::Mul<Output = Self>
+    + ops::Neg<Output = Self>
+{
+    /// Largest value, used as infinity
+    const MAX: Self;
+
+    fn from_f32(x: f32) -> Self;
+    fn to_f32(self) -> f32;
+    fn from_int(x: i16) -> Self;
+    fn floor(self) -> i16;
+    fn abs(self) -> Self;
+    fn inv_sqrt(self) -> Self;
+    fn recip_lte1(self) -> Self;
+}
+
+impl<const B: u32, const D: u32> Scalar for FixedPoint<B, D> {
+    const MAX: Self = Self::MAX;
+
+    fn from_f32(x: f32) -> Self {
+        Self::from_f32(x)
+    }
+
+    fn to_f32(self) -> f32 {
+        self.to_f32()
+    }
+
+    fn from_int(x: i16) -> Self {
+        x.into()
+    }
+
+    fn floor(self) -> i16 {
+        self.floor()
+    }
+
+    fn abs(self) -> Self {
+        self.abs()
+    }
+
+    fn inv_sqrt(self) -> Self {
+        self.inv_sqrt()
+    }
+
+    fn recip_lte1(self) -> Self {
+        self.recip_lte1()
+    }
+}
+
+/// Exact maths, without the hardware's approximations
+impl Scalar for f32 {
+    const MAX: Self = f32::MAX;
+
+    fn from_f32(x: f32) -> Self {
+        x
+    }
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_int(x: i16) -> Self {
+        x.into()
+    }
+
+    fn floor(self) -> i16 {
+        f32::floor(self) as _
+    }
+
+    fn abs(self) -> Self {
+        f32::abs(self)
+    }
+
+    fn inv_sqrt(self) -> Self {
+        1.0 / self.sqrt()
+    }
+
+    fn recip_lte1(self) -> Self {
+        1.0 / self
+    }
+}
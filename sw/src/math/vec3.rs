@@ -1,25 +1,32 @@
 use std::{fmt, ops};
 
-use crate::{fixed, math::{Fixed, Vec3i}};
+use crate::{fixed, math::{Fixed, Scalar, Vec3i}};
 
-/// A 3-dimensional vector
+/// A 3-dimensional vector, of the hardware's [Fixed] unless said otherwise
 #[derive(Debug, Default, Clone, Copy, PartialEq, PartialOrd)]
-pub struct Vec3 {
-    pub x: Fixed,
-    pub y: Fixed,
-    pub z: Fixed,
+pub struct Vec3<T = Fixed> {
+    pub x: T,
+    pub y: T,
+    pub z: T,
 }
 
 impl Vec3 {
     pub const UP: Self = Self::new(fixed!(0.0), fixed!(1.0), fixed!(0.0));
     pub const RIGHT: Self = Self::new(fixed!(1.0), fixed!(0.0), fixed!(0.0));
     pub const FORWARD: Self = Self::new(fixed!(0.0), fixed!(0.0), fixed!(1.0));
+}
 
-    pub const fn new(x: Fixed, y: Fixed, z: Fixed) -> Self {
+impl<T: Scalar> Vec3<T> {
+    pub const fn new(x: T, y: T, z: T) -> Self {
         Self { x, y, z }
     }
 
-    pub fn magnitude_squared(self) -> Fixed {
+    /// Converts from `f32`, e.g. [crate::camera::Pose::heading_f32]
+    pub fn from_f32([x, y, z]: [f32; 3]) -> Self {
+        Self::new(T::from_f32(x), T::from_f32(y), T::from_f32(z))
+    }
+
+    pub fn magnitude_squared(self) -> T {
         (self.x * self.x) + (self.y * self.y) + (self.z * self.z)
     }
 
@@ -43,12 +50,12 @@ impl Vec3 {
         }
     }
 
-    pub fn dot(self, rhs: Self) -> Fixed {
+    pub fn dot(self, rhs: Self) -> T {
         (self.x * rhs.x) + (self.y * rhs.y) + (self.z * rhs.z)
     }
 }
 
-impl ops::Add for Vec3 {
+impl<T: Scalar> ops::Add for Vec3<T> {
     type Output = Self;
 
     fn add(self, rhs: Self) -> Self::Output {
@@ -60,7 +67,7 @@ impl ops::Add for Vec3 {
     }
 }
 
-impl ops::Sub for Vec3 {
+impl<T: Scalar> ops::Sub for Vec3<T> {
     type Output = Self;
 
     fn sub(self, rhs: Self) -> Self::Output {
@@ -72,10 +79,10 @@ impl ops::Sub for Vec3 {
     }
 }
 
-impl ops::Mul<Fixed> for Vec3 {
+impl<T: Scalar> ops::Mul<T> for Vec3<T> {
     type Output = Self;
 
-    fn mul(self, rhs: Fixed) -> Self::Output {
+    fn mul(self, rhs: T) -> Self::Output {
         Self {
             x: self.x * rhs,
             y: self.y * rhs,
@@ -92,7 +99,7 @@ impl ops::Mul<Vec3> for Fixed {
     }
 }
 
-impl ops::Neg for Vec3 {
+impl<T: Scalar> ops::Neg for Vec3<T> {
     type Output = Self;
 
     fn neg(self) -> Self::Output {
@@ -104,25 +111,25 @@ impl ops::Neg for Vec3 {
     }
 }
 
-impl ops::AddAssign for Vec3 {
+impl<T: Scalar> ops::AddAssign for Vec3<T> {
     fn add_assign(&mut self, rhs: Self) {
         *self = *self + rhs;
     }
 }
 
-impl ops::SubAssign for Vec3 {
+impl<T: Scalar> ops::SubAssign for Vec3<T> {
     fn sub_assign(&mut self, rhs: Self) {
         *self = *self - rhs;
     }
 }
 
-impl ops::MulAssign<Fixed> for Vec3 {
-    fn mul_assign(&mut self, rhs: Fixed) {
+impl<T: Scalar> ops::MulAssign<T> for Vec3<T> {
+    fn mul_assign(&mut self, rhs: T) {
         *self = *self * rhs;
     }
 }
 
-impl fmt::Display for Vec3 {
+impl<T: fmt::Display> fmt::Display for Vec3<T> {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "({}, {}, {})", self.x, self.y, self.z)
     }
@@ -1,6 +1,6 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, fmt, rc::Rc};
 
-use crate::{block::Block, cache::{l2_benchmark_reset, l2_cache_hit_ratio, l2_cache_total_accesses, L2Cache, L3Cache}, fixed, math::{Fixed, Rgb565, Vec3}, top_level::{NUM_L2_ENTRIES, NUM_VTU}, vtu::VoxelTraversalUnit};
+use crate::{block::{Block, Material}, cache::{L2Cache, L3Cache, Window, WindowUpdate}, config::Config, counters::{FrameCounters, L2Counters, OrchestratorCounters}, fixed, math::{overflow, Fixed, Rgb565, Scalar, Vec3}, parallel::{Batch, VtuWorkers}, texture::{Face, TextureAtlas}, trace::{Trace, Tracer}, vtu::VoxelTraversalUnit};
 
 #[derive(Debug)]
 pub struct Orchestrator {
@@ -10,71 +10,197 @@ pub struct Orchestrator {
     pub camera_pos_in: Vec3,
     /// Camera's heading (forward vector)
     pub camera_heading_in: Vec3,
-    /// Framebuffer being drawn (no double buffering for now)
+    /// Camera's up vector, which sets its roll. Doesn't need to be exactly perpendicular to
+    /// [Orchestrator::camera_heading_in], but mustn't be parallel to it
+    pub camera_up_in: Vec3,
+    /// Slice data and window shifts from the host, for the L3 cache
+    pub window_in: WindowUpdate,
+    /// Whether the next frame may start once this one is done, i.e. the framebuffer it'd
+    /// be drawn into isn't waiting to be shown. Always high without a [crate::display::Display]
+    pub frame_start_in: bool,
+    /// Every pixel as it's drawn, i.e. the last frame once [Orchestrator::frame_done_out]
+    /// goes high. Not part of the design, that's the framebuffers of the [crate::display::Display]
     pub frame_buffer_out: Box<[Rgb565]>,
+    /// Framebuffer write port: the pixel written this cycle and its colour, if any
+    pub pixel_write_out: Option<(usize, Rgb565)>,
     /// Signal that goes high for one cycle after a frame is done rendering
     pub frame_done_out: bool,
+    /// Everything the modules counted during the last frame, updated when
+    /// [Orchestrator::frame_done_out] goes high
+    pub counters_out: FrameCounters,
 
     /// Camera position as being currently rendered
     camera_pos: Vec3,
+    /// Height of the viewport, one unit in front of the camera. Sets the field of view
+    viewport_height: Fixed,
+    /// Width of the viewport, for square pixels
+    viewport_width: Fixed,
+    /// Size of the frames
+    resolution: Resolution,
     /// Instances of the VTUs
-    vtu: [VoxelTraversalUnit; NUM_VTU],
+    vtu: Batch,
+    /// Threads that help clock the VTUs, if more than one, see [Config::threads]
+    workers: Option<VtuWorkers>,
     /// L2 cache shared by all the VTUs
-    l2: Rc<RefCell<L2Cache<{NUM_VTU}, {NUM_L2_ENTRIES}>>>,
+    l2: Rc<RefCell<L2Cache>>,
     /// L3 cache shared by all the VTUs
     l3: Rc<RefCell<L3Cache>>,
-    /// Index of the next pixel to be rendered
+    /// Pixel that each VTU is currently computing, i.e. where its result gets written
+    vtu_pixel: Vec<usize>,
+    /// Whether each VTU owns a pixel that hasn't been retired yet
+    vtu_busy: Vec<bool>,
+    /// Index of the next pixel to be dispatched
     next_pixel: usize,
+    /// How many pixels of the current frame have been written to the framebuffer
+    pixels_done: usize,
+    /// VTU that has priority for retiring its result (round-robin arbitration)
+    next_retire: usize,
     /// Location of the top-left pixel in world space
     pixel0_loc: Vec3,
     /// Horizontal delta from pixel to pixel, in world space
     pixel_delta_u: Vec3,
     /// Vertical delta from pixel to pixel, in world space
     pixel_delta_v: Vec3,
+    /// Textures of the blocks, if they aren't flat colours
+    textures: Option<TextureAtlas>,
+    /// Pixel retired last cycle, and its colour once lit. With textures, reading the atlas'
+    /// BRAM takes a cycle, so pixels only get written to the framebuffer on the next one
+    texel_read: Option<(usize, Rgb565)>,
+    /// Index of the frame being rendered, since reset
+    frame: usize,
+    /// Performance counters of the orchestrator itself, not part of the design
+    counters: OrchestratorCounters,
+}
+
+/// Size of the frames, in pixels
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    /// Largest width or height. Pixel coordinates go through [Fixed], so they can't get any
+    /// bigger
+    pub const MAX: usize = 2047;
+
+    pub const fn pixels(self) -> usize {
+        self.width * self.height
+    }
+
+    /// Parses `WIDTHxHEIGHT`
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let Some((width, height)) = s.split_once('x') else {
+            return Err(format!("expected WIDTHxHEIGHT for the resolution, got {s:?}"));
+        };
+        let parse = |n: &str| n
+            .parse::<usize>()
+            .ok()
+            .filter(|n| (1..=Self::MAX).contains(n))
+            .ok_or_else(|| format!("invalid resolution {s:?}, width and height go from 1 to {}", Self::MAX));
+
+        Ok(Self {
+            width: parse(width)?,
+            height: parse(height)?,
+        })
+    }
+}
+
+impl Default for Resolution {
+    fn default() -> Self {
+        Self {
+            width: Orchestrator::FRAME_WIDTH,
+            height: Orchestrator::FRAME_HEIGHT,
+        }
+    }
+}
+
+impl fmt::Display for Resolution {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}x{}", self.width, self.height)
+    }
 }
 
 impl Default for Orchestrator {
     fn default() -> Self {
+        Self::new(Config::default())
+    }
+}
+
+impl Orchestrator {
+    /// Direction towards the sun, i.e. opposite to the direction the sunlight travels in
+    pub const SUN: Vec3 = Vec3::new(fixed!(-1.0), fixed!(5.0), fixed!(-2.0));
+
+    /// Default resolution, the one of the board's framebuffer, see [Resolution]
+    pub const FRAME_WIDTH: usize = 160;
+    pub const FRAME_HEIGHT: usize = 128;
+    pub const NUM_PIXELS: usize = Self::FRAME_WIDTH * Self::FRAME_HEIGHT;
+
+    pub fn new(config: Config) -> Self {
+        // Rounded rather than truncated, so that 90 degrees is exactly 2 units high
+        let resolution = (1 << Fixed::D) as f32;
+        let viewport_height = 2.0 * (config.fov.to_radians() / 2.0).tan();
+        let viewport_height = (viewport_height * resolution).round() / resolution;
+        let resolution = config.resolution;
+        let viewport_width = viewport_height * resolution.width as f32 / resolution.height as f32;
+
         Self {
             reset: Default::default(),
-            frame_buffer_out: vec![Rgb565::default(); Self::NUM_PIXELS].into_boxed_slice(),
+            frame_buffer_out: vec![Rgb565::default(); resolution.pixels()].into_boxed_slice(),
             camera_pos_in: Default::default(),
             camera_heading_in: Default::default(),
+            camera_up_in: Vec3::UP,
+            window_in: Default::default(),
+            frame_start_in: true,
+            pixel_write_out: None,
             frame_done_out: Default::default(),
+            counters_out: Default::default(),
             camera_pos: Default::default(),
-            vtu: std::array::from_fn(|i| VoxelTraversalUnit::default().with_index(i)),
-            l2: Default::default(),
-            l3: Default::default(),
+            viewport_height: fixed!(viewport_height),
+            viewport_width: fixed!(viewport_width),
+            resolution,
+            vtu: (0..config.vtus)
+                .map(|i| Box::new(VoxelTraversalUnit::default()
+                    .with_index(i)
+                    .with_l1(config.l1_entries)
+                    .with_shadows(config.shadow_cycles)
+                    .with_render_distance(config.render_distance)
+                ))
+                .collect(),
+            workers: (config.threads > 1 && config.vtus > 1).then(|| VtuWorkers::new(config.threads.min(config.vtus))),
+            l2: Rc::new(RefCell::new(L2Cache::default()
+                .with_size(config.vtus, config.l2_entries)
+                .with_policy(config.l2_organization, config.l2_replacement)
+            )),
+            l3: Rc::new(RefCell::new(L3Cache::default().with_timing(config.ddr).with_window(config.window))),
+            vtu_pixel: vec![Default::default(); config.vtus],
+            vtu_busy: vec![Default::default(); config.vtus],
             next_pixel: Default::default(),
+            pixels_done: Default::default(),
+            next_retire: Default::default(),
             pixel0_loc: Default::default(),
             pixel_delta_u: Default::default(),
             pixel_delta_v: Default::default(),
+            textures: config.textures,
+            texel_read: Default::default(),
+            frame: Default::default(),
+            counters: Default::default(),
         }
     }
-}
-
-impl Orchestrator {
-    pub const FRAME_WIDTH: usize = 160;
-    pub const FRAME_HEIGHT: usize = 128;
-    pub const NUM_PIXELS: usize = Self::FRAME_WIDTH * Self::FRAME_HEIGHT;
-
-    pub const VIEWPORT_HEIGHT: Fixed = fixed!(2.0);
-    pub const VIEWPORT_WIDTH: Fixed = fixed!(2.0 * (Self::FRAME_WIDTH as f32) / (Self::FRAME_HEIGHT as f32));
 
     /// Unrealistic render that finishes instantly (and isn't parallelized)
+    #[allow(unused)]
     pub fn mock_render(&mut self) {
         // Calculate orthonormal basis of camera
-        let w = self.camera_heading_in.normalized();
-        let u = Vec3::UP.cross(w).normalized();
-        let v = w.cross(u);
+        let (u, v, w) = Self::basis(self.camera_heading_in, self.camera_up_in);
 
         // UV vectors that span the viewport in world coordinates
-        let viewport_u = u * Self::VIEWPORT_WIDTH;
-        let viewport_v = -v * Self::VIEWPORT_HEIGHT;
+        let viewport_u = u * self.viewport_width;
+        let viewport_v = -v * self.viewport_height;
 
         // Delta vectors from pixel to pixel
-        let pixel_delta_x = u * fixed!(f32::from(Self::VIEWPORT_WIDTH) / Self::FRAME_WIDTH as f32);
-        let pixel_delta_y = -v * fixed!(f32::from(Self::VIEWPORT_HEIGHT) / Self::FRAME_HEIGHT as f32);
+        let pixel_delta_x = u * fixed!(f32::from(self.viewport_width) / self.resolution.width as f32);
+        let pixel_delta_y = -v * fixed!(f32::from(self.viewport_height) / self.resolution.height as f32);
 
         // Upper left pixel
         let viewport_corner = self.camera_pos_in - (w * fixed!(1.0)) - ((viewport_u + viewport_v) * fixed!(0.5));
@@ -82,24 +208,23 @@ impl Orchestrator {
 
         self.vtu[0].ray_origin_in = self.camera_pos_in;
         for (i, px) in self.frame_buffer_out.iter_mut().enumerate() {
-            let x: Fixed = ((i % Self::FRAME_WIDTH) as i16).into();
-            let y: Fixed = ((i / Self::FRAME_WIDTH) as i16).into();
+            let x: Fixed = ((i % self.resolution.width) as i16).into();
+            let y: Fixed = ((i / self.resolution.width) as i16).into();
             
             let pixel = pixel_center + (pixel_delta_x * x) + (pixel_delta_y * y);
             
-            let light = self.vtu[0].normal_out.dot(Vec3::new(fixed!(1.0), fixed!(-5.0), fixed!(2.0)).normalized());
-            let light = fixed!(0.4) + fixed!(0.2) * light;
-            let apply_light = |c| (Fixed::from(c) * light).floor() as u8;
-            let apply_light_rgb = |r, g, b| Rgb565::new(apply_light(r), apply_light(g), apply_light(b));
-
             self.vtu[0].ray_direction_in = pixel - self.camera_pos_in;
             self.vtu[0].mock_cast();
 
-            match self.vtu[0].voxel_out {
-                Block::Air => *px = Rgb565::new(174, 200, 235),
-                Block::Water => *px = apply_light_rgb(52, 67, 138),
-                _ => *px = apply_light_rgb(98, 168, 98),
-            }
+            // Same shading as when retiring a pixel, without shadows or textures
+            let vtu = &self.vtu[0];
+            let light = fixed!(0.4) + fixed!(0.2) * vtu.normal_out.dot(Self::SUN.normalized());
+            let color = match vtu.voxel_out {
+                Block::Air => Block::Air.color(),
+                block if matches!(block.material(), Material::Translucent { .. }) => Rgb565::default(),
+                block => block.color() * light,
+            };
+            *px = vtu.tint_out + color * vtu.transmittance_out;
         }
 
         self.frame_done_out = !self.frame_done_out;
@@ -110,70 +235,101 @@ impl Orchestrator {
         // Reset
         (*self.l2).borrow_mut().reset = self.reset;
         (*self.l3).borrow_mut().reset = self.reset;
+        (*self.l3).borrow_mut().window_in = self.window_in;
+        let window_min = self.l3.borrow().window_min_out;
+        let invalidate = self.window_in.invalidation(window_min);
+        (*self.l2).borrow_mut().invalidate_in = invalidate;
         for vtu in &mut self.vtu {
             vtu.reset = self.reset;
+            vtu.sun_in = Self::SUN;
+            vtu.window_min_in = window_min;
+            vtu.invalidate_in = invalidate;
         }
         
         // Clock edge
         (*self.l2).borrow_mut().rising_clk_edge();
         (*self.l3).borrow_mut().rising_clk_edge();
-        for vtu in &mut self.vtu {
-            vtu.rising_clk_edge();
+
+        // Each VTU only sees its own L2 port, and what they drive only gets to the L2 once
+        // they're all done, so they can all be clocked at once
+        let l2 = self.l2.borrow();
+        for (i, vtu) in self.vtu.iter_mut().enumerate() {
+            l2.drive(i, &mut vtu.l2);
+        }
+        drop(l2);
+
+        match &mut self.workers {
+            Some(workers) => workers.clock(&mut self.vtu),
+            None => for (i, vtu) in self.vtu.iter_mut().enumerate() {
+                overflow::locate("vtu", Some(i), self.vtu_busy[i].then_some(self.vtu_pixel[i]));
+                vtu.rising_clk_edge();
+            },
         }
 
+        let mut l2 = self.l2.borrow_mut();
+        for (i, vtu) in self.vtu.iter().enumerate() {
+            l2.latch(i, &vtu.l2);
+        }
+        drop(l2);
+        overflow::locate("orchestrator", None, None);
+
         // Reset
         if self.reset {
             self.frame_buffer_out.fill(Default::default());
+            self.pixel_write_out = None;
             self.frame_done_out = false;
             self.camera_pos = Vec3::default();
-            self.next_pixel = Self::NUM_PIXELS;
+            self.vtu_pixel.fill(0);
+            self.vtu_busy.fill(false);
+            self.next_pixel = self.resolution.pixels();
+            self.pixels_done = self.resolution.pixels();
+            self.next_retire = 0;
             self.pixel0_loc = Vec3::default();
             self.pixel_delta_u = Vec3::default();
             self.pixel_delta_v = Vec3::default();
+            self.texel_read = None;
+            self.frame = 0;
+            self.counters = Default::default();
+            self.counters_out = Default::default();
             
             (*self.l2).borrow_mut().l3 = Rc::clone(&self.l3);
-            for vtu in &mut self.vtu {
-                vtu.l2 = Rc::clone(&self.l2);
-            }
             return;
         }
 
-        self.frame_done_out = self.next_pixel == Self::NUM_PIXELS - 1;
+        self.frame_done_out = false;
+        self.pixel_write_out = None;
+        self.counters.cycles += 1;
+
+        // Texel read from the atlas last cycle is out of the BRAM
+        let mut wrote_texel = false;
+        if let Some((pixel, color)) = self.texel_read.take() {
+            self.write_pixel(pixel, color);
+            if self.frame_done_out {
+                return;
+            }
+            wrote_texel = true;
+        }
+
+        // Wait for the framebuffer to be free before drawing the next frame into it
+        if self.pixels_done == self.resolution.pixels() && !self.frame_start_in {
+            self.counters.idle_cycles += 1;
+            return;
+        }
 
         // Initialize camera vectors
-        if self.next_pixel == Self::NUM_PIXELS {
+        if self.pixels_done == self.resolution.pixels() {
             self.next_pixel = 0;
+            self.pixels_done = 0;
             self.camera_pos = self.camera_pos_in;
-            
-            // Calculate orthonormal basis of camera
-            let w = self.camera_heading_in.normalized();
-            let u = Vec3::UP.cross(w).normalized();
-            let v = w.cross(u);
-            
-            // UV vectors that span the viewport in world coordinates
-            let viewport_u = u * Self::VIEWPORT_WIDTH;
-            let viewport_v = -v * Self::VIEWPORT_HEIGHT;
-
-            // Delta vectors from pixel to pixel
-            self.pixel_delta_u = u * fixed!(f32::from(Self::VIEWPORT_WIDTH) / Self::FRAME_WIDTH as f32);
-            self.pixel_delta_v = -v * fixed!(f32::from(Self::VIEWPORT_HEIGHT) / Self::FRAME_HEIGHT as f32);
-
-            // Upper left pixel
-            let viewport_corner = self.camera_pos_in - (w * fixed!(1.0)) - ((viewport_u + viewport_v) * fixed!(0.5));
-            self.pixel0_loc = viewport_corner + ((self.pixel_delta_u + self.pixel_delta_v) * fixed!(0.5));
-
-            // TODO: change me. right now they all compute the first pixel
-            for vtu in &mut self.vtu {
-                vtu.ray_direction_in = self.pixel0_loc - self.camera_pos_in;
-                vtu.ray_origin_in = self.camera_pos_in;
-                vtu.ray_init_in = true;
-                vtu.current_pixel = 0;
-            }
+            (self.pixel0_loc, self.pixel_delta_u, self.pixel_delta_v) = self.viewport();
 
-            println!("This frame had {} accesses with {}% hit-ratio", l2_cache_total_accesses(), l2_cache_hit_ratio() * 100.0);
-            l2_benchmark_reset();
+            // Every VTU gets its own pixel to start with
+            for i in 0..self.vtu.len() {
+                self.dispatch(i);
+            }
 
             // TODO: this will probably require more cycles
+            self.counters.idle_cycles += 1;
             return;
         }
 
@@ -182,48 +338,233 @@ impl Orchestrator {
             vtu.ray_init_in = false;
         }
 
-        // VTU is done rendering a pixel!
-        for vtu in &mut self.vtu {
-            if !vtu.valid_out {
-                continue;
+        // Find a VTU that is done rendering a pixel. The framebuffer has a single write
+        // port, so only one VTU can be serviced per cycle. Round-robin arbitration, starting
+        // after the last VTU that was serviced, so that no VTU starves.
+        let Some(i) = (self.next_retire..self.vtu.len())
+            .chain(0..self.next_retire)
+            .find(|&i| self.vtu_busy[i] && self.vtu[i].valid_out)
+        else {
+            if !wrote_texel {
+                self.counters.idle_cycles += 1;
             }
+            return;
+        };
+        let vtu = &self.vtu[i];
+        let pixel = self.vtu_pixel[i];
+        overflow::locate("orchestrator", None, Some(pixel));
+
+        // Only ambient light when the sun is hidden
+        let light = match vtu.shadowed_out {
+            true => fixed!(0.4),
+            false => fixed!(0.4) + fixed!(0.2) * vtu.normal_out.dot(Self::SUN.normalized()),
+        };
+
+        let color = match (&self.textures, vtu.voxel_out) {
+            (_, Block::Air) => Block::Air.color(),
+            // A run of translucent blocks hid what's behind it, and is already in the tint
+            (_, block) if matches!(block.material(), Material::Translucent { .. }) => Rgb565::default(),
+            (None, block) => block.color() * light,
+            (Some(textures), block) => {
+                let [u, v] = vtu.face_uv_out;
+                self.counters.texture_reads += 1;
+
+                textures.sample(block, Face::from_normal(vtu.normal_out), u, v) * light
+            },
+        };
+
+        // Whatever translucent blocks were in front of the hit
+        let color = vtu.tint_out + color * vtu.transmittance_out;
+
+        // With textures, every pixel goes through the extra stage, even the sky, so that
+        // the framebuffer's single write port is only used once per cycle
+        match self.textures {
+            Some(_) => self.texel_read = Some((pixel, color)),
+            None => self.write_pixel(pixel, color),
+        }
 
-            let px = &mut self.frame_buffer_out[vtu.current_pixel];
+        // Retire the pixel, results can come back in any order
+        self.vtu_busy[i] = false;
+        self.next_retire = if i + 1 == self.vtu.len() { 0 } else { i + 1 };
 
-            let sun = Vec3 {
-                x: fixed!(1.0),
-                y: fixed!(-5.0),
-                z: fixed!(2.0)
-            };
-            let light = fixed!(0.4) + fixed!(0.2) * vtu.normal_out.dot(sun.normalized());
-
-            *px = match vtu.voxel_out {
-                Block::Air => Rgb565::new(174, 200, 235),
-                Block::Water => Rgb565::new(52, 67, 138),
-                Block::Grass => Rgb565::new(90, 133, 77),
-                Block::Dirt => Rgb565::new(133, 96, 77),
-                Block::OakLog => Rgb565::new(91, 58, 42),
-                Block::OakLeaves => Rgb565::new(129, 165, 118),
-                _ => Rgb565::new(82, 70, 84),
+        // Keep the VTU busy with the next pixel, if there's any left
+        self.dispatch(i);
+    }
+
+    /// Writes the colour of a pixel to the framebuffer, ending the frame if it's the last one
+    fn write_pixel(&mut self, pixel: usize, color: Rgb565) {
+        self.frame_buffer_out[pixel] = color;
+        self.pixel_write_out = Some((pixel, color));
+        self.pixels_done += 1;
+        self.frame_done_out = self.pixels_done == self.resolution.pixels();
+        self.counters.pixels += 1;
+
+        if self.frame_done_out {
+            self.counters_out = self.take_counters();
+            self.frame += 1;
+        }
+    }
+
+    /// Location of the top-left pixel in world space, and the horizontal and vertical
+    /// deltas from pixel to pixel, for the camera inputs
+    fn viewport(&self) -> (Vec3, Vec3, Vec3) {
+        // Calculate orthonormal basis of camera
+        let (u, v, w) = Self::basis(self.camera_heading_in, self.camera_up_in);
+
+        // UV vectors that span the viewport in world coordinates
+        let viewport_u = u * self.viewport_width;
+        let viewport_v = -v * self.viewport_height;
+
+        // Delta vectors from pixel to pixel
+        let pixel_delta_u = u * fixed!(f32::from(self.viewport_width) / self.resolution.width as f32);
+        let pixel_delta_v = -v * fixed!(f32::from(self.viewport_height) / self.resolution.height as f32);
+
+        // Upper left pixel
+        let viewport_corner = self.camera_pos_in - (w * fixed!(1.0)) - ((viewport_u + viewport_v) * fixed!(0.5));
+        let pixel0_loc = viewport_corner + ((pixel_delta_u + pixel_delta_v) * fixed!(0.5));
+
+        (pixel0_loc, pixel_delta_u, pixel_delta_v)
+    }
+
+    /// Location of `pixel` in world space, on a viewport set up by [Orchestrator::viewport]
+    fn pixel_loc(&self, pixel0_loc: Vec3, pixel_delta_u: Vec3, pixel_delta_v: Vec3, pixel: usize) -> Vec3 {
+        let x = ((pixel % self.resolution.width) as i16).into();
+        let y = ((pixel / self.resolution.width) as i16).into();
+
+        pixel0_loc + (pixel_delta_u * x) + (pixel_delta_v * y)
+    }
+
+    /// Origin and direction of the ray that a frame started with the current camera inputs
+    /// dispatches for `pixel`. For the ray debugger, not part of the design
+    pub fn pixel_ray(&self, pixel: usize) -> (Vec3, Vec3) {
+        let (pixel0_loc, pixel_delta_u, pixel_delta_v) = self.viewport();
+        let pixel_loc = self.pixel_loc(pixel0_loc, pixel_delta_u, pixel_delta_v, pixel);
+
+        (self.camera_pos_in, pixel_loc - self.camera_pos_in)
+    }
+
+    /// Size of the frames
+    pub fn resolution(&self) -> Resolution {
+        self.resolution
+    }
+
+    /// The voxels the L3 cache holds right now. For the ray debugger, not part of the design
+    pub fn window(&self) -> Window {
+        self.l3.borrow().window().clone()
+    }
+
+    /// Orthonormal basis `(right, up, backward)` of a camera looking along `heading`.
+    ///
+    /// `up` only needs to not be parallel to `heading`. When it (nearly) is, e.g. looking
+    /// straight up with the world's up vector, the world axis that's the most perpendicular
+    /// to `heading` is used instead, so the basis never degenerates.
+    pub fn basis<T: Scalar>(heading: Vec3<T>, up: Vec3<T>) -> (Vec3<T>, Vec3<T>, Vec3<T>) {
+        let (zero, one) = (T::from_int(0), T::from_int(1));
+        let w = heading.normalized();
+
+        let mut right = up.cross(w);
+        if right.magnitude_squared() < T::from_f32(1.0 / 64.0) {
+            let (x, y, z) = (w.x.abs(), w.y.abs(), w.z.abs());
+            let axis = if x <= y && x <= z {
+                Vec3::new(one, zero, zero)
+            } else if y <= z {
+                Vec3::new(zero, one, zero)
+            } else {
+                Vec3::new(zero, zero, one)
             };
-            if vtu.voxel_out != Block::Air {
-                *px *= light;
-            }
 
-            self.next_pixel += 1;
+            right = axis.cross(w);
+        }
 
-            let x = ((self.next_pixel % Self::FRAME_WIDTH) as i16).into();
-            let y = ((self.next_pixel / Self::FRAME_WIDTH) as i16).into();
-            let pixel_loc = self.pixel0_loc + (self.pixel_delta_u * x) + (self.pixel_delta_v * y);
+        let u = right.normalized();
+        let v = w.cross(u);
+        (u, v, w)
+    }
 
-            vtu.ray_direction_in = pixel_loc - self.camera_pos;
-            vtu.ray_origin_in = self.camera_pos;
-            vtu.current_pixel = self.next_pixel;
-            vtu.ray_init_in = true;
+    /// Gathers the counters of every module for the frame that just finished, and starts
+    /// counting from zero for the next one
+    fn take_counters(&mut self) -> FrameCounters {
+        let mut l2 = self.l2.borrow_mut();
+        let l2_counters = L2Counters::new(self.vtu.len(), l2.counters.comparators);
+
+        self.counters.texture_bits = self.textures.as_ref().map_or(0, TextureAtlas::bits);
+
+        FrameCounters {
+            frame: self.frame,
+            orchestrator: std::mem::take(&mut self.counters),
+            vtu: self.vtu
+                .iter_mut()
+                .map(|vtu| std::mem::take(&mut vtu.counters))
+                .collect(),
+            l1: self.vtu
+                .iter_mut()
+                .filter_map(|vtu| vtu.l1_counters_mut().map(std::mem::take))
+                .collect(),
+            l2: std::mem::replace(&mut l2.counters, l2_counters),
+            l3: std::mem::take(&mut self.l3.borrow_mut().counters),
+            // Filled in by the top level, which owns the display
+            display: Default::default(),
+        }
+    }
 
-            // Can only service one VTU at a time
-            // Static priority arbitration
-            break;
+    /// Hands the next pixel of the frame to the `i`th VTU, if there's any left
+    fn dispatch(&mut self, i: usize) {
+        if self.next_pixel == self.resolution.pixels() {
+            return;
         }
+
+        overflow::locate("orchestrator", None, Some(self.next_pixel));
+
+        let pixel_loc = self.pixel_loc(self.pixel0_loc, self.pixel_delta_u, self.pixel_delta_v, self.next_pixel);
+
+        let vtu = &mut self.vtu[i];
+        vtu.ray_direction_in = pixel_loc - self.camera_pos;
+        vtu.ray_origin_in = self.camera_pos;
+        vtu.ray_init_in = true;
+
+        self.vtu_pixel[i] = self.next_pixel;
+        self.vtu_busy[i] = true;
+        self.next_pixel += 1;
     }
-}
\ No newline at end of file
+}
+
+impl Trace for Orchestrator {
+    fn trace(&self, t: &mut Tracer) {
+        // The framebuffer is too big to be a signal
+        t.scope("orchestrator", |t| {
+            t.bit("reset", self.reset);
+            t.vec3("camera_pos_in", self.camera_pos_in);
+            t.vec3("camera_heading_in", self.camera_heading_in);
+            t.vec3("camera_up_in", self.camera_up_in);
+            t.bit("frame_done_out", self.frame_done_out);
+
+            for vtu in &self.vtu {
+                vtu.trace(t);
+            }
+            self.l2.borrow().trace(t);
+            self.l3.borrow().trace(t);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::camera::Pose;
+
+    #[test]
+    fn basis_at_the_poles() {
+        for (yaw, pitch, roll) in [(0.0, 90.0, 0.0), (0.0, -90.0, 0.0), (25.0, 90.0, 30.0), (25.0, -90.0, 90.0), (200.0, 90.0, 180.0)] {
+            let pose = Pose::parse(&format!("0 0 0 {yaw} {pitch} {roll}")).unwrap();
+            let (u, v, w) = Orchestrator::basis(pose.heading(), pose.up());
+
+            // Unit length up to `inv_sqrt`'s approximation (0.94 for 1), and perpendicular
+            for a in [u, v, w] {
+                assert!((a.magnitude_squared().to_f32() - 1.0).abs() < 0.125, "{pose:?}: {a:?} isn't unit length");
+            }
+            for (a, b) in [(u, v), (v, w), (w, u)] {
+                assert!(a.dot(b).to_f32().abs() < 1.0 / 64.0, "{pose:?}: {a:?} and {b:?} aren't perpendicular");
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Clocks the VTUs of a cycle on several threads. A VTU only sees its own inputs and its
+//! own [L2Port](crate::cache::L2Port), which the orchestrator connects to the L2 cache
+//! before clocking the VTUs and after they're all done (evaluate, then commit). So the
+//! order they're clocked in within a cycle doesn't matter, and any number of threads
+//! gives the same results as clocking them one after the other.
+//!
+//! Not part of the design, only of how fast the simulation runs.
+
+use std::{sync::mpsc::{self, Receiver, Sender, TryRecvError}, thread};
+
+use crate::vtu::VoxelTraversalUnit;
+
+/// How many times to look for work (or results) before going to sleep. A cycle's worth of
+/// VTUs takes microseconds, less than it takes to wake up a sleeping thread
+const SPINS: usize = 1000;
+
+/// VTUs handed over to a worker, boxed so that handing them over only moves pointers
+pub type Batch = Vec<Box<VoxelTraversalUnit>>;
+
+#[derive(Debug)]
+struct Worker {
+    jobs: Sender<Batch>,
+    results: Receiver<Batch>,
+    /// Kept from one cycle to the next, so that handing VTUs over doesn't allocate
+    batch: Batch,
+}
+
+/// Threads that clock VTUs alongside the simulation's own thread
+#[derive(Debug)]
+pub struct VtuWorkers {
+    workers: Vec<Worker>,
+}
+
+impl VtuWorkers {
+    /// `threads` threads in total, counting the one the simulation runs on
+    pub fn new(threads: usize) -> Self {
+        let workers = (1..threads)
+            .map(|i| {
+                let (jobs, pending) = mpsc::channel::<Batch>();
+                let (done, results) = mpsc::channel();
+
+                thread::Builder::new()
+                    .name(format!("vtu-worker-{i}"))
+                    .spawn(move || {
+                        while let Some(mut batch) = receive(&pending) {
+                            for vtu in &mut batch {
+                                vtu.rising_clk_edge();
+                            }
+                            if done.send(batch).is_err() {
+                                return;
+                            }
+                        }
+                    })
+                    .expect("couldn't start a VTU worker thread");
+
+                Worker { jobs, results, batch: Vec::new() }
+            })
+            .collect();
+
+        Self { workers }
+    }
+
+    /// Clocks every VTU of `vtus`, like calling their `rising_clk_edge` in order would.
+    /// The calling thread takes the first share of them
+    pub fn clock(&mut self, vtus: &mut Batch) {
+        let threads = self.workers.len() + 1;
+        let len = vtus.len();
+
+        // From the back, so that they're put back in the same order
+        for (i, worker) in self.workers.iter_mut().enumerate().rev() {
+            let mut batch = std::mem::take(&mut worker.batch);
+            batch.extend(vtus.drain((i + 1) * len / threads..));
+
+            worker.jobs.send(batch).expect("a VTU worker thread panicked");
+        }
+
+        for vtu in vtus.iter_mut() {
+            vtu.rising_clk_edge();
+        }
+
+        for worker in &mut self.workers {
+            let mut batch = receive(&worker.results).expect("a VTU worker thread panicked");
+
+            vtus.append(&mut batch);
+            worker.batch = batch;
+        }
+    }
+}
+
+/// Waits for the next message, polling for a while before going to sleep. [None] once the
+/// other side has hung up
+fn receive<T>(receiver: &Receiver<T>) -> Option<T> {
+    for _ in 0..SPINS {
+        match receiver.try_recv() {
+            Ok(message) => return Some(message),
+            Err(TryRecvError::Disconnected) => return None,
+            Err(TryRecvError::Empty) => thread::yield_now(),
+        }
+    }
+
+    receiver.recv().ok()
+}
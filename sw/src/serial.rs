@@ -0,0 +1,315 @@
+//! A virtual FPGA for the plugin to talk to: listens on a TCP socket, and decodes the byte
+//! stream of `FpgaPlugin::send_blocks` into the L3 cache's window, like the board would
+//! decode what comes in on its UART. With the plugin's `FPGACRAFT_PORT` pointing at the
+//! socket, the whole pipeline runs on one machine.
+//!
+//! The plugin sends its whole 64³ chunk, centred on the player, every time the player moves
+//! to another block: one voxel per `[0, id, 1]` frame, in `chunk.bin` order. Each chunk
+//! overwrites the window in place, which doesn't slide.
+//!
+//! The socket isn't part of the design, but bytes get out of it no faster than they would
+//! come in over the UART, so the FPGA sees them with the same latency as on the board.
+
+use std::{io::Read, net::TcpListener, sync::mpsc::{self, Receiver, TryRecvError}, thread};
+
+use crate::{block::Block, cache::{Window, WindowUpdate}, math::Vec3i, stream::UartLink, top_level::TopLevel};
+
+#[derive(Debug)]
+pub struct VirtualSerial {
+    /// Drops the frame being decoded. Bytes that came in but aren't over the wire yet are
+    /// kept, they're the host's
+    pub reset: bool,
+    /// Voxel that finished arriving this cycle, for [crate::orchestrator::Orchestrator::window_in]
+    pub window_out: WindowUpdate,
+    /// Frames that didn't start with a 0 or end with a 1, since reset
+    pub framing_errors_out: usize,
+
+    /// What the socket's thread received
+    rx: Receiver<Event>,
+    /// How fast bytes come in
+    link: UartLink,
+    /// Bytes received, but not over the wire yet
+    queue: Vec<u8>,
+    /// Next byte of [VirtualSerial::queue], and cycles until it's fully arrived
+    arriving: Option<(usize, usize)>,
+    /// Where the frame being decoded is at
+    frame: Frame,
+    /// Voxel of the chunk that the next frame is for
+    voxel: usize,
+}
+
+/// What happened on the socket
+#[derive(Debug)]
+enum Event {
+    /// The plugin connected, so it starts a new chunk
+    Connected,
+    /// Bytes that the plugin sent
+    Data(Vec<u8>),
+}
+
+/// State of the frame decoder, `[0, id, 1]`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Frame {
+    /// Waiting for the start byte
+    #[default]
+    Idle,
+    /// Got the start byte, waiting for the voxel
+    Start,
+    /// Got the voxel, waiting for the stop byte
+    Data(u8),
+}
+
+impl VirtualSerial {
+    /// Listens on `addr` for the plugin, taking one connection at a time, with bytes coming
+    /// in at the speed of `link`
+    pub fn listen(addr: &str, link: UartLink) -> Result<Self, String> {
+        let listener = TcpListener::bind(addr).map_err(|e| format!("couldn't listen on {addr}: {e}"))?;
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut stream) = stream else {
+                    continue;
+                };
+                if tx.send(Event::Connected).is_err() {
+                    return;
+                }
+
+                let mut buffer = [0; 4096];
+                while let Ok(n @ 1..) = stream.read(&mut buffer) {
+                    if tx.send(Event::Data(buffer[..n].to_vec())).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+
+        eprintln!("virtual FPGA listening on {addr}");
+        Ok(Self::new(rx, link))
+    }
+
+    /// Decodes what the socket's thread sends on `rx`, with bytes coming in at the speed of
+    /// `link`
+    fn new(rx: Receiver<Event>, link: UartLink) -> Self {
+        Self {
+            reset: false,
+            window_out: WindowUpdate::None,
+            framing_errors_out: 0,
+            rx,
+            link,
+            queue: Vec::new(),
+            arriving: None,
+            frame: Frame::Idle,
+            voxel: 0,
+        }
+    }
+
+    /// Bytes that came in on the socket but aren't over the wire yet
+    pub fn backlog(&self) -> usize {
+        self.queue.len() - self.arriving.map_or(0, |(i, _)| i)
+    }
+
+    pub fn rising_clk_edge(&mut self) {
+        self.receive();
+
+        if self.reset {
+            self.window_out = WindowUpdate::None;
+            self.framing_errors_out = 0;
+            self.frame = Frame::Idle;
+            self.voxel = 0;
+            self.arriving = self.arriving.map(|(i, _)| (i, self.link.cycles_per_byte()));
+            return;
+        }
+
+        self.window_out = WindowUpdate::None;
+
+        let i = match &mut self.arriving {
+            Some((_, cycles)) if *cycles > 1 => {
+                *cycles -= 1;
+                return;
+            },
+            Some((i, _)) => *i,
+            None => {
+                self.next_byte(0);
+                return;
+            },
+        };
+
+        let byte = self.queue[i];
+        self.frame = match (self.frame, byte) {
+            (Frame::Idle, 0) => Frame::Start,
+            (Frame::Start, id) => Frame::Data(id),
+            (Frame::Data(id), 1) => {
+                self.write(id);
+                Frame::Idle
+            },
+            // Out of sync, a 0 might be the start of the next frame
+            (_, byte) => {
+                self.framing_errors_out += 1;
+                match byte {
+                    0 => Frame::Start,
+                    _ => Frame::Idle,
+                }
+            },
+        };
+
+        self.next_byte(i + 1);
+    }
+
+    /// Takes whatever the socket's thread received since last cycle
+    fn receive(&mut self) {
+        loop {
+            match self.rx.try_recv() {
+                Ok(Event::Connected) => {
+                    // Whatever's left of the last connection is lost with it
+                    eprintln!("virtual FPGA: plugin connected");
+                    self.queue.clear();
+                    self.arriving = None;
+                    self.frame = Frame::Idle;
+                    self.voxel = 0;
+                },
+                Ok(Event::Data(bytes)) => self.queue.extend(bytes),
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return,
+            }
+        }
+    }
+
+    /// Starts receiving the `i`th byte of the queue, if it's there yet
+    fn next_byte(&mut self, i: usize) {
+        // Drop what's already arrived every now and then, rather than on every byte
+        let i = match i >= 4096 {
+            true => {
+                self.queue.drain(..i);
+                0
+            },
+            false => i,
+        };
+
+        self.arriving = match i < self.queue.len() {
+            true => Some((i, self.link.cycles_per_byte())),
+            false => {
+                if i > 0 {
+                    self.queue.clear();
+                }
+                None
+            },
+        };
+    }
+
+    /// Writes voxel `id` to its place in the window, and moves on to the next one
+    fn write(&mut self, id: u8) {
+        let size = Window::SIZE;
+        let offset = Vec3i::new(
+            (self.voxel % size) as _,
+            ((self.voxel / size) % size) as _,
+            (self.voxel / (size * size)) as _,
+        );
+
+        // Unknown ids can't be drawn anyway
+        let block = Block::try_from(id).unwrap_or_default();
+        self.window_out = WindowUpdate::Write(offset, block);
+
+        self.voxel = (self.voxel + 1) % size.pow(3);
+    }
+}
+
+/// Clocks `serial` after `top_level`, feeding its L3 cache on the next cycle
+pub fn clock(serial: &mut VirtualSerial, top_level: &mut TopLevel) {
+    serial.reset = top_level.reset;
+    serial.rising_clk_edge();
+
+    top_level.orchestrator.window_in = serial.window_out;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::Sender;
+
+    use super::*;
+
+    /// A link that gets a byte across every cycle
+    const FAST: UartLink = UartLink {
+        baud: 10,
+        clock_hz: 1,
+        bytes_per_message: 3,
+    };
+
+    fn connect(link: UartLink) -> (VirtualSerial, Sender<Event>) {
+        let (tx, rx) = mpsc::channel();
+        tx.send(Event::Connected).unwrap();
+        (VirtualSerial::new(rx, link), tx)
+    }
+
+    /// Clocks `serial` for `cycles` cycles, returning the cycle (from 1) of every write
+    fn run(serial: &mut VirtualSerial, cycles: usize) -> Vec<(usize, WindowUpdate)> {
+        (1..=cycles)
+            .filter_map(|cycle| {
+                serial.rising_clk_edge();
+                (serial.window_out != WindowUpdate::None).then_some((cycle, serial.window_out))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_frames_in_chunk_order() {
+        let (mut serial, tx) = connect(FAST);
+        tx.send(Event::Data(vec![0, 1, 1, 0, 2, 1])).unwrap();
+
+        let writes = run(&mut serial, 20).into_iter().map(|(_, w)| w).collect::<Vec<_>>();
+        assert_eq!(writes, [
+            WindowUpdate::Write(Vec3i::new(0, 0, 0), Block::Stone),
+            WindowUpdate::Write(Vec3i::new(1, 0, 0), Block::Grass),
+        ]);
+        assert_eq!(serial.framing_errors_out, 0);
+        assert_eq!(serial.backlog(), 0);
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let (mut serial, tx) = connect(FAST);
+
+        tx.send(Event::Data(vec![0])).unwrap();
+        assert_eq!(run(&mut serial, 5), []);
+        tx.send(Event::Data(vec![3])).unwrap();
+        assert_eq!(run(&mut serial, 5), []);
+        tx.send(Event::Data(vec![1, 0])).unwrap();
+
+        let writes = run(&mut serial, 5).into_iter().map(|(_, w)| w).collect::<Vec<_>>();
+        assert_eq!(writes, [WindowUpdate::Write(Vec3i::new(0, 0, 0), Block::Dirt)]);
+
+        // The next frame picks up where its start byte left off
+        tx.send(Event::Data(vec![4, 1])).unwrap();
+        let writes = run(&mut serial, 5).into_iter().map(|(_, w)| w).collect::<Vec<_>>();
+        assert_eq!(writes, [WindowUpdate::Write(Vec3i::new(1, 0, 0), Block::Cobblestone)]);
+    }
+
+    #[test]
+    fn bytes_arrive_at_the_baud_rate() {
+        let link = UartLink::PLUGIN;
+        let (mut serial, tx) = connect(link);
+        tx.send(Event::Data(vec![0, 1, 1, 0, 2, 1])).unwrap();
+
+        // The first byte starts arriving on the first cycle, and each takes a byte's worth
+        let byte = link.cycles_per_byte();
+        let writes = run(&mut serial, 7 * byte);
+        assert_eq!(writes.iter().map(|&(cycle, _)| cycle).collect::<Vec<_>>(), [1 + 3 * byte, 1 + 6 * byte]);
+
+        // Everything came in at once, but only goes over the wire a byte at a time
+        let (mut serial, tx) = connect(link);
+        tx.send(Event::Data(vec![0, 1, 1, 0, 2, 1])).unwrap();
+        run(&mut serial, 1 + byte);
+        assert_eq!(serial.backlog(), 5);
+    }
+
+    #[test]
+    fn resyncs_after_framing_errors() {
+        let (mut serial, tx) = connect(FAST);
+
+        // A stray byte, then a frame without its stop byte whose 0 starts the next one
+        tx.send(Event::Data(vec![7, 0, 3, 0, 2, 1])).unwrap();
+
+        let writes = run(&mut serial, 20).into_iter().map(|(_, w)| w).collect::<Vec<_>>();
+        assert_eq!(writes, [WindowUpdate::Write(Vec3i::new(0, 0, 0), Block::Grass)]);
+        assert_eq!(serial.framing_errors_out, 2);
+    }
+}
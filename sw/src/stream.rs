@@ -0,0 +1,184 @@
+//! The host's side of the L3 cache's window: keeps it centred on the camera by sliding it
+//! whenever the camera crosses a block boundary, and sending the slice of the [World] that
+//! comes into view, one voxel at a time over the UART.
+//!
+//! This isn't part of the design, but it's clocked along with it so that the FPGA sees the
+//! slices arrive with the same latency as it would on the board.
+
+use crate::{cache::{Shift, Window, WindowUpdate}, math::{Vec3, Vec3i}, top_level::TopLevel, world::World};
+
+/// Timing of the UART between the host and the FPGA
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UartLink {
+    /// Bits per second
+    pub baud: usize,
+    /// Frequency of the FPGA's clock, in Hz
+    pub clock_hz: usize,
+    /// Bytes sent for every shift or voxel. The plugin frames each byte between a start
+    /// and a stop byte
+    pub bytes_per_message: usize,
+}
+
+impl UartLink {
+    /// What the plugin uses, into the 100MHz clock of the design
+    pub const PLUGIN: Self = Self {
+        baud: 115_200,
+        clock_hz: 100_000_000,
+        bytes_per_message: 3,
+    };
+
+    /// Clock cycles it takes for one shift or voxel to arrive, with a start and a stop bit
+    /// around every byte
+    pub fn cycles_per_message(&self) -> usize {
+        (self.bytes_per_message * 10 * self.clock_hz).div_ceil(self.baud)
+    }
+
+    /// Clock cycles it takes for one byte to arrive, with its start and stop bits
+    pub fn cycles_per_byte(&self) -> usize {
+        (10 * self.clock_hz).div_ceil(self.baud)
+    }
+}
+
+impl Default for UartLink {
+    fn default() -> Self {
+        Self::PLUGIN
+    }
+}
+
+#[derive(Debug)]
+pub struct Streamer {
+    /// Starts sending the current message over. The window itself isn't reset, like in the
+    /// [crate::cache::L3Cache]
+    pub reset: bool,
+    /// Camera's position in world space, which the window gets centred on
+    pub camera_pos_in: Vec3,
+    /// Message that finished arriving this cycle, for [crate::orchestrator::Orchestrator::window_in]
+    pub window_out: WindowUpdate,
+
+    /// Where the slices come from
+    world: World,
+    /// How fast they come
+    link: UartLink,
+    /// Corner of the window once the FPGA has received every message sent so far
+    min: Vec3i,
+    /// Slice being sent, by the shift that brought it into view, and how many of its voxels
+    /// were already sent
+    slice: Option<(Shift, usize)>,
+    /// Message being sent, and cycles until it's fully arrived
+    sending: Option<(WindowUpdate, usize)>,
+}
+
+impl Streamer {
+    /// Keeps `window` up to date with `world`, sending slices over `link`
+    pub fn new(world: World, window: &Window, link: UartLink) -> Self {
+        Self {
+            reset: false,
+            camera_pos_in: Vec3::default(),
+            window_out: WindowUpdate::None,
+            world,
+            link,
+            min: window.min(),
+            slice: None,
+            sending: None,
+        }
+    }
+
+    /// Number of slices that still have to be sent for the window to be centred on the
+    /// camera, including the one being sent
+    pub fn backlog(&self) -> usize {
+        let target = self.target();
+        let behind = (target.x - self.min.x).unsigned_abs() + (target.z - self.min.z).unsigned_abs();
+
+        usize::from(behind) + usize::from(self.slice.is_some())
+    }
+
+    pub fn rising_clk_edge(&mut self) {
+        if self.reset {
+            self.window_out = WindowUpdate::None;
+            self.sending = self.sending.map(|(message, _)| (message, self.link.cycles_per_message()));
+            return;
+        }
+
+        self.window_out = WindowUpdate::None;
+
+        match &mut self.sending {
+            Some((_, cycles)) if *cycles > 1 => {
+                *cycles -= 1;
+                return;
+            },
+            Some((message, _)) => self.window_out = *message,
+            None => (),
+        }
+
+        self.sending = self
+            .next_message()
+            .map(|message| (message, self.link.cycles_per_message()));
+    }
+
+    /// Next voxel of the slice being sent or, once it's done, the next shift towards the
+    /// camera, if any
+    fn next_message(&mut self) -> Option<WindowUpdate> {
+        let size = Window::SIZE;
+
+        if let Some((shift, i)) = &mut self.slice {
+            // Slice that came into view, along the opposite side of the window
+            let (a, b) = ((*i % size) as i16, (*i / size) as i16);
+            let last = size as i16 - 1;
+            let offset = match shift {
+                Shift::PosX => Vec3i::new(last, a, b),
+                Shift::NegX => Vec3i::new(0, a, b),
+                Shift::PosZ => Vec3i::new(a, b, last),
+                Shift::NegZ => Vec3i::new(a, b, 0),
+            };
+
+            *i += 1;
+            if *i == size * size {
+                self.slice = None;
+            }
+
+            let pos = self.min + offset;
+            let voxel = self.world.get([pos.x.into(), pos.y.into(), pos.z.into()]);
+            return Some(WindowUpdate::Write(offset, voxel));
+        }
+
+        let target = self.target();
+        let shift = if target.x > self.min.x {
+            Shift::PosX
+        } else if target.x < self.min.x {
+            Shift::NegX
+        } else if target.z > self.min.z {
+            Shift::PosZ
+        } else if target.z < self.min.z {
+            Shift::NegZ
+        } else {
+            return None;
+        };
+
+        match shift {
+            Shift::PosX => self.min.x += 1,
+            Shift::NegX => self.min.x -= 1,
+            Shift::PosZ => self.min.z += 1,
+            Shift::NegZ => self.min.z -= 1,
+        }
+        self.slice = Some((shift, 0));
+        Some(WindowUpdate::Shift(shift))
+    }
+
+    /// Corner of the window centred on the camera's block. Only its X and Z matter, the
+    /// window doesn't slide vertically
+    fn target(&self) -> Vec3i {
+        let half = (Window::SIZE / 2) as i16;
+
+        self.camera_pos_in.floor() - Vec3i::new(half, half, half)
+    }
+}
+
+/// Clocks `streamer` after `top_level`, following its camera and feeding its L3 cache on the
+/// next cycle
+pub fn clock(streamer: &mut Streamer, top_level: &mut TopLevel) {
+    streamer.reset = top_level.reset;
+    streamer.camera_pos_in = top_level.orchestrator.camera_pos_in;
+    streamer.rising_clk_edge();
+
+    top_level.orchestrator.window_in = streamer.window_out;
+}
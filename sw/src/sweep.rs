@@ -0,0 +1,308 @@
+//! Design-space exploration: renders the same camera paths with every combination of a
+//! few parameters of the architecture, and tabulates how fast each one is, to pick what to
+//! synthesize. Every run starts from reset, so the numbers are deterministic.
+
+use std::{fmt::Write as _, fs, path::PathBuf};
+
+use crate::{cache::{DdrTiming, Organization, Replacement}, camera::Pose, config::Config, counters::{CacheCounters, FrameCounters}, headless, orchestrator::Resolution};
+
+pub const USAGE: &str = "\
+usage: fpgacraft sweep --scene FILE [--scene FILE ...] [options]
+
+Renders every scene (a camera path, one pose per line as for `fpgacraft render`) with every
+combination of the parameters below, and reports the cycles per frame, the cache hit ratios
+and the frame rate they'd give at --clock-hz. Each VTU has its own L2 port, so --vtus also
+sweeps the number of L2 ports. Prints a table, and writes it as CSV to --out.
+
+swept options (comma-separated lists):
+    --vtus N,...                 Number of VTUs (default: 1)
+    --l2-entries N,...           Size of the L2 cache (default: 16)
+    --l3-latency N,...           Cycles from a DDR read to an open row until its data
+                                 comes back (default: from --ddr)
+    --render-steps N,...         Voxels a ray may step through (default: 110)
+    --resolution WxH,...         Size of the frames (default: 160x128)
+
+other options:
+    --frames N                   Frames per scene (default: one per pose)
+    --clock-hz N                 Clock of the design, for the frame rate (default: 100000000)
+    --threads N                  Clock the VTUs on N threads, which gives the same results,
+                                 only sooner with many VTUs (default: 1)
+    --out FILE                   Where to write the CSV (default: sweep.csv)
+    --ddr instant|FIELD=N,...    DDR timing, as for `fpgacraft render`
+    --l2-organization ORG        full, direct or N-way (default: full)
+    --l2-replacement POLICY      fifo, lru, plru or random (default: fifo)
+    --l1-entries N               Size of each VTU's private L1 cache (default: 0)
+    --shadows CYCLES             Shadow ray budget (default: 0, no shadows)";
+
+/// A camera path, as given on the command line
+#[derive(Debug)]
+struct Scene {
+    path: String,
+    poses: Vec<Pose>,
+}
+
+/// One point of the grid
+#[derive(Debug, Clone, Copy)]
+struct Point {
+    vtus: usize,
+    l2_entries: usize,
+    l3_latency: usize,
+    render_steps: usize,
+    resolution: Resolution,
+}
+
+#[derive(Debug)]
+struct Options {
+    scenes: Vec<Scene>,
+    points: Vec<Point>,
+    frames: Option<usize>,
+    clock_hz: u64,
+    out: PathBuf,
+    /// What isn't swept
+    base: Config,
+}
+
+impl Options {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut base = Config::default();
+        let mut scenes = vec![];
+        let mut vtus = vec![base.vtus];
+        let mut l2_entries = vec![base.l2_entries];
+        let mut l3_latency = None;
+        let mut render_steps = vec![base.render_distance.steps];
+        let mut resolutions = vec![base.resolution];
+        let mut frames = None;
+        let mut clock_hz = 100_000_000;
+        let mut out = PathBuf::from("sweep.csv");
+
+        let list = |s: &str, what: &str| s
+            .split(',')
+            .map(|n| n.trim().parse::<usize>().map_err(|e| format!("invalid {what} {n:?}: {e}")))
+            .collect::<Result<Vec<_>, _>>();
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args
+                .next()
+                .ok_or_else(|| format!("missing value for {arg}"));
+
+            match arg.as_str() {
+                "--scene" => {
+                    let path = value()?;
+                    let scene = fs::read_to_string(path).map_err(|e| format!("couldn't read {path}: {e}"))?;
+
+                    let poses = Pose::parse_scene(&scene)?;
+                    if poses.is_empty() {
+                        return Err(format!("scene {path} has no poses"));
+                    }
+                    scenes.push(Scene { path: path.clone(), poses });
+                },
+                "--vtus" => vtus = list(value()?, "number of VTUs")?,
+                "--l2-entries" => l2_entries = list(value()?, "L2 size")?,
+                "--l3-latency" => l3_latency = Some(list(value()?, "L3 latency")?),
+                "--render-steps" => render_steps = list(value()?, "render distance")?,
+                "--resolution" => resolutions = value()?
+                    .split(',')
+                    .map(|s| Resolution::parse(s.trim()))
+                    .collect::<Result<_, _>>()?,
+                "--frames" => frames = Some(value()?.parse().map_err(|e| format!("invalid frame count: {e}"))?),
+                "--clock-hz" => clock_hz = value()?.parse().map_err(|e| format!("invalid clock frequency: {e}"))?,
+                "--out" => out = value()?.into(),
+                "--threads" => base.threads = value()?.parse().map_err(|e| format!("invalid number of threads: {e}"))?,
+                "--ddr" => base.ddr = DdrTiming::parse(value()?)?,
+                "--l2-organization" => base.l2_organization = Organization::parse(value()?)?,
+                "--l2-replacement" => base.l2_replacement = Replacement::parse(value()?)?,
+                "--l1-entries" => base.l1_entries = value()?.parse().map_err(|e| format!("invalid L1 size: {e}"))?,
+                "--shadows" => base.shadow_cycles = value()?.parse().map_err(|e| format!("invalid shadow budget: {e}"))?,
+                _ => return Err(format!("unknown argument {arg:?}")),
+            }
+        }
+
+        if scenes.is_empty() {
+            return Err("at least one --scene is needed".into());
+        }
+        if frames == Some(0) {
+            return Err("at least one frame per scene is needed".into());
+        }
+        if clock_hz == 0 {
+            return Err("clock frequency must be at least 1 Hz".into());
+        }
+        let l3_latency = l3_latency.unwrap_or(vec![base.ddr.read_latency]);
+
+        let mut points = vec![];
+        for &vtus in &vtus {
+            for &l2_entries in &l2_entries {
+                for &l3_latency in &l3_latency {
+                    for &render_steps in &render_steps {
+                        for &resolution in &resolutions {
+                            points.push(Point { vtus, l2_entries, l3_latency, render_steps, resolution });
+                        }
+                    }
+                }
+            }
+        }
+
+        // Rather than finding out hours into the sweep
+        for point in &points {
+            point.config(&base).validate()?;
+        }
+
+        Ok(Self { scenes, points, frames, clock_hz, out, base })
+    }
+}
+
+impl Point {
+    fn config(&self, base: &Config) -> Config {
+        let mut config = base.clone();
+        config.vtus = self.vtus;
+        config.l2_entries = self.l2_entries;
+        config.ddr.read_latency = self.l3_latency;
+        config.render_distance.steps = self.render_steps;
+        config.resolution = self.resolution;
+        config
+    }
+}
+
+/// What one point of the grid did on one scene
+#[derive(Debug)]
+struct Outcome {
+    frames: usize,
+    mean_cycles: f64,
+    max_cycles: usize,
+    l1: CacheCounters,
+    l2: CacheCounters,
+    l3_reads: usize,
+    l3_row_misses: usize,
+    /// Fraction of the VTUs' cycles spent waiting on the caches
+    vtu_stalls: f64,
+}
+
+impl Outcome {
+    fn new(counters: &[FrameCounters]) -> Self {
+        let cycles = counters.iter().map(|c| c.orchestrator.cycles).sum::<usize>();
+        let vtu_cycles = counters.iter().map(|c| c.orchestrator.cycles * c.vtu.len()).sum::<usize>();
+        let stalls = counters.iter().flat_map(|c| &c.vtu).map(|vtu| vtu.stall_cycles).sum::<usize>();
+
+        Self {
+            frames: counters.len(),
+            mean_cycles: cycles as f64 / counters.len() as f64,
+            max_cycles: counters.iter().map(|c| c.orchestrator.cycles).max().unwrap_or(0),
+            l1: counters.iter().map(FrameCounters::l1_total).fold(Default::default(), |a, b| a + b),
+            l2: counters.iter().map(|c| c.l2.total()).fold(Default::default(), |a, b| a + b),
+            l3_reads: counters.iter().map(|c| c.l3.reads).sum(),
+            l3_row_misses: counters.iter().map(|c| c.l3.row_misses).sum(),
+            vtu_stalls: stalls as f64 / vtu_cycles.max(1) as f64,
+        }
+    }
+
+    fn fps(&self, clock_hz: u64) -> f64 {
+        clock_hz as f64 / self.mean_cycles
+    }
+}
+
+/// Entry point of `fpgacraft sweep`
+pub fn main(args: &[String]) -> Result<(), String> {
+    if args.iter().any(|a| a == "--help" || a == "-h") {
+        println!("{USAGE}");
+        return Ok(());
+    }
+
+    let opts = Options::parse(args)?;
+    let runs = opts.points.len() * opts.scenes.len();
+
+    let mut csv = String::from("scene,vtus,l2_entries,l3_latency,render_steps,resolution,frames,mean_cycles,max_cycles,fps,l1_hit_ratio,l2_hit_ratio,l3_reads,l3_row_misses,vtu_stall_ratio\n");
+    println!(
+        "{:<20} {:>4} {:>4} {:>4} {:>5} {:>9} {:>12} {:>12} {:>8} {:>7} {:>7} {:>10} {:>7}",
+        "scene", "vtus", "l2", "l3", "steps", "res", "cycles/frame", "max", "fps", "l1 hit", "l2 hit", "l3 reads", "stalls",
+    );
+
+    for (i, point) in opts.points.iter().enumerate() {
+        for (j, scene) in opts.scenes.iter().enumerate() {
+            eprintln!("run {}/{runs}: {} with {point:?}", i * opts.scenes.len() + j + 1, scene.path);
+
+            let mut top_level = headless::reset_top_level(point.config(&opts.base));
+            let counters = scene.poses
+                .iter()
+                .cycle()
+                .take(opts.frames.unwrap_or(scene.poses.len()))
+                .map(|pose| headless::render_frame(&mut top_level, pose))
+                .collect::<Vec<_>>();
+            let outcome = Outcome::new(&counters);
+
+            // No L1 at all isn't a 0% hit ratio
+            let ratio = |c: &CacheCounters| match c.accesses() {
+                0 => String::new(),
+                _ => format!("{:.4}", c.hit_ratio()),
+            };
+            writeln!(
+                csv,
+                "{},{},{},{},{},{},{},{:.1},{},{:.3},{},{},{},{},{:.4}",
+                scene.path, point.vtus, point.l2_entries, point.l3_latency, point.render_steps, point.resolution,
+                outcome.frames, outcome.mean_cycles, outcome.max_cycles, outcome.fps(opts.clock_hz),
+                ratio(&outcome.l1), ratio(&outcome.l2), outcome.l3_reads, outcome.l3_row_misses, outcome.vtu_stalls,
+            ).unwrap();
+
+            let percent = |c: &CacheCounters| match c.accesses() {
+                0 => "-".into(),
+                _ => format!("{:.1}%", 100.0 * c.hit_ratio()),
+            };
+            println!(
+                "{:<20} {:>4} {:>4} {:>4} {:>5} {:>9} {:>12.0} {:>12} {:>8.2} {:>7} {:>7} {:>10} {:>6.1}%",
+                scene.path, point.vtus, point.l2_entries, point.l3_latency, point.render_steps, point.resolution.to_string(),
+                outcome.mean_cycles, outcome.max_cycles, outcome.fps(opts.clock_hz),
+                percent(&outcome.l1), percent(&outcome.l2), outcome.l3_reads, 100.0 * outcome.vtu_stalls,
+            );
+        }
+    }
+
+    fs::write(&opts.out, csv).map_err(|e| format!("couldn't write {}: {e}", opts.out.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Arguments for a sweep of a one-pose scene at a small resolution, plus `args`, with
+    /// its files in the temporary directory under `name`
+    fn args(name: &str, args: &[&str]) -> (PathBuf, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("fpgacraft-sweep-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("scene.txt"), "0.5 5.5 0.5 30 -20\n").unwrap();
+
+        let scene = dir.join("scene.txt").display().to_string();
+        let out = dir.join("sweep.csv").display().to_string();
+        let args = ["--scene", &scene, "--out", &out, "--resolution", "32x16"]
+            .iter()
+            .chain(args)
+            .map(|s| s.to_string())
+            .collect();
+        (dir, args)
+    }
+
+    #[test]
+    fn more_than_64_l2_entries() {
+        for replacement in ["fifo", "lru"] {
+            let (dir, args) = args(replacement, &["--l2-entries", "16,128", "--l2-replacement", replacement]);
+            main(&args).unwrap();
+
+            let csv = fs::read_to_string(dir.join("sweep.csv")).unwrap();
+            fs::remove_dir_all(dir).unwrap();
+
+            let l2_entries = csv
+                .lines()
+                .skip(1)
+                .map(|line| line.split(',').nth(2).unwrap())
+                .collect::<Vec<_>>();
+            assert_eq!(l2_entries, ["16", "128"], "{replacement}");
+        }
+    }
+
+    #[test]
+    fn pseudo_lru_at_most_64_ways() {
+        let (dir, args) = args("plru", &["--l2-entries", "16,128", "--l2-replacement", "plru"]);
+        let err = Options::parse(&args).unwrap_err();
+        fs::remove_dir_all(dir).unwrap();
+
+        assert_eq!(err, "pseudo-LRU supports at most 64 ways, got 128");
+    }
+}
@@ -0,0 +1,183 @@
+//! Block textures. The [TextureAtlas] is a ROM of 16x16 RGB565 tiles, three per block (top,
+//! side, bottom), that the [crate::orchestrator::Orchestrator] samples where a ray hit a face.
+//! In hardware, it'd be a BRAM initialized at synthesis, e.g. with `$readmemh`.
+
+use std::path::Path;
+
+use nannou::image::{self, RgbImage};
+
+use crate::{block::Block, fixed, math::{Fixed, Rgb565, Vec3}};
+
+/// Which tile of a block a face uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Face {
+    Top,
+    Side,
+    Bottom,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextureAtlas {
+    /// Every texel, tile after tile: `TILE_SIZE * TILE_SIZE` texels per tile, row by row.
+    /// Tiles are ordered by block id, then by [Face]
+    texels: Box<[Rgb565]>,
+}
+
+impl Face {
+    /// Face that a ray hit, from the surface normal the VTU found
+    pub fn from_normal(normal: Vec3) -> Self {
+        if normal.y > fixed!(0.0) {
+            Face::Top
+        } else if normal.y < fixed!(0.0) {
+            Face::Bottom
+        } else {
+            Face::Side
+        }
+    }
+}
+
+impl TextureAtlas {
+    /// Width and height of a tile, in texels
+    pub const TILE_SIZE: usize = 16;
+
+    /// Number of tiles per block, one per [Face]
+    pub const FACES: usize = 3;
+
+    /// Number of blocks in the atlas, including [Block::Air] which is never sampled but
+    /// keeps addresses simple
+    pub const BLOCKS: usize = Block::COUNT;
+
+    /// Loads an atlas from an image that's `3 * 16` texels wide (top, side and bottom
+    /// tiles, left to right) and has one row of 16 texels per block, in order of id
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let image = image::open(path)
+            .map_err(|e| format!("couldn't read texture atlas {}: {e}", path.display()))?
+            .to_rgb8();
+
+        Self::from_image(&image).map_err(|e| format!("{}: {e}", path.display()))
+    }
+
+    /// Same as [TextureAtlas::load], from an image that's already loaded
+    pub fn from_image(image: &RgbImage) -> Result<Self, String> {
+        let width = Self::FACES * Self::TILE_SIZE;
+        let height = Self::BLOCKS * Self::TILE_SIZE;
+        if image.dimensions() != (width as u32, height as u32) {
+            return Err(format!(
+                "texture atlas must be {width}x{height}, got {}x{}",
+                image.width(),
+                image.height(),
+            ));
+        }
+
+        let mut texels = vec![Rgb565::default(); Self::BLOCKS * Self::FACES * Self::TILE_SIZE * Self::TILE_SIZE];
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let (x, y) = (x as usize, y as usize);
+            let [r, g, b] = pixel.0;
+            let tile = (y / Self::TILE_SIZE) * Self::FACES + x / Self::TILE_SIZE;
+
+            texels[Self::address(tile, x % Self::TILE_SIZE, y % Self::TILE_SIZE)] = Rgb565::new(r, g, b);
+        }
+
+        Ok(Self { texels: texels.into() })
+    }
+
+    /// Atlas that doesn't need any file: each block's [Block::color] with some noise, and a
+    /// few patterns so that grass, logs, planks and leaves can be told apart
+    pub fn builtin() -> Self {
+        let mut texels = vec![Rgb565::default(); Self::BLOCKS * Self::FACES * Self::TILE_SIZE * Self::TILE_SIZE];
+
+        for id in 0..Self::BLOCKS {
+            let block = Block::try_from(id as u8).unwrap();
+
+            for (f, face) in [Face::Top, Face::Side, Face::Bottom].into_iter().enumerate() {
+                for y in 0..Self::TILE_SIZE {
+                    for x in 0..Self::TILE_SIZE {
+                        let noise = hash(id, f, x, y);
+                        let (base, shade) = builtin_texel(block, face, x, y, noise);
+
+                        texels[Self::address(id * Self::FACES + f, x, y)] = scale(base, shade);
+                    }
+                }
+            }
+        }
+
+        Self { texels: texels.into() }
+    }
+
+    /// Texel of `block`'s `face` at `(u, v)`, each in `[0, 1)`. `v` goes down the tile
+    pub fn sample(&self, block: Block, face: Face, u: Fixed, v: Fixed) -> Rgb565 {
+        let tile = block as usize * Self::FACES + face as usize;
+
+        self.texels[Self::address(tile, Self::texel(u), Self::texel(v))]
+    }
+
+    /// Size of the atlas in BRAM, in bits
+    pub fn bits(&self) -> usize {
+        self.texels.len() * 16
+    }
+
+    /// Texel coordinate of `x` in `[0, 1)`, i.e. its top 4 fractional bits
+    fn texel(x: Fixed) -> usize {
+        let i = (x * Fixed::from(Self::TILE_SIZE as i16)).floor();
+
+        i.clamp(0, Self::TILE_SIZE as i16 - 1) as usize
+    }
+
+    fn address(tile: usize, x: usize, y: usize) -> usize {
+        (tile * Self::TILE_SIZE + y) * Self::TILE_SIZE + x
+    }
+}
+
+/// Colour and brightness of a texel of [TextureAtlas::builtin]. `noise` is in `[0, 1)`
+fn builtin_texel(block: Block, face: Face, x: usize, y: usize, noise: f32) -> (Rgb565, f32) {
+    let shade = 0.85 + 0.3 * noise;
+
+    match (block, face) {
+        // Dirt, with a fringe of grass on the sides
+        (Block::Grass, Face::Side) if y < 3 || (y == 3 && noise > 0.5) => (Block::Grass.color(), shade),
+        (Block::Grass, Face::Side | Face::Bottom) => (Block::Dirt.color(), shade),
+
+        // Rings on the ends of logs, bark on their sides
+        (Block::OakLog | Block::SpruceLog | Block::BirchLog, Face::Top | Face::Bottom) => {
+            let ring = x.min(y).min(15 - x).min(15 - y);
+            (Block::OakPlanks.color(), if ring.is_multiple_of(3) { 0.8 } else { 1.1 } + 0.1 * noise)
+        },
+        (Block::OakLog | Block::SpruceLog | Block::BirchLog, Face::Side) => {
+            (block.color(), if x.is_multiple_of(4) { 0.75 } else { shade })
+        },
+
+        // Boards
+        (Block::OakPlanks | Block::SprucePlanks | Block::BirchPlanks, _) => {
+            (block.color(), if y % 4 == 3 { 0.7 } else { 0.95 + 0.1 * noise })
+        },
+
+        // Gaps in between leaves
+        (Block::OakLeaves | Block::SpruceLeaves | Block::BirchLeaves, _) => {
+            (block.color(), if noise < 0.2 { 0.5 } else { shade })
+        },
+
+        // A frame around glass
+        (Block::Glass, _) if x == 0 || y == 0 || x == 15 || y == 15 => (block.color(), 1.2),
+
+        _ => (block.color(), shade),
+    }
+}
+
+/// `color` times `shade`, saturating
+fn scale(color: Rgb565, shade: f32) -> Rgb565 {
+    let channel = |c: u8| (c as f32 * shade).clamp(0.0, 255.0) as u8;
+
+    Rgb565::new(channel(color.r()), channel(color.g()), channel(color.b()))
+}
+
+/// Deterministic noise in `[0, 1)` for a texel of the builtin atlas
+fn hash(block: usize, face: usize, x: usize, y: usize) -> f32 {
+    let mut h = (block * 0x9E37 + face * 0x85EB + y * 0xC2B2 + x) as u32;
+    h ^= h >> 16;
+    h = h.wrapping_mul(0x7FEB_352D);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x846C_A68B);
+    h ^= h >> 16;
+
+    (h >> 8) as f32 / (1 << 24) as f32
+}
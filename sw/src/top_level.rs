@@ -8,16 +8,17 @@
 //!     - Every module should implement [Default], and have a `reset` signal to set the appropriate values
 //!     - Registers/submodules that aren't input/outputs should be private fields
 
-use crate::{math::Vec3, orchestrator::Orchestrator};
+use crate::{config::Config, display::Display, math::Vec3, orchestrator::Orchestrator, trace::{Trace, Tracer}};
 
-/// How many instances of voxel traversal units (VTU) we have.
+/// How many instances of voxel traversal units (VTU) we have by default, see
+/// [Config::vtus].
 /// 
 /// Lower numbers (e.g. 1) are good for simulation in realtime, but higher
 /// numbers (e.g. 16) give us a better idea of how it will perform in hardware,
-/// at the cost of running simulation at ~1FPS
+/// at the cost of running simulation at ~1FPS. `fpgacraft sweep` compares them
 pub const NUM_VTU: usize = 1;
 
-/// How many entries in the L2 cache
+/// How many entries in the L2 cache by default, see [Config::l2_entries]
 pub const NUM_L2_ENTRIES: usize = 16;
 
 #[derive(Debug, Default)]
@@ -26,17 +27,61 @@ pub struct TopLevel {
     pub reset: bool,
     /// Module that manages the VTUs
     pub orchestrator: Orchestrator,
+    /// Framebuffers and HDMI scanout, if they're simulated
+    pub display: Option<Display>,
 }
 
 impl TopLevel {
+    pub fn new(config: Config) -> Self {
+        let display = config.display.map(|buffering| Display::default()
+            .with_buffering(buffering)
+            .with_timing(config.video)
+            .with_resolution(config.resolution)
+        );
+
+        Self {
+            reset: false,
+            orchestrator: Orchestrator::new(config),
+            display,
+        }
+    }
+
     pub fn rising_clk_edge(&mut self) {
         // Propagate signals to owned submodules
         self.orchestrator.reset = self.reset;
+        if let Some(display) = &self.display {
+            self.orchestrator.frame_start_in = display.frame_start_out;
+        }
         self.orchestrator.rising_clk_edge();
 
+        if let Some(display) = &mut self.display {
+            display.reset = self.reset;
+            display.pixel_write_in = self.orchestrator.pixel_write_out;
+            display.frame_done_in = self.orchestrator.frame_done_out;
+            display.rising_clk_edge();
+
+            // The display's counters go along with the frame's
+            if self.orchestrator.frame_done_out {
+                self.orchestrator.counters_out.display = std::mem::take(&mut display.counters);
+            }
+        }
+
         if self.reset {
             self.orchestrator.camera_heading_in = Vec3::FORWARD;
+            self.orchestrator.camera_up_in = Vec3::UP;
             self.orchestrator.camera_pos_in = Vec3::default();
         }
     }
-}
\ No newline at end of file
+}
+
+impl Trace for TopLevel {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope("top_level", |t| {
+            t.bit("reset", self.reset);
+            self.orchestrator.trace(t);
+            if let Some(display) = &self.display {
+                display.trace(t);
+            }
+        });
+    }
+}
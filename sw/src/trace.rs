@@ -0,0 +1,245 @@
+//! Waveform tracing. Modules implement [Trace] to record their inputs and outputs, and
+//! [Vcd] samples them after every clock edge into a VCD file that GTKWave can open next to
+//! the waveforms of the cocotb tests in `hw/src/sim`.
+//!
+//! Signals are recorded with the same widths as in the SystemVerilog: [Fixed] numbers are
+//! their raw `B`-bit two's complement, blocks are 5 bits, etc.
+
+use std::io::{self, Write};
+
+use crate::{block::Block, math::{Fixed, Vec3, Vec3i}};
+
+/// Clock period, in `ns`. Same as the cocotb tests, i.e. 100MHz
+const CLOCK_PERIOD: u64 = 10;
+
+/// A module whose signals can be recorded
+pub trait Trace {
+    /// Records every input and output of the module, and those of the modules it owns in
+    /// their own [Tracer::scope]. Signals must be recorded in the same order every cycle.
+    fn trace(&self, t: &mut Tracer);
+}
+
+/// Collects the value of every signal during one cycle
+#[derive(Debug, Default)]
+pub struct Tracer {
+    /// Whether signals are being recorded for the first time, i.e. need to be declared
+    declaring: bool,
+    /// Scopes that signals are currently being recorded in, outermost first
+    scope: Vec<String>,
+    /// Every signal, in the order they're recorded
+    vars: Vec<Var>,
+    /// Value of every signal this cycle, same order as [Tracer::vars]
+    values: Vec<u64>,
+}
+
+/// Declaration of a signal
+#[derive(Debug)]
+struct Var {
+    scope: Vec<String>,
+    name: String,
+    width: u32,
+}
+
+impl Tracer {
+    /// Records the signals of `f` in a nested scope, e.g. a submodule
+    pub fn scope(&mut self, name: impl Into<String>, f: impl FnOnce(&mut Self)) {
+        if self.declaring {
+            self.scope.push(name.into());
+        }
+        f(self);
+        if self.declaring {
+            self.scope.pop();
+        }
+    }
+
+    /// Records a `width`-bit signal, whose value is the low bits of `value`
+    pub fn bits(&mut self, name: &str, width: u32, value: u64) {
+        if self.declaring {
+            self.vars.push(Var {
+                scope: self.scope.clone(),
+                name: name.into(),
+                width,
+            });
+        }
+
+        let mask = u64::MAX >> (64 - width);
+        self.values.push(value & mask);
+    }
+
+    pub fn bit(&mut self, name: &str, value: bool) {
+        self.bits(name, 1, value.into());
+    }
+
+    pub fn fixed(&mut self, name: &str, value: Fixed) {
+        self.bits(name, Fixed::B as _, value.to_raw() as u64);
+    }
+
+    pub fn block(&mut self, name: &str, value: Block) {
+        self.bits(name, 5, value as u64);
+    }
+
+    /// Records each component as its own signal, in a scope named `name`
+    pub fn vec3(&mut self, name: &str, value: Vec3) {
+        self.scope(name, |t| {
+            t.fixed("x", value.x);
+            t.fixed("y", value.y);
+            t.fixed("z", value.z);
+        });
+    }
+
+    /// Records each component as its own signal, in a scope named `name`
+    pub fn vec3i(&mut self, name: &str, value: Vec3i) {
+        self.scope(name, |t| {
+            t.bits("x", 16, value.x as u64);
+            t.bits("y", 16, value.y as u64);
+            t.bits("z", 16, value.z as u64);
+        });
+    }
+}
+
+/// Writes a VCD file, one sample per clock cycle
+#[derive(Debug)]
+pub struct Vcd<W: Write> {
+    out: W,
+    tracer: Tracer,
+    /// Values written at the last sample, to only write the ones that changed
+    last: Vec<u64>,
+    /// Number of samples so far
+    cycle: u64,
+    /// Cycles after which samples are ignored
+    max_cycles: u64,
+    /// First error that happened while writing, if any. Reported by [Vcd::finish]
+    error: Option<io::Error>,
+}
+
+impl<W: Write> Vcd<W> {
+    pub fn new(out: W) -> Self {
+        Self {
+            out,
+            tracer: Tracer::default(),
+            last: Vec::new(),
+            cycle: 0,
+            max_cycles: u64::MAX,
+            error: None,
+        }
+    }
+
+    /// Only records the first `cycles` samples, the rest are ignored
+    pub fn with_max_cycles(mut self, cycles: u64) -> Self {
+        self.max_cycles = cycles;
+        self
+    }
+
+    /// Records the signals of `module`, right after a rising clock edge
+    pub fn sample(&mut self, module: &impl Trace) {
+        if self.error.is_some() || self.cycle >= self.max_cycles {
+            return;
+        }
+
+        self.tracer.declaring = self.cycle == 0;
+        self.tracer.values.clear();
+        module.trace(&mut self.tracer);
+        assert_eq!(self.tracer.values.len(), self.tracer.vars.len(), "signals changed between cycles");
+
+        let result = match self.cycle {
+            0 => self.write_header(),
+            _ => self.write_changes(),
+        };
+        if let Err(e) = result {
+            self.error = Some(e);
+        }
+
+        std::mem::swap(&mut self.last, &mut self.tracer.values);
+        self.cycle += 1;
+    }
+
+    /// Flushes the file, returning the first error that happened while writing it
+    pub fn finish(mut self) -> io::Result<W> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        // Falling edge that ends the last cycle
+        if self.cycle > 0 {
+            writeln!(self.out, "#{}\n0{}", self.cycle * CLOCK_PERIOD - CLOCK_PERIOD / 2, id(0))?;
+        }
+        self.out.flush()?;
+        Ok(self.out)
+    }
+
+    fn write_header(&mut self) -> io::Result<()> {
+        writeln!(self.out, "$version fpgacraft sw simulator $end")?;
+        writeln!(self.out, "$timescale 1ns $end")?;
+
+        // The clock isn't a signal of any module, it gets the first identifier
+        writeln!(self.out, "$var wire 1 {} clk_in $end", id(0))?;
+
+        let mut scope: &[String] = &[];
+        for (i, var) in self.tracer.vars.iter().enumerate() {
+            let common = zip_len(scope, &var.scope);
+            for _ in common..scope.len() {
+                writeln!(self.out, "$upscope $end")?;
+            }
+            for name in &var.scope[common..] {
+                writeln!(self.out, "$scope module {name} $end")?;
+            }
+            scope = &var.scope;
+
+            writeln!(self.out, "$var wire {} {} {} $end", var.width, id(i + 1), var.name)?;
+        }
+        for _ in scope {
+            writeln!(self.out, "$upscope $end")?;
+        }
+        writeln!(self.out, "$enddefinitions $end")?;
+
+        // Initial values
+        writeln!(self.out, "#0\n$dumpvars\n1{}", id(0))?;
+        for i in 0..self.tracer.values.len() {
+            self.write_value(i, self.tracer.values[i])?;
+        }
+        writeln!(self.out, "$end")
+    }
+
+    fn write_changes(&mut self) -> io::Result<()> {
+        let t = self.cycle * CLOCK_PERIOD;
+
+        writeln!(self.out, "#{}\n0{}", t - CLOCK_PERIOD / 2, id(0))?;
+        writeln!(self.out, "#{t}\n1{}", id(0))?;
+        for i in 0..self.tracer.values.len() {
+            let value = self.tracer.values[i];
+            if value != self.last[i] {
+                self.write_value(i, value)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the value of the `i`th signal
+    fn write_value(&mut self, i: usize, value: u64) -> io::Result<()> {
+        match self.tracer.vars[i].width {
+            1 => writeln!(self.out, "{value}{}", id(i + 1)),
+            _ => writeln!(self.out, "b{value:b} {}", id(i + 1)),
+        }
+    }
+}
+
+/// Short identifier of the `i`th signal, made of printable ASCII characters
+fn id(mut i: usize) -> String {
+    let mut id = String::new();
+    loop {
+        id.push((b'!' + (i % 94) as u8) as char);
+        i /= 94;
+        if i == 0 {
+            return id;
+        }
+        i -= 1;
+    }
+}
+
+/// Length of the common prefix of `a` and `b`
+fn zip_len(a: &[String], b: &[String]) -> usize {
+    a.iter()
+        .zip(b)
+        .take_while(|(a, b)| a == b)
+        .count()
+}
@@ -1,6 +1,4 @@
-use std::{cell::RefCell, rc::Rc};
-
-use crate::{block::Block, cache::{L2Cache, MockCache}, fixed, math::{Fixed, Vec3, Vec3i}, top_level::{NUM_L2_ENTRIES, NUM_VTU}};
+use crate::{block::{Block, Material}, cache::{Invalidate, L1Cache, L2Port, MockCache, Window}, counters::{CacheCounters, VtuCounters}, fixed, math::{overflow, Fixed, Rgb565, Scalar, Vec3, Vec3i}, trace::{Trace, Tracer}};
 
 #[derive(Debug, Default)]
 pub struct VoxelTraversalUnit {
@@ -10,24 +8,49 @@ pub struct VoxelTraversalUnit {
     /// Note: in Verilog, this would be statically determined using a genvar block, but
     /// in Rust using (equivalent) const-generic leads to more trouble than it's worth
     pub index: usize,
-    /// The current pixel being computed
-    pub current_pixel: usize,
     /// World-space coordinate where the ray begins, e.g. camera's position
     pub ray_origin_in: Vec3,
     /// Heading of the ray. Doesn't need to be normalized.
     pub ray_direction_in: Vec3,
     /// Reinitialize the internal state of the VTU with the input parameters?
     pub ray_init_in: bool,
+    /// Direction towards the sun, for shadow rays. Doesn't need to be normalized.
+    pub sun_in: Vec3,
+    /// Corner with the smallest coordinates of the voxels the L3 cache holds, i.e. of its
+    /// [Window]. Rays that leave it for good are done
+    pub window_min_in: Vec3i,
+    /// Entries of the L1 cache to drop this cycle, as the L3's window changes
+    pub invalidate_in: Invalidate,
     /// The voxel that this VTU last intersected with
     pub voxel_out: Block,
     /// Surface normal of the voxel hit
     pub normal_out: Vec3,
+    /// Where the ray hit the face of the voxel, each in `[0, 1)`. Horizontal then downwards
+    /// on side faces, `x` then `z` on top and bottom faces
+    pub face_uv_out: [Fixed; 2],
+    /// Whether something is in between the hit and the sun. Always false without shadows
+    pub shadowed_out: bool,
+    /// Colour of the translucent blocks in front of the hit, premultiplied by their alpha
+    pub tint_out: Rgb565,
+    /// How much of the hit shows through [VoxelTraversalUnit::tint_out], 1 if the ray only
+    /// went through air
+    pub transmittance_out: Fixed,
     /// Whether or not [VoxelTraversalUnit::voxel_out] corresponds to the inputs
     pub valid_out: bool,
-    /// Reference to an L2 cache used for cache misses
-    /// TODO: store also an L1 cache
-    pub l2: Rc<RefCell<L2Cache<{NUM_VTU}, {NUM_L2_ENTRIES}>>>,
+    /// This VTU's port of the L2 cache, used for cache misses (of the L1, if there is one)
+    pub l2: L2Port,
+    /// Performance counters, not part of the design
+    pub counters: VtuCounters,
+
+    /// Private cache in front of the L2, if any
+    l1: Option<L1Cache>,
+    /// Cycles a shadow ray gets before giving up on it, 0 for no shadow rays at all
+    shadow_budget: usize,
+    /// How far rays go before giving up
+    render_distance: RenderDistance,
 
+    /// Where the ray being traversed begins
+    ray_origin: Vec3,
     /// Normalized ray direction being traversed
     ray_direction: Vec3,
     /// Current voxel along the ray being traversed
@@ -42,12 +65,77 @@ pub struct VoxelTraversalUnit {
     ray_t_max: Vec3,
     /// How many voxels has the ray traversed, so far?
     num_steps: usize,
+    /// Where the ray entered the current voxel, in units of t
+    ray_t: Fixed,
     /// Along which axis was the last step taken?
     last_step: Axis,
+    /// Whether the ray being traversed is a shadow ray, i.e. the primary ray is done
+    shadow_ray: bool,
+    /// Cycles spent on the current shadow ray
+    shadow_cycles: usize,
 }
 
-#[derive(Debug, Default)]
-enum Axis {
+/// How far a ray goes before giving up, in which case it hit the sky. Both limits apply,
+/// whichever comes first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderDistance {
+    /// Voxels a ray may step through
+    pub steps: usize,
+    /// Distance from the ray's origin, in blocks (ray directions are normalized, so that's
+    /// also units of t), or [None] for no limit
+    pub t: Option<Fixed>,
+}
+
+impl RenderDistance {
+    /// Parses a comma-separated list of `field=value` overrides on top of the default, with
+    /// `steps` as a number of voxels and `t` in blocks
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let mut distance = Self::default();
+
+        for kv in s.split(',').filter(|kv| !kv.is_empty()) {
+            let Some((key, value)) = kv.split_once('=') else {
+                return Err(format!("expected field=value in render distance, got {kv:?}"));
+            };
+
+            match key {
+                "steps" => distance.steps = value
+                    .parse()
+                    .map_err(|e| format!("invalid render distance steps: {e}"))?,
+                "t" => {
+                    let t: f32 = value
+                        .parse()
+                        .map_err(|e| format!("invalid render distance t: {e}"))?;
+                    if !(t > 0.0 && t < f32::from(Fixed::MAX)) {
+                        return Err(format!("render distance t must be between 0 and {}, got {t}", f32::from(Fixed::MAX)));
+                    }
+                    distance.t = Some(fixed!(t));
+                },
+                _ => return Err(format!("unknown render distance field {key:?}")),
+            }
+        }
+        Ok(distance)
+    }
+
+    /// Whether a ray that went through `steps` voxels and entered the current one at `t` is
+    /// done. `t` can be in any [Scalar], as it's compared as a real number
+    fn reached<T: Scalar>(&self, steps: usize, t: T) -> bool {
+        steps > self.steps || self.t.is_some_and(|max| t.to_f32() > max.to_f32())
+    }
+}
+
+impl Default for RenderDistance {
+    fn default() -> Self {
+        Self {
+            steps: 110,
+            t: None,
+        }
+    }
+}
+
+/// Axis along which a ray stepped into its current voxel
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Axis {
+    /// Still in the voxel it started in
     #[default]
     None,
     X,
@@ -55,24 +143,105 @@ enum Axis {
     Z,
 }
 
+/// Where a [VoxelTraversalUnit] is along its ray, for the ray debugger. Not part of the
+/// design, just a window into its registers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Traversal {
+    /// Current voxel
+    pub position: Vec3i,
+    /// Where the ray entered [Traversal::position], in units of t
+    pub t: Fixed,
+    /// Nearest voxel boundary along each axis, in units of t
+    pub t_max: Vec3,
+    /// Axis stepped along to get into [Traversal::position]
+    pub last_step: Axis,
+    /// Voxels stepped through since the ray started
+    pub steps: usize,
+    /// Whether this is the shadow ray, i.e. the primary ray is done
+    pub shadow_ray: bool,
+}
+
+/// What [VoxelTraversalUnit::mock_trace] found along a ray, like the VTU's outputs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockHit<T> {
+    /// Block the ray stopped at, air if none
+    pub block: Block,
+    /// Normal of the face it went in through
+    pub normal: Vec3<T>,
+    /// Where it went in, see [VoxelTraversalUnit::face_uv_out]
+    pub face_uv: [T; 2],
+    /// Translucent blocks in front of [MockHit::block]
+    pub tint: Rgb565,
+    /// How much of [MockHit::block] shows through them
+    pub transmittance: T,
+}
+
 impl VoxelTraversalUnit {
     pub fn with_index(mut self, i: usize) -> Self {
         self.index = i;
         self
     }
 
+    /// Puts an L1 cache of `entries` entries in front of the L2, or none if `entries == 0`
+    pub fn with_l1(mut self, entries: usize) -> Self {
+        self.l1 = (entries > 0).then(|| L1Cache::default().with_size(entries));
+        self
+    }
+
+    /// After hitting a block, traces a second ray from the hit towards [VoxelTraversalUnit::sun_in]
+    /// to find whether it's in the shadow, for at most `budget` cycles. 0 for no shadow rays.
+    pub fn with_shadows(mut self, budget: usize) -> Self {
+        self.shadow_budget = budget;
+        self
+    }
+
+    /// Gives up on rays past `distance`
+    pub fn with_render_distance(mut self, distance: RenderDistance) -> Self {
+        self.render_distance = distance;
+        self
+    }
+
+    /// Performance counters of the L1 cache, if there is one
+    pub fn l1_counters_mut(&mut self) -> Option<&mut CacheCounters> {
+        self.l1.as_mut().map(|l1| &mut l1.counters)
+    }
+
+    /// Where the ray being traversed is at, see [Traversal]
+    pub fn traversal(&self) -> Traversal {
+        Traversal {
+            position: self.ray_position,
+            t: self.ray_t,
+            t_max: self.ray_t_max,
+            last_step: self.last_step,
+            steps: self.num_steps,
+            shadow_ray: self.shadow_ray,
+        }
+    }
+
     /// Unrealistic ray cast that finishes instantly, for testing
+    #[allow(unused)]
     pub fn mock_cast(&mut self) {
-        let cache = MockCache::default();
+        let hit = Self::mock_trace(&MockCache::default(), &self.render_distance, self.ray_origin_in, self.ray_direction_in);
+
+        self.voxel_out = hit.block;
+        self.normal_out = hit.normal;
+        self.face_uv_out = hit.face_uv;
+        self.tint_out = hit.tint;
+        self.transmittance_out = hit.transmittance;
+        self.valid_out = true;
+    }
 
-        let ray_ori = self.ray_origin_in;
-        let ray_dir = self.ray_direction_in.normalized();
+    /// The ray cast of [VoxelTraversalUnit::mock_cast] through `cache` up to `distance`,
+    /// with its maths done in any [Scalar], e.g. to compare formats
+    pub fn mock_trace<T: Scalar>(cache: &MockCache, distance: &RenderDistance, ray_ori: Vec3<T>, ray_dir: Vec3<T>) -> MockHit<T> {
+        let (zero, one) = (T::from_int(0), T::from_int(1));
+        let ray_dir = ray_dir.normalized();
 
-        let mut ray_pos = self.ray_origin_in.floor();
+        let mut ray_pos = ray_ori.floor();
         let step = Vec3i {
-            x: if ray_dir.x > fixed!(0.0) { 1 } else { -1 },
-            y: if ray_dir.y > fixed!(0.0) { 1 } else { -1 },
-            z: if ray_dir.z > fixed!(0.0) { 1 } else { -1 },
+            x: if ray_dir.x > zero { 1 } else { -1 },
+            y: if ray_dir.y > zero { 1 } else { -1 },
+            z: if ray_dir.z > zero { 1 } else { -1 },
         };
 
         // Movement along each axis per unit t
@@ -83,87 +252,111 @@ impl VoxelTraversalUnit {
             z: ray_dir.z.recip_lte1().abs(),
         };
         let dist = Vec3 {
-            x: if step.x > 0 { fixed!(1.0) - ray_ori.x + ray_pos.x.into() } else { ray_ori.x - ray_pos.x.into() },
-            y: if step.y > 0 { fixed!(1.0) - ray_ori.y + ray_pos.y.into() } else { ray_ori.y - ray_pos.y.into() },
-            z: if step.z > 0 { fixed!(1.0) - ray_ori.z + ray_pos.z.into() } else { ray_ori.z - ray_pos.z.into() },
+            x: if step.x > 0 { one - ray_ori.x + T::from_int(ray_pos.x) } else { ray_ori.x - T::from_int(ray_pos.x) },
+            y: if step.y > 0 { one - ray_ori.y + T::from_int(ray_pos.y) } else { ray_ori.y - T::from_int(ray_pos.y) },
+            z: if step.z > 0 { one - ray_ori.z + T::from_int(ray_pos.z) } else { ray_ori.z - T::from_int(ray_pos.z) },
         };
 
         // Nearest voxel boundary in units of t
         let mut t_max = Vec3 {
-            x: if ray_dir.x != fixed!(0.0) { t_delta.x * dist.x } else { Fixed::MAX },
-            y: if ray_dir.y != fixed!(0.0) { t_delta.y * dist.y } else { Fixed::MAX },
-            z: if ray_dir.z != fixed!(0.0) { t_delta.z * dist.z } else { Fixed::MAX },
+            x: if ray_dir.x != zero { t_delta.x * dist.x } else { T::MAX },
+            y: if ray_dir.y != zero { t_delta.y * dist.y } else { T::MAX },
+            z: if ray_dir.z != zero { t_delta.z * dist.z } else { T::MAX },
         };
 
         let mut num_steps = 0;
+        let mut t = zero;
         let mut last_step = Axis::None;
 
+        // "Nothing hit" is encoded as an air block
+        let mut hit = MockHit {
+            block: Block::Air,
+            normal: Vec3::new(zero, zero, zero),
+            face_uv: [zero; 2],
+            tint: Rgb565::default(),
+            transmittance: one,
+        };
+
         loop {
             // Exit condition -> Out of render distance
-            if num_steps > 220 {
-                // "Nothing hit" is encoded as an air block
-                self.voxel_out = Block::Air;
-                self.valid_out = true;
+            if distance.reached(num_steps, t) {
                 break;
             }
-            // Exit condition -> Out of bounds
-            let Some(block) = cache.query(ray_pos) else {
-                // "Nothing hit" is encoded as an air block
-                self.voxel_out = Block::Air;
-                self.valid_out = true;
+            // Exit condition -> Out of the cache for good
+            if Self::leaving_window(ray_pos, cache.min(), step) {
                 break;
-            };
-            
-            // Exit condition -> Found a block!
-            if block != Block::Air {
-                self.voxel_out = block;
-                self.valid_out = true;
-                self.normal_out = match last_step {
-                    Axis::None => Default::default(),
-                    Axis::X => Fixed::from(-step.x) * Vec3::RIGHT,
-                    Axis::Y => Fixed::from(-step.y) * Vec3::UP,
-                    Axis::Z => Fixed::from(-step.z) * Vec3::FORWARD,
+            }
+            // Outside of the cache but heading into it, there's nothing to hit yet
+            let block = cache.query(ray_pos).unwrap_or(Block::Air);
+
+            // Exit condition -> Found a block! Translucent ones only once they hide what's behind
+            if Self::blend(block, &mut hit.tint, &mut hit.transmittance) {
+                hit.block = block;
+                hit.normal = match last_step {
+                    Axis::None => hit.normal,
+                    Axis::X => Vec3::new(T::from_int(-step.x), zero, zero),
+                    Axis::Y => Vec3::new(zero, T::from_int(-step.y), zero),
+                    Axis::Z => Vec3::new(zero, zero, T::from_int(-step.z)),
                 };
+                hit.face_uv = Self::face_uv(ray_ori, ray_dir, ray_pos, &last_step, t_max, t_delta);
                 break;
             }
 
             // Advance to the next voxel
             if t_max.x < t_max.y {
                 if t_max.x < t_max.z {
+                    t = t_max.x;
                     ray_pos.x += step.x;
-                    t_max.x += t_delta.x;
+                    t_max.x = t_max.x + t_delta.x;
                     last_step = Axis::X;
                 } else {
+                    t = t_max.z;
                     ray_pos.z += step.z;
-                    t_max.z += t_delta.z;
+                    t_max.z = t_max.z + t_delta.z;
                     last_step = Axis::Z;
                 }
             } else {
                 if t_max.y < t_max.z {
+                    t = t_max.y;
                     ray_pos.y += step.y;
-                    t_max.y += t_delta.y;
+                    t_max.y = t_max.y + t_delta.y;
                     last_step = Axis::Y;
                 } else {
+                    t = t_max.z;
                     ray_pos.z += step.z;
-                    t_max.z += t_delta.z;
+                    t_max.z = t_max.z + t_delta.z;
                     last_step = Axis::Z;
                 }
             }
             num_steps += 1;
         }
+
+        hit
     }
 
     pub fn rising_clk_edge(&mut self) {
-        let mut l2 = self.l2.borrow_mut();
-        
+        // Propagate signals to owned submodules
+        if let Some(l1) = &mut self.l1 {
+            l1.reset = self.reset;
+            l1.invalidate_in = self.invalidate_in;
+            l1.l2 = self.l2;
+            l1.rising_clk_edge();
+            self.l2 = l1.l2;
+        }
+
         // Reset
         if self.reset {
             // Outputs
             self.voxel_out = Block::Air;
             self.valid_out = false;
             self.normal_out = Vec3::FORWARD;
+            self.face_uv_out = Default::default();
+            self.shadowed_out = false;
+            self.tint_out = Rgb565::default();
+            self.transmittance_out = fixed!(1.0);
 
             // Internal state
+            self.ray_origin = Vec3::default();
             self.ray_direction = Vec3::FORWARD;
             self.ray_position = Vec3i::default();
             self.ray_step = Vec3i::default();
@@ -171,119 +364,479 @@ impl VoxelTraversalUnit {
             self.ray_dist = Vec3::default();
             self.ray_t_max = Vec3::default();
             self.num_steps = 0;
+            self.ray_t = fixed!(0.0);
             self.last_step = Axis::None;
+            self.shadow_ray = false;
+            self.shadow_cycles = 0;
+            self.counters = Default::default();
 
             // Memory access
-            l2.read_enable_in[self.index] = false;
+            self.read(None);
             return;
         }
 
+        overflow::at_step(if self.ray_init_in { 0 } else { self.num_steps });
+
         // Change inputs -> reinitialize the traversal algorithm
         if self.ray_init_in {
             // TODO: this would probably take more than one cycle...
-            self.ray_direction = self.ray_direction_in.normalized();
-            self.ray_position = self.ray_origin_in.floor();
-
-            // Shorthands, or this gets quite long
-            let o = self.ray_origin_in;
-            let d = self.ray_direction;
-            let p = self.ray_position;
-
-            self.ray_step = Vec3i {
-                x: if d.x > fixed!(0.0) { 1 } else { -1 },
-                y: if d.y > fixed!(0.0) { 1 } else { -1 },
-                z: if d.z > fixed!(0.0) { 1 } else { -1 },
-            };
-
-            // Movement along each axis per unit t
-            // Ray direction is normalized, so every component is <= 1
-            self.ray_t_delta = Vec3 {
-                x: d.x.recip_lte1().abs(),
-                y: d.y.recip_lte1().abs(),
-                z: d.z.recip_lte1().abs(),
-            };
-            self.ray_dist = Vec3 {
-                x: if self.ray_step.x > 0 { fixed!(1.0) - o.x + p.x.into() } else { o.x - p.x.into() },
-                y: if self.ray_step.y > 0 { fixed!(1.0) - o.y + p.y.into() } else { o.y - p.y.into() },
-                z: if self.ray_step.z > 0 { fixed!(1.0) - o.z + p.z.into() } else { o.z - p.z.into() },
-            };
-
-            // Nearest voxel boundary in units of t
-            self.ray_t_max = Vec3 {
-                x: if d.x != fixed!(0.0) { self.ray_t_delta.x * self.ray_dist.x } else { Fixed::MAX },
-                y: if d.y != fixed!(0.0) { self.ray_t_delta.y * self.ray_dist.y } else { Fixed::MAX },
-                z: if d.z != fixed!(0.0) { self.ray_t_delta.z * self.ray_dist.z } else { Fixed::MAX },
-            };
+            self.start_ray(self.ray_origin_in, self.ray_direction_in);
 
             // Other state stuff
-            self.num_steps = 0;
             self.valid_out = false;
+            self.shadow_ray = false;
+            self.tint_out = Rgb565::default();
+            self.transmittance_out = fixed!(1.0);
+            self.counters.rays += 1;
 
-            l2.read_enable_in[self.index] = true;
-            l2.addr_in[self.index] = self.ray_position;
+            self.read(Some(self.ray_position));
 
             return;
         }
 
         // We're done
         if self.valid_out {
+            self.counters.idle_cycles += 1;
             return;
         }
+
+        // Shadow ray ran out of time, assume that nothing's in the way
+        if self.shadow_ray {
+            self.shadow_cycles += 1;
+
+            if self.shadow_cycles > self.shadow_budget {
+                self.counters.shadow_timeouts += 1;
+                self.finish_shadow(false);
+                return;
+            }
+        }
         
-        // Out of render distance
-        if self.num_steps > 110 {
+        // Out of render distance, or out of the window for good. Either way, nothing's hit
+        let out_of_distance = self.render_distance.reached(self.num_steps, self.ray_t);
+        if out_of_distance || Self::leaving_window(self.ray_position, self.window_min_in, self.ray_step) {
+            if self.shadow_ray {
+                self.finish_shadow(false);
+                return;
+            }
+
+            match out_of_distance {
+                true => self.counters.distance_exits += 1,
+                false => self.counters.bounds_exits += 1,
+            }
             self.voxel_out = Block::Air;
             self.valid_out = true;
 
-            l2.read_enable_in[self.index] = false;
+            self.read(None);
             return;
         }
-        
+
+
         // Stall until we can read the block
-        if !l2.valid_out[self.index] {
+        let Some(voxel) = self.read_result() else {
+            self.counters.stall_cycles += 1;
+            return;
+        };
+
+        // Shadow ray hit a block, the sun is hidden. Only opaque blocks cast shadows
+        if self.shadow_ray && voxel.material() == Material::Opaque {
+            self.finish_shadow(true);
             return;
         }
 
         // Hit a block!
-        if l2.voxel_out[self.index] != Block::Air {
-            self.voxel_out = l2.voxel_out[self.index];
-            self.valid_out = true;
+        if !self.shadow_ray && Self::blend(voxel, &mut self.tint_out, &mut self.transmittance_out) {
+            self.counters.hits += 1;
+            self.voxel_out = voxel;
+            self.shadowed_out = false;
             self.normal_out = match self.last_step {
                 Axis::None => Default::default(),
                 Axis::X => Fixed::from(-self.ray_step.x) * Vec3::RIGHT,
                 Axis::Y => Fixed::from(-self.ray_step.y) * Vec3::UP,
                 Axis::Z => Fixed::from(-self.ray_step.z) * Vec3::FORWARD,
             };
-            
-            l2.read_enable_in[self.index] = false;
+            self.face_uv_out = Self::face_uv(
+                self.ray_origin,
+                self.ray_direction,
+                self.ray_position,
+                &self.last_step,
+                self.ray_t_max,
+                self.ray_t_delta,
+            );
+
+            // Faces that look away from the sun are in their own shadow, no need to check
+            let hit = Self::entry_point(self.ray_origin, self.ray_direction, &self.last_step, self.ray_t_max, self.ray_t_delta);
+            match hit {
+                Some(hit) if self.shadow_budget > 0 && self.normal_out.dot(self.sun_in) > fixed!(0.0) => {
+                    // Start slightly off the face, so that the ray starts in front of it
+                    self.start_ray(hit + self.normal_out * fixed!(1.0 / 16.0), self.sun_in);
+                    self.shadow_ray = true;
+                    self.shadow_cycles = 0;
+                    self.counters.shadow_rays += 1;
+
+                    self.read(Some(self.ray_position));
+                },
+                _ => {
+                    self.valid_out = true;
+                    self.read(None);
+                },
+            }
             return;
         }
 
         // Advance to the next voxel
         if self.ray_t_max.x < self.ray_t_max.y {
             if self.ray_t_max.x < self.ray_t_max.z {
+                self.ray_t = self.ray_t_max.x;
                 self.ray_position.x += self.ray_step.x;
                 self.ray_t_max.x += self.ray_t_delta.x;
                 self.last_step = Axis::X;
             } else {
+                self.ray_t = self.ray_t_max.z;
                 self.ray_position.z += self.ray_step.z;
                 self.ray_t_max.z += self.ray_t_delta.z;
                 self.last_step = Axis::Z;
             }
         } else {
             if self.ray_t_max.y < self.ray_t_max.z {
+                self.ray_t = self.ray_t_max.y;
                 self.ray_position.y += self.ray_step.y;
                 self.ray_t_max.y += self.ray_t_delta.y;
                 self.last_step = Axis::Y;
             } else {
+                self.ray_t = self.ray_t_max.z;
                 self.ray_position.z += self.ray_step.z;
                 self.ray_t_max.z += self.ray_t_delta.z;
                 self.last_step = Axis::Z;
             }
         }
         self.num_steps += 1;
+        self.counters.steps += 1;
+
+        self.read(Some(self.ray_position));
+    }
+
+    /// Blends `voxel` into `tint` (front to back), where `transmittance` of what's behind
+    /// still shows, returning whether the ray stops there, i.e. it's opaque or nothing
+    /// behind it would show anyway. Those are [VoxelTraversalUnit::tint_out] and
+    /// [VoxelTraversalUnit::transmittance_out] in the design.
+    ///
+    /// In hardware, this is a 9x16 bit multiplier for the transmittance, and one 8x9 bit
+    /// multiplier and a saturating adder per colour channel. Both truncate. The colours are
+    /// always in [Fixed], whatever `T` is.
+    fn blend<T: Scalar>(voxel: Block, tint: &mut Rgb565, transmittance: &mut T) -> bool {
+        match voxel.material() {
+            Material::Opaque => true,
+            Material::Transparent => false,
+            Material::Translucent { alpha } => {
+                let alpha = T::from_f32(alpha.to_f32());
+
+                *tint += voxel.color() * fixed!((*transmittance * alpha).to_f32());
+                *transmittance = *transmittance * (T::from_int(1) - alpha);
 
-        l2.read_enable_in[self.index] = true;
-        l2.addr_in[self.index] = self.ray_position;
+                *transmittance < T::from_f32(1.0 / 16.0)
+            },
+        }
     }
-}
\ No newline at end of file
+
+    /// Whether the voxel at `position` is outside of the window whose corner with the
+    /// smallest coordinates is `window_min`, and a ray stepping along `step` is moving away
+    /// from it, so that it'll never come back in. Rays that start outside of the window and
+    /// head towards it carry on.
+    ///
+    /// In hardware, this is two comparators per axis, on the same cycle as the render
+    /// distance's.
+    fn leaving_window(position: Vec3i, window_min: Vec3i, step: Vec3i) -> bool {
+        let leaving = |position: i16, min: i16, step: i16| {
+            let offset = i32::from(position) - i32::from(min);
+
+            (offset < 0 && step < 0) || (offset >= Window::SIZE as i32 && step > 0)
+        };
+        let (p, min) = (position, window_min);
+
+        leaving(p.x, min.x, step.x) || leaving(p.y, min.y, step.y) || leaving(p.z, min.z, step.z)
+    }
+
+    /// Ends the shadow ray, and with it the pixel
+    fn finish_shadow(&mut self, shadowed: bool) {
+        self.shadowed_out = shadowed;
+        self.shadow_ray = false;
+        self.valid_out = true;
+
+        self.read(None);
+    }
+
+    /// Starts traversing a ray from `origin` along `direction`, which doesn't need to be
+    /// normalized
+    fn start_ray(&mut self, origin: Vec3, direction: Vec3) {
+        self.ray_origin = origin;
+        self.ray_direction = direction.normalized();
+        self.ray_position = origin.floor();
+
+        // Shorthands, or this gets quite long
+        let o = origin;
+        let d = self.ray_direction;
+        let p = self.ray_position;
+
+        self.ray_step = Vec3i {
+            x: if d.x > fixed!(0.0) { 1 } else { -1 },
+            y: if d.y > fixed!(0.0) { 1 } else { -1 },
+            z: if d.z > fixed!(0.0) { 1 } else { -1 },
+        };
+
+        // Movement along each axis per unit t
+        // Ray direction is normalized, so every component is <= 1
+        self.ray_t_delta = Vec3 {
+            x: d.x.recip_lte1().abs(),
+            y: d.y.recip_lte1().abs(),
+            z: d.z.recip_lte1().abs(),
+        };
+        self.ray_dist = Vec3 {
+            x: if self.ray_step.x > 0 { fixed!(1.0) - o.x + p.x.into() } else { o.x - p.x.into() },
+            y: if self.ray_step.y > 0 { fixed!(1.0) - o.y + p.y.into() } else { o.y - p.y.into() },
+            z: if self.ray_step.z > 0 { fixed!(1.0) - o.z + p.z.into() } else { o.z - p.z.into() },
+        };
+
+        // Nearest voxel boundary in units of t
+        self.ray_t_max = Vec3 {
+            x: if d.x != fixed!(0.0) { self.ray_t_delta.x * self.ray_dist.x } else { Fixed::MAX },
+            y: if d.y != fixed!(0.0) { self.ray_t_delta.y * self.ray_dist.y } else { Fixed::MAX },
+            z: if d.z != fixed!(0.0) { self.ray_t_delta.z * self.ray_dist.z } else { Fixed::MAX },
+        };
+
+        self.num_steps = 0;
+        self.ray_t = fixed!(0.0);
+    }
+
+    /// Where a ray entered its current voxel, through the face it crossed with its
+    /// `last_step`. `t_max` is the next boundary along each axis, so the face it crossed is
+    /// one `t_delta` back. [None] if the ray hasn't left its first voxel.
+    ///
+    /// In hardware, this is one multiply-add per axis on the cycle of the hit.
+    fn entry_point<T: Scalar>(origin: Vec3<T>, direction: Vec3<T>, last_step: &Axis, t_max: Vec3<T>, t_delta: Vec3<T>) -> Option<Vec3<T>> {
+        let t = match last_step {
+            Axis::None => return None,
+            Axis::X => t_max.x - t_delta.x,
+            Axis::Y => t_max.y - t_delta.y,
+            Axis::Z => t_max.z - t_delta.z,
+        };
+
+        Some(origin + direction * t)
+    }
+
+    /// Where a ray entered the voxel at `position`, on the face it crossed, see
+    /// [VoxelTraversalUnit::face_uv_out] and [VoxelTraversalUnit::entry_point]
+    fn face_uv<T: Scalar>(origin: Vec3<T>, direction: Vec3<T>, position: Vec3i, last_step: &Axis, t_max: Vec3<T>, t_delta: Vec3<T>) -> [T; 2] {
+        let (zero, max) = (T::from_int(0), T::from_f32(255.0 / 256.0));
+        let Some(hit) = Self::entry_point(origin, direction, last_step, t_max, t_delta) else {
+            return [zero; 2];
+        };
+
+        // Fractional part within the voxel, which rounding can push slightly outside of it
+        let frac = |x: T, p: i16| {
+            let frac = x - T::from_int(p);
+            if frac < zero { zero } else if frac > max { max } else { frac }
+        };
+        let x = frac(hit.x, position.x);
+        let y = frac(hit.y, position.y);
+        let z = frac(hit.z, position.z);
+
+        match last_step {
+            Axis::X => [z, max - y],
+            Axis::Z => [x, max - y],
+            _ => [x, z],
+        }
+    }
+
+    /// Starts reading the voxel at `addr` (or stops reading, if [None]), through the L1
+    /// cache if there is one
+    fn read(&mut self, addr: Option<Vec3i>) {
+        match &mut self.l1 {
+            Some(l1) => {
+                l1.read_enable_in = addr.is_some();
+                l1.addr_in = addr.unwrap_or(l1.addr_in);
+            },
+            None => {
+                self.l2.read_enable_out = addr.is_some();
+                self.l2.addr_out = addr.unwrap_or(self.l2.addr_out);
+            },
+        }
+    }
+
+    /// Voxel read by the last [VoxelTraversalUnit::read], or [None] if it isn't available (yet)
+    fn read_result(&self) -> Option<Block> {
+        match &self.l1 {
+            Some(l1) => l1.valid_out.then_some(l1.voxel_out),
+            None => self.l2.valid_in.then_some(self.l2.voxel_in),
+        }
+    }
+}
+
+impl Trace for VoxelTraversalUnit {
+    fn trace(&self, t: &mut Tracer) {
+        t.scope(format!("vtu{}", self.index), |t| {
+            t.bit("reset", self.reset);
+            t.vec3("ray_origin_in", self.ray_origin_in);
+            t.vec3("ray_direction_in", self.ray_direction_in);
+            t.bit("ray_init_in", self.ray_init_in);
+            t.vec3("sun_in", self.sun_in);
+            t.vec3i("window_min_in", self.window_min_in);
+            t.block("voxel_out", self.voxel_out);
+            t.vec3("normal_out", self.normal_out);
+            t.fixed("face_u_out", self.face_uv_out[0]);
+            t.fixed("face_v_out", self.face_uv_out[1]);
+            t.bit("shadowed_out", self.shadowed_out);
+            t.bits("tint_out", 16, self.tint_out.to_raw().into());
+            t.fixed("transmittance_out", self.transmittance_out);
+            t.bit("valid_out", self.valid_out);
+
+            if let Some(l1) = &self.l1 {
+                l1.trace(t);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Casts a ray from the middle of the origin's voxel towards +X through `blocks` (air
+    /// anywhere else), answering every read on the next cycle, and returns the VTU once
+    /// it's done
+    fn cast(blocks: &[(i16, Block)]) -> VoxelTraversalUnit {
+        cast_from(RenderDistance::default(), fixed!(0.5), Vec3::RIGHT, blocks)
+    }
+
+    /// Same as [cast], from `x` along `direction` and giving up at `distance`
+    fn cast_from(distance: RenderDistance, x: Fixed, direction: Vec3, blocks: &[(i16, Block)]) -> VoxelTraversalUnit {
+        let mut vtu = VoxelTraversalUnit {
+            window_min_in: Window::default().min(),
+            reset: true,
+            ..Default::default()
+        }
+        .with_render_distance(distance);
+        vtu.rising_clk_edge();
+        vtu.reset = false;
+
+        vtu.ray_origin_in = Vec3::new(x, fixed!(0.5), fixed!(0.5));
+        vtu.ray_direction_in = direction;
+        vtu.ray_init_in = true;
+        vtu.rising_clk_edge();
+        vtu.ray_init_in = false;
+
+        for _ in 0..1000 {
+            let addr = vtu.l2.addr_out;
+            vtu.l2.valid_in = vtu.l2.read_enable_out;
+            vtu.l2.voxel_in = match (addr.y, addr.z) {
+                (0, 0) => blocks.iter().find(|&&(x, _)| x == addr.x).map_or(Block::Air, |&(_, block)| block),
+                _ => Block::Air,
+            };
+
+            vtu.rising_clk_edge();
+            if vtu.valid_out {
+                return vtu;
+            }
+        }
+        panic!("ray never finished");
+    }
+
+    /// What [VoxelTraversalUnit::blend] should make of going through `blocks`, in order
+    fn expected(blocks: &[Block]) -> (Rgb565, Fixed) {
+        let mut tint = Rgb565::default();
+        let mut transmittance = fixed!(1.0);
+        for block in blocks {
+            let Material::Translucent { alpha } = block.material() else { unreachable!() };
+            tint += block.color() * (transmittance * alpha);
+            transmittance *= fixed!(1.0) - alpha;
+        }
+        (tint, transmittance)
+    }
+
+    #[test]
+    fn blends_translucent_blocks_in_front_of_the_hit() {
+        let vtu = cast(&[(2, Block::Glass), (3, Block::Water), (6, Block::Stone)]);
+
+        assert_eq!(vtu.voxel_out, Block::Stone);
+        assert_eq!(vtu.traversal().position, Vec3i::new(6, 0, 0));
+        assert_eq!((vtu.tint_out, vtu.transmittance_out), expected(&[Block::Glass, Block::Water]));
+
+        // 1 - 32/256, then times 1 - 96/256, both exact
+        assert_eq!(vtu.transmittance_out, fixed!(0.546875));
+        assert_ne!(vtu.tint_out, Rgb565::default());
+    }
+
+    #[test]
+    fn only_air_leaves_the_hit_alone() {
+        let vtu = cast(&[(4, Block::Stone)]);
+
+        assert_eq!(vtu.voxel_out, Block::Stone);
+        assert_eq!((vtu.tint_out, vtu.transmittance_out), (Rgb565::default(), fixed!(1.0)));
+    }
+
+    #[test]
+    fn stops_once_nothing_shows_through() {
+        let water = (1..=10).map(|x| (x, Block::Water)).chain([(12, Block::Stone)]).collect::<Vec<_>>();
+        let vtu = cast(&water);
+
+        // 160/256 per voxel of water (truncated): 160, 100, 62, 38, 23, then 14 < 16 at the
+        // 6th one, which is what's hit
+        assert_eq!(vtu.voxel_out, Block::Water);
+        assert_eq!(vtu.traversal().position, Vec3i::new(6, 0, 0));
+        assert_eq!(vtu.normal_out, -Vec3::RIGHT);
+        assert_eq!((vtu.tint_out, vtu.transmittance_out), expected(&[Block::Water; 6]));
+        assert!(vtu.transmittance_out < fixed!(1.0 / 16.0));
+        assert!(expected(&[Block::Water; 5]).1 >= fixed!(1.0 / 16.0));
+    }
+
+    #[test]
+    fn gives_up_after_the_steps() {
+        let distance = RenderDistance { steps: 10, t: None };
+        let vtu = cast_from(distance, fixed!(0.5), Vec3::RIGHT, &[(12, Block::Stone)]);
+
+        assert_eq!(vtu.voxel_out, Block::Air);
+        assert_eq!(vtu.traversal().steps, 11);
+        assert_eq!((vtu.counters.distance_exits, vtu.counters.bounds_exits, vtu.counters.hits), (1, 0, 0));
+    }
+
+    #[test]
+    fn gives_up_after_the_distance() {
+        let distance = RenderDistance { t: Some(fixed!(5.0)), ..Default::default() };
+
+        // The voxel at x = 5 is entered at t = 4.5, the next one at t = 5.5
+        let vtu = cast_from(distance, fixed!(0.5), Vec3::RIGHT, &[(5, Block::Stone)]);
+        assert_eq!(vtu.voxel_out, Block::Stone);
+
+        let vtu = cast_from(distance, fixed!(0.5), Vec3::RIGHT, &[(6, Block::Stone)]);
+        assert_eq!(vtu.voxel_out, Block::Air);
+        assert_eq!(vtu.traversal().position, Vec3i::new(6, 0, 0));
+        assert_eq!((vtu.counters.distance_exits, vtu.counters.bounds_exits), (1, 0));
+    }
+
+    #[test]
+    fn leaves_the_window() {
+        let max = Window::default().min().x + Window::SIZE as i16 - 1;
+
+        // Past the window's last voxel, well within the render distance
+        let vtu = cast(&[(max + 1, Block::Stone)]);
+        assert_eq!(vtu.voxel_out, Block::Air);
+        assert_eq!(vtu.traversal().position, Vec3i::new(max + 1, 0, 0));
+        assert_eq!((vtu.counters.distance_exits, vtu.counters.bounds_exits), (0, 1));
+    }
+
+    #[test]
+    fn comes_into_the_window() {
+        let min = Window::default().min().x;
+        let origin = Fixed::from(min - 4) + fixed!(0.5);
+
+        // Heading away from it, it's over straight away...
+        let vtu = cast_from(RenderDistance::default(), origin, -Vec3::RIGHT, &[]);
+        assert_eq!((vtu.voxel_out, vtu.traversal().steps), (Block::Air, 0));
+        assert_eq!(vtu.counters.bounds_exits, 1);
+
+        // ...but heading towards it, it goes through the window
+        let vtu = cast_from(RenderDistance::default(), origin, Vec3::RIGHT, &[(min + 2, Block::Stone)]);
+        assert_eq!(vtu.voxel_out, Block::Stone);
+        assert_eq!(vtu.traversal().position, Vec3i::new(min + 2, 0, 0));
+        assert_eq!((vtu.counters.hits, vtu.counters.bounds_exits), (1, 0));
+    }
+}
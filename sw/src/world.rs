@@ -0,0 +1,191 @@
+//! The world the FPGA only sees a window of, as the host has it. [crate::stream::Streamer]
+//! sends it to the L3 cache slice by slice as the camera moves.
+
+use std::{collections::HashMap, fs, path::{Path, PathBuf}};
+
+use crate::{anvil, block::Block};
+
+/// Blocks of the world, in tiles of [World::TILE]³ that are loaded as needed. Anything that
+/// isn't loaded is air.
+#[derive(Debug, Default, Clone)]
+pub struct World {
+    /// Tiles by the world coordinates of their corner with the smallest coordinates,
+    /// divided by [World::TILE]. Blocks are in `chunk.bin` order, i.e. X, then Y, then Z
+    tiles: HashMap<[i32; 3], Box<[Block]>>,
+}
+
+impl World {
+    /// Size of a tile in one dimension
+    pub const TILE: usize = 64;
+
+    /// Reads a world dump, or a directory of them.
+    ///
+    /// A dump is a cube of blocks in `chunk.bin` format (one byte per block id, X, then Y,
+    /// then Z). A single file is centred on the origin, like `chunk.bin`. In a directory,
+    /// every `X_Y_Z.bin` file has its corner with the smallest coordinates at `(X, Y, Z)`,
+    /// and other files are ignored.
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let mut world = Self::default();
+
+        if !path.is_dir() {
+            let (size, blocks) = Self::read_dump(path)?;
+            world.insert([-((size / 2) as i32); 3], size, &blocks);
+            return Ok(world);
+        }
+
+        let entries = fs::read_dir(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+        for entry in entries {
+            let path = entry.map_err(|e| format!("couldn't read {}: {e}", path.display()))?.path();
+
+            let Some(corner) = path
+                .file_name()
+                .and_then(|name| name.to_str()?.strip_suffix(".bin"))
+                .and_then(|name| {
+                    let coords = name
+                        .split('_')
+                        .map(|n| n.parse().ok())
+                        .collect::<Option<Vec<i32>>>()?;
+                    coords.try_into().ok()
+                })
+            else {
+                continue;
+            };
+
+            let (size, blocks) = Self::read_dump(&path)?;
+            world.insert(corner, size, &blocks);
+        }
+
+        Ok(world)
+    }
+
+    /// Reads the `size`³ box around `center` of the Anvil world in `dir` (see [anvil]),
+    /// centred on the origin like `chunk.bin`. Warns about blocks that aren't in the palette
+    pub fn load_anvil(dir: &Path, center: [i32; 3], size: usize) -> Result<Self, String> {
+        let half = (size / 2) as i32;
+        let corner = center.map(|x| x - half);
+        let extract = anvil::read_box(dir, corner, size)?;
+
+        if extract.missing_chunks > 0 {
+            eprintln!("warning: {} chunk columns around {center:?} were never generated, they're air", extract.missing_chunks);
+        }
+        for (name, count) in &extract.unmapped {
+            eprintln!("warning: {count} {name} aren't in palette/blocks.csv, they're air");
+        }
+
+        let mut world = Self::default();
+        world.insert([-half; 3], size, &extract.blocks);
+        Ok(world)
+    }
+
+    /// Block at `pos`, in world coordinates
+    pub fn get(&self, pos: [i32; 3]) -> Block {
+        let tile = pos.map(|x| x.div_euclid(Self::TILE as i32));
+        let [x, y, z] = pos.map(|x| x.rem_euclid(Self::TILE as i32) as usize);
+
+        self.tiles
+            .get(&tile)
+            .map_or(Block::Air, |blocks| blocks[Self::TILE * (Self::TILE * z + y) + x])
+    }
+
+    /// Copies a cube of `size`³ `blocks` (in `chunk.bin` order) with its corner with the
+    /// smallest coordinates at `corner`, over whatever was there
+    pub fn insert(&mut self, corner: [i32; 3], size: usize, blocks: &[Block]) {
+        assert_eq!(blocks.len(), size.pow(3), "blocks don't make a cube of {size}");
+
+        for (i, [x, y, z]) in Self::cube(size).enumerate() {
+            let pos = [corner[0] + x as i32, corner[1] + y as i32, corner[2] + z as i32];
+            let tile = pos.map(|x| x.div_euclid(Self::TILE as i32));
+            let [x, y, z] = pos.map(|x| x.rem_euclid(Self::TILE as i32) as usize);
+
+            let tile = self.tiles
+                .entry(tile)
+                .or_insert_with(|| vec![Block::Air; Self::TILE.pow(3)].into_boxed_slice());
+            tile[Self::TILE * (Self::TILE * z + y) + x] = blocks[i];
+        }
+    }
+
+    /// Every `[x, y, z]` of a cube of `size`³, in `chunk.bin` order
+    fn cube(size: usize) -> impl Iterator<Item = [usize; 3]> {
+        (0..size.pow(3)).map(move |i| [i % size, (i / size) % size, i / (size * size)])
+    }
+
+    /// Size and blocks of the dump at `path`
+    fn read_dump(path: &Path) -> Result<(usize, Vec<Block>), String> {
+        let bytes = fs::read(path).map_err(|e| format!("couldn't read {}: {e}", path.display()))?;
+
+        let size = (bytes.len() as f64).cbrt().round() as usize;
+        if size.pow(3) != bytes.len() || size == 0 {
+            return Err(format!("{} has {} blocks, which isn't a cube", path.display(), bytes.len()));
+        }
+
+        let blocks = bytes
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| Block::try_from(id).map_err(|_| format!("{}: unknown block id {id} at byte {i}", path.display())))
+            .collect::<Result<_, _>>()?;
+
+        Ok((size, blocks))
+    }
+}
+
+/// Where the [World] comes from, from the command line of the viewer or of `fpgacraft render`
+#[derive(Debug, Clone)]
+pub struct WorldOptions {
+    /// `--world`: a dump or a directory of them
+    pub dump: Option<PathBuf>,
+    /// `--anvil`: an Anvil world
+    pub anvil: Option<PathBuf>,
+    /// `--anvil-center`: block of the Anvil world that ends up at the origin
+    pub anvil_center: [i32; 3],
+    /// `--anvil-size`: size of the box read out of the Anvil world
+    pub anvil_size: usize,
+}
+
+impl Default for WorldOptions {
+    fn default() -> Self {
+        Self {
+            dump: None,
+            anvil: None,
+            anvil_center: [0, 64, 0],
+            anvil_size: 64,
+        }
+    }
+}
+
+impl WorldOptions {
+    /// Takes `arg` if it's one of the options above, with `value` for its value. Returns
+    /// whether it was.
+    pub fn parse_arg<'a>(&mut self, arg: &str, value: impl FnOnce() -> Result<&'a String, String>) -> Result<bool, String> {
+        match arg {
+            "--world" => self.dump = Some(value()?.into()),
+            "--anvil" => self.anvil = Some(value()?.into()),
+            "--anvil-center" => {
+                let s = value()?;
+                self.anvil_center = s
+                    .split(|c: char| c.is_whitespace() || c == ',')
+                    .filter(|n| !n.is_empty())
+                    .map(|n| n.parse().map_err(|e| format!("invalid coordinate {n:?} in {s:?}: {e}")))
+                    .collect::<Result<Vec<_>, _>>()?
+                    .try_into()
+                    .map_err(|_| format!("Anvil center {s:?} should be \"x y z\""))?;
+            },
+            "--anvil-size" => self.anvil_size = value()?
+                .parse()
+                .ok()
+                .filter(|&size| size > 0)
+                .ok_or("Anvil box size must be a positive integer")?,
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// The world, or [None] if neither `--world` nor `--anvil` was given
+    pub fn load(&self) -> Result<Option<World>, String> {
+        match (&self.dump, &self.anvil) {
+            (Some(_), Some(_)) => Err("--world and --anvil can't be used together".into()),
+            (Some(path), None) => World::load(path).map(Some),
+            (None, Some(dir)) => World::load_anvil(dir, self.anvil_center, self.anvil_size).map(Some),
+            (None, None) => Ok(None),
+        }
+    }
+}